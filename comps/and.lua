@@ -1,6 +1,11 @@
 NUM_OF_INS = 2
 NUM_OF_OUTS = 1
 MEMORY_SIZE = 0
+DELAY = 1
+
+DESCRIPTION = "1 when every input is 1"
+CATEGORY = "Logic"
+ICON = "&"
 
 WIDTH = 3
 HEIGHT = 2
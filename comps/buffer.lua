@@ -2,6 +2,10 @@ NUM_OF_INS = 1
 NUM_OF_OUTS = 1
 MEMORY_SIZE = 1
 
+DESCRIPTION = "Passes its input through"
+CATEGORY = "Logic"
+ICON = ">"
+
 WIDTH = 2
 HEIGHT = 2
 
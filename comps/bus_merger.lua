@@ -0,0 +1,27 @@
+NUM_OF_INS = 8
+NUM_OF_OUTS = 1
+MEMORY_SIZE = 0
+
+DESCRIPTION = "Merges 8 single bits into an 8 bit bus"
+CATEGORY = "Wiring"
+ICON = ">>"
+
+-- Eight single bits in, one 8 bit bus out
+INPUT_WIDTHS = {1, 1, 1, 1, 1, 1, 1, 1}
+OUTPUT_WIDTHS = {8}
+
+WIDTH = 2
+HEIGHT = 9
+
+INPUT_POSITIONS = {21, 20, 19, 18, 17, 16, 15, 14}
+OUTPUT_POSITIONS = {6}
+
+-- inputs holds the bits of every pin back to back, so the bits just pass through
+function Calculate(inputs)
+    return inputs
+end
+
+-- Purple
+function Draw(buffer)
+    buffer:set_all(160, 0, 255, 255)
+end
@@ -0,0 +1,27 @@
+NUM_OF_INS = 1
+NUM_OF_OUTS = 8
+MEMORY_SIZE = 0
+
+DESCRIPTION = "Splits an 8 bit bus into single bits"
+CATEGORY = "Wiring"
+ICON = "<<"
+
+-- One 8 bit bus in, eight single bits out
+INPUT_WIDTHS = {8}
+OUTPUT_WIDTHS = {1, 1, 1, 1, 1, 1, 1, 1}
+
+WIDTH = 2
+HEIGHT = 9
+
+INPUT_POSITIONS = {17}
+OUTPUT_POSITIONS = {3, 4, 5, 6, 7, 8, 9, 10}
+
+-- inputs holds the bits of every pin back to back, so the bits just pass through
+function Calculate(inputs)
+    return inputs
+end
+
+-- Purple
+function Draw(buffer)
+    buffer:set_all(160, 0, 255, 255)
+end
@@ -2,6 +2,10 @@ NUM_OF_INS = 0
 NUM_OF_OUTS = 1
 MEMORY_SIZE = 1
 
+DESCRIPTION = "Click to switch between 0 and 1, becomes an input pin of a component"
+CATEGORY = "Input/Output"
+ICON = "o"
+
 WIDTH = 2
 HEIGHT = 2
 
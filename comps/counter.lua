@@ -0,0 +1,36 @@
+NUM_OF_INS = 1
+NUM_OF_OUTS = 1
+MEMORY_SIZE = 4
+
+DESCRIPTION = "4 bit counter, counts rising clock edges"
+CATEGORY = "Memory"
+ICON = "+1"
+
+-- Counts rising clock edges on a 4 bit bus, least significant bit first
+OUTPUT_WIDTHS = {4}
+CLOCK_INPUT = 1
+
+WIDTH = 2
+HEIGHT = 2
+
+INPUT_POSITIONS = {7}
+OUTPUT_POSITIONS = {3}
+
+function Calculate(inputs)
+    return memory
+end
+
+function OnClock(inputs, edge)
+    if edge == "rising" then
+        local carry = true
+        for i = 1, 4 do
+            memory[i], carry = memory[i] ~= carry, memory[i] and carry
+        end
+    end
+    return memory
+end
+
+-- Dark blue
+function Draw(buffer)
+    buffer:set_all(0, 0, 160, 255)
+end
@@ -0,0 +1,29 @@
+NUM_OF_INS = 2
+NUM_OF_OUTS = 1
+MEMORY_SIZE = 0
+
+DESCRIPTION = "D flip-flop, Q takes D on the rising clock edge"
+CATEGORY = "Memory"
+ICON = "D"
+
+WIDTH = 3
+HEIGHT = 2
+
+-- Data and clock on the left
+INPUT_POSITIONS = {8, 10}
+OUTPUT_POSITIONS = {4}
+
+-- inputs[2] is the clock, OnClock runs on its edges instead of Calculate
+CLOCK_INPUT = 2
+
+-- Q takes D on the rising edge, returning nothing keeps Q
+function OnClock(inputs, edge)
+    if edge == "rising" then
+        return {inputs[1]}
+    end
+end
+
+-- Orange
+function Draw(buffer)
+    buffer:set_all(255, 160, 0, 255)
+end
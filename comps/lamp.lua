@@ -2,6 +2,10 @@ NUM_OF_INS = 1
 NUM_OF_OUTS = 0
 MEMORY_SIZE = 1
 
+DESCRIPTION = "Lights up on 1, becomes an output pin of a component"
+CATEGORY = "Input/Output"
+ICON = "*"
+
 WIDTH = 2
 HEIGHT = 2
 
@@ -1,6 +1,11 @@
 NUM_OF_INS = 1
 NUM_OF_OUTS = 1
 MEMORY_SIZE = 0
+DELAY = 1
+
+DESCRIPTION = "Inverts its input"
+CATEGORY = "Logic"
+ICON = "!"
 
 WIDTH = 1
 HEIGHT = 3
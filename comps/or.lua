@@ -1,6 +1,11 @@
 NUM_OF_INS = 2
 NUM_OF_OUTS = 1
 MEMORY_SIZE = 0
+DELAY = 1
+
+DESCRIPTION = "1 when any input is 1"
+CATEGORY = "Logic"
+ICON = ">=1"
 
 HEIGHT = 2
 WIDTH = 3
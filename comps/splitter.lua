@@ -2,6 +2,10 @@ NUM_OF_INS = 1
 NUM_OF_OUTS = 0
 MEMORY_SIZE = 0
 
+DESCRIPTION = "Connects one signal to several inputs"
+CATEGORY = "Wiring"
+ICON = "<"
+
 HEIGHT = 0
 WIDTH = 0
 
@@ -0,0 +1,27 @@
+NUM_OF_INS = 2
+NUM_OF_OUTS = 1
+MEMORY_SIZE = 0
+
+DESCRIPTION = "Drives the data input while enabled, Z otherwise"
+CATEGORY = "Logic"
+ICON = "Z"
+
+WIDTH = 2
+HEIGHT = 2
+
+-- Data on the left, enable on top
+INPUT_POSITIONS = {7, 1}
+OUTPUT_POSITIONS = {3}
+
+-- Outputs may be booleans or one of "0", "1", "X" and "Z"
+function Calculate(inputs)
+    if inputs[2] then
+        return {inputs[1]}
+    end
+    return {"Z"}
+end
+
+-- Grey
+function Draw(buffer)
+    buffer:set_all(128, 128, 128, 255)
+end
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::LogicGate;
+
+// Owns the gates of a circuit, everything else refers to them by id. Every gate
+// sits in a slot that doesn't change while the gate is there, so schedules can
+// keep slot numbers instead of looking ids up. Slots of removed gates are reused.
+pub struct GateArena {
+    slots: Vec<Option<(Uuid, Box<dyn LogicGate>)>>,
+    free: Vec<usize>,
+    index: HashMap<Uuid, usize>,
+}
+
+impl GateArena {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    // Adds a gate and returns its slot, a gate that had the id before is replaced
+    pub fn insert(&mut self, id: Uuid, gate: Box<dyn LogicGate>) -> usize {
+        if let Some(&slot) = self.index.get(&id) {
+            self.slots[slot] = Some((id, gate));
+            return slot;
+        }
+
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some((id, gate));
+                slot
+            },
+            None => {
+                self.slots.push(Some((id, gate)));
+                self.slots.len() - 1
+            },
+        };
+        self.index.insert(id, slot);
+        slot
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Box<dyn LogicGate>> {
+        let slot = self.index.remove(id)?;
+        self.free.push(slot);
+        self.slots[slot].take().map(|(_, gate)| gate)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.index.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&dyn LogicGate> {
+        self.index.get(id).map(|&slot| self.slot(slot))
+    }
+
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut dyn LogicGate> {
+        let slot = *self.index.get(id)?;
+        Some(self.slot_mut(slot))
+    }
+
+    pub fn slot_of(&self, id: &Uuid) -> Option<usize> {
+        self.index.get(id).copied()
+    }
+
+    // Panics if the slot is empty
+    pub fn slot(&self, slot: usize) -> &dyn LogicGate {
+        self.slots[slot].as_ref().expect("empty gate slot").1.as_ref()
+    }
+
+    pub fn slot_mut(&mut self, slot: usize) -> &mut dyn LogicGate {
+        self.slots[slot].as_mut().expect("empty gate slot").1.as_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // Every gate in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, &dyn LogicGate)> {
+        self.slots.iter().flatten().map(|(id, gate)| (*id, gate.as_ref()))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Uuid, &mut dyn LogicGate)> {
+        self.slots.iter_mut().flatten().map(|(id, gate)| (*id, gate.as_mut() as &mut dyn LogicGate))
+    }
+}
+
+impl Default for GateArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
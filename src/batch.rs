@@ -0,0 +1,504 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+
+use uuid::Uuid;
+
+use crate::minimize::{minimize_table, SumOfProducts};
+use crate::{pin_offset, Circuit, Connection, GateError, GateInfo, Logic, LogicGate, TruthTable};
+
+// Input vectors evaluated in one pass, one per bit of a signal
+pub const BATCH_SIZE: usize = 64;
+
+// Gates with more inputs aren't turned into logic, they are evaluated one vector at a time
+pub const MAX_TABLE_INPUTS: usize = 12;
+
+// Signals that are always 0 and always 1, the circuit input bits follow them
+pub const ZERO_SIGNAL: usize = 0;
+pub const ONE_SIGNAL: usize = 1;
+pub const FIRST_INPUT_SIGNAL: usize = 2;
+
+// Lane patterns of the lowest six bits of the vector number
+const LANE_PATTERNS: [u64; 6] = [
+    0xAAAA_AAAA_AAAA_AAAA,
+    0xCCCC_CCCC_CCCC_CCCC,
+    0xF0F0_F0F0_F0F0_F0F0,
+    0xFF00_FF00_FF00_FF00,
+    0xFFFF_0000_FFFF_0000,
+    0xFFFF_FFFF_0000_0000,
+];
+
+#[derive(Debug)]
+pub enum BatchError {
+    // State can't be carried between the vectors of a batch
+    Stateful { gate: GateInfo },
+    CombinationalLoop { gates: Vec<GateInfo> },
+    Undriven { gate: GateInfo, bit: usize },
+    MultipleDrivers { gate: GateInfo, bit: usize },
+    // A gate put out X or Z for known inputs
+    Unknown { gate: GateInfo, vector: usize },
+    // A vector needs one bit per circuit input bit
+    VectorWidth { vector: usize, bits: usize, expected: usize },
+    // The circuit isn't the one the netlist was built from, or a gate in it was changed since
+    CircuitChanged { gate: GateInfo },
+}
+
+impl Error for BatchError {}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Stateful { gate } => write!(f, "Gate {} keeps state and can't be batch simulated", gate),
+            BatchError::CombinationalLoop { gates } => {
+                let names: Vec<String> = gates.iter().map(|g| g.to_string()).collect();
+                write!(f, "Batch simulation needs a circuit without loops, found one through [{}]", names.join(", "))
+            },
+            BatchError::Undriven { gate, bit } => write!(f, "Input bit {} of {} isn't driven", bit, gate),
+            BatchError::MultipleDrivers { gate, bit } => write!(f, "Input bit {} of {} has more than one driver", bit, gate),
+            BatchError::Unknown { gate, vector } => write!(f, "Gate {} put out X or Z for vector {}", gate, vector),
+            BatchError::VectorWidth { vector, bits, expected } => write!(f, "Vector {} has {} bits, the circuit has {} input bits", vector, bits, expected),
+            BatchError::CircuitChanged { gate } => write!(f, "Gate {} isn't in the circuit the netlist was built from anymore", gate),
+        }
+    }
+}
+
+// One operation of the netlist, every operand and result is a signal index
+pub enum Step {
+    Not { a: usize, dest: usize },
+    And { a: usize, b: usize, dest: usize },
+    Or { a: usize, b: usize, dest: usize },
+    // A gate that has no truth table, evaluated once per vector. The path holds the
+    // ids of the nested circuits that lead to the gate, ending with its own id.
+    PerVector {
+        path: Vec<Uuid>,
+        info: GateInfo,
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+    },
+}
+
+// A circuit flattened into AND, OR and NOT on 64 bit signals, so one pass
+// evaluates 64 input vectors. Nested circuits are inlined and every other gate
+// is replaced by the logic of its truth table.
+pub struct BatchNetlist {
+    input_num: usize,
+    signal_num: usize,
+    steps: Vec<Step>,
+    outputs: Vec<usize>,
+}
+
+impl BatchNetlist {
+    // Compiling gates into logic runs them, so the circuit is borrowed mutably
+    pub fn new(circuit: &mut Circuit) -> Result<Self, BatchError> {
+        let input_num = circuit.get_input_num();
+        let mut builder = Builder {
+            signal_num: FIRST_INPUT_SIGNAL + input_num,
+            steps: Vec::new(),
+            sop_cache: HashMap::new(),
+        };
+
+        let inputs: Vec<usize> = (FIRST_INPUT_SIGNAL..FIRST_INPUT_SIGNAL + input_num).collect();
+        let outputs = builder.flatten(circuit, &mut Vec::new(), &inputs)?;
+
+        Ok(Self {
+            input_num,
+            signal_num: builder.signal_num,
+            steps: builder.steps,
+            outputs,
+        })
+    }
+
+    pub fn get_input_num(&self) -> usize {
+        self.input_num
+    }
+
+    pub fn get_output_num(&self) -> usize {
+        self.outputs.len()
+    }
+
+    // Number of primitive operations one pass runs
+    pub fn get_op_num(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn get_signal_num(&self) -> usize {
+        self.signal_num
+    }
+
+    pub fn get_steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    // Signal of every circuit output bit
+    pub fn get_outputs(&self) -> &[usize] {
+        &self.outputs
+    }
+
+    // Bit i of every input word belongs to vector i, only the first lanes vectors are valid.
+    // The circuit has to be the one the netlist was built from, it runs the per vector gates.
+    pub fn eval(&self, circuit: &mut Circuit, inputs: &[u64], lanes: usize) -> Result<Vec<u64>, Box<dyn Error>> {
+        // Every vector of the batch has one bit per word, the first one is reported
+        if inputs.len() != self.input_num {
+            return Err(Box::new(BatchError::VectorWidth { vector: 0, bits: inputs.len(), expected: self.input_num }));
+        }
+
+        let mut signals = vec![0; self.signal_num];
+        signals[ONE_SIGNAL] = u64::MAX;
+        signals[FIRST_INPUT_SIGNAL..FIRST_INPUT_SIGNAL + self.input_num].copy_from_slice(inputs);
+
+        for step in self.steps.iter() {
+            match step {
+                Step::Not { a, dest } => signals[*dest] = !signals[*a],
+                Step::And { a, b, dest } => signals[*dest] = signals[*a] & signals[*b],
+                Step::Or { a, b, dest } => signals[*dest] = signals[*a] | signals[*b],
+                Step::PerVector { path, info, inputs, outputs } => {
+                    let gate = circuit.get_nested_gate_mut(path)
+                        .filter(|gate| gate.get_input_num() == inputs.len() && gate.get_output_num() == outputs.len())
+                        .ok_or_else(|| BatchError::CircuitChanged { gate: info.clone() })?;
+                    eval_per_vector(gate, info, inputs, outputs, &mut signals, lanes)?;
+                },
+            }
+        }
+
+        Ok(self.outputs.iter().map(|&s| signals[s]).collect())
+    }
+
+    // Evaluates any number of vectors, BATCH_SIZE at a time
+    pub fn eval_vectors(&self, circuit: &mut Circuit, vectors: &[Vec<bool>]) -> Result<Vec<Vec<bool>>, Box<dyn Error>> {
+        if let Some((vector, v)) = vectors.iter().enumerate().find(|(_, v)| v.len() != self.input_num) {
+            return Err(Box::new(BatchError::VectorWidth { vector, bits: v.len(), expected: self.input_num }));
+        }
+
+        let mut results = Vec::with_capacity(vectors.len());
+
+        for chunk in vectors.chunks(BATCH_SIZE) {
+            let inputs: Vec<u64> = (0..self.input_num)
+                .map(|bit| chunk.iter().enumerate().fold(0, |word, (lane, v)| word | (v[bit] as u64) << lane))
+                .collect();
+            let outputs = self.eval(circuit, &inputs, chunk.len())?;
+
+            for lane in 0..chunk.len() {
+                results.push(outputs.iter().map(|word| word >> lane & 1 == 1).collect());
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Truth table over every input combination, same row layout as Circuit::compile
+    pub fn truth_table(&self, circuit: &mut Circuit) -> Result<TruthTable, Box<dyn Error>> {
+        let n = self.input_num;
+        let total = 1_usize << n;
+        let mut table = TruthTable::new();
+
+        for batch in 0..total.div_ceil(BATCH_SIZE) {
+            let inputs = exhaustive_inputs(n, batch);
+            let lanes = BATCH_SIZE.min(total - batch * BATCH_SIZE);
+            let outputs = self.eval(circuit, &inputs, lanes)?;
+
+            for lane in 0..lanes {
+                let row: Vec<bool> = inputs.iter().map(|word| word >> lane & 1 == 1).collect();
+                table.add(row, outputs.iter().map(|word| word >> lane & 1 == 1).collect());
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+// Input words of one batch when counting through every input combination,
+// the first input is the highest bit of the vector number
+pub fn exhaustive_inputs(input_num: usize, batch: usize) -> Vec<u64> {
+    (0..input_num).map(|bit| {
+        let k = input_num - 1 - bit;
+        if k < LANE_PATTERNS.len() {
+            LANE_PATTERNS[k]
+        }
+        else if batch >> (k - LANE_PATTERNS.len()) & 1 == 1 {
+            u64::MAX
+        }
+        else {
+            0
+        }
+    }).collect()
+}
+
+fn eval_per_vector(gate: &mut dyn LogicGate, info: &GateInfo, inputs: &[usize], outputs: &[usize], signals: &mut [u64], lanes: usize) -> Result<(), Box<dyn Error>> {
+    let saved_ins = gate.get_input_values();
+    let saved_outs = gate.get_output_values();
+
+    let mut words = vec![0_u64; outputs.len()];
+    let mut result: Result<(), Box<dyn Error>> = Ok(());
+    for lane in 0..lanes {
+        for (i, &s) in inputs.iter().enumerate() {
+            gate.set_input(i, signals[s] >> lane & 1 == 1);
+        }
+        if let Err(source) = gate.calculate() {
+            result = Err(GateError { gate: info.clone(), source }.into());
+            break;
+        }
+
+        let values: Option<Vec<bool>> = gate.get_output_values().iter().map(|v| v.to_bool()).collect();
+        let Some(values) = values else {
+            result = Err(BatchError::Unknown { gate: info.clone(), vector: lane }.into());
+            break;
+        };
+        for (word, value) in words.iter_mut().zip(values) {
+            *word |= (value as u64) << lane;
+        }
+    }
+
+    // The gate is part of the circuit, it shouldn't look like it saw these vectors
+    restore_values(gate, &saved_ins, &saved_outs);
+    result?;
+
+    for (&s, word) in outputs.iter().zip(words) {
+        signals[s] = word;
+    }
+    Ok(())
+}
+
+pub(crate) fn restore_values(gate: &mut dyn LogicGate, ins: &[Logic], outs: &[Logic]) {
+    for (i, value) in ins.iter().enumerate() {
+        gate.set_input_value(i, *value);
+    }
+    for (i, value) in outs.iter().enumerate() {
+        gate.set_output_value(i, *value);
+    }
+}
+
+// Rows of a truth table sorted by their inputs
+type Rows = Vec<(Vec<bool>, Vec<bool>)>;
+
+struct Builder {
+    signal_num: usize,
+    steps: Vec<Step>,
+    // Gates with the same truth table share the minimized logic
+    sop_cache: HashMap<Rows, Vec<SumOfProducts>>,
+}
+
+impl Builder {
+    fn new_signal(&mut self) -> usize {
+        self.signal_num += 1;
+        self.signal_num - 1
+    }
+
+    fn not(&mut self, a: usize) -> usize {
+        match a {
+            ZERO_SIGNAL => ONE_SIGNAL,
+            ONE_SIGNAL => ZERO_SIGNAL,
+            _ => {
+                let dest = self.new_signal();
+                self.steps.push(Step::Not { a, dest });
+                dest
+            },
+        }
+    }
+
+    fn and(&mut self, a: usize, b: usize) -> usize {
+        match (a, b) {
+            (ZERO_SIGNAL, _) | (_, ZERO_SIGNAL) => ZERO_SIGNAL,
+            (ONE_SIGNAL, x) | (x, ONE_SIGNAL) => x,
+            _ => {
+                let dest = self.new_signal();
+                self.steps.push(Step::And { a, b, dest });
+                dest
+            },
+        }
+    }
+
+    fn or(&mut self, a: usize, b: usize) -> usize {
+        match (a, b) {
+            (ONE_SIGNAL, _) | (_, ONE_SIGNAL) => ONE_SIGNAL,
+            (ZERO_SIGNAL, x) | (x, ZERO_SIGNAL) => x,
+            _ => {
+                let dest = self.new_signal();
+                self.steps.push(Step::Or { a, b, dest });
+                dest
+            },
+        }
+    }
+
+    // Adds the logic of one circuit with its input bits on the given signals, returns the signals of its output bits.
+    // The path holds the ids of the circuits above this one.
+    fn flatten(&mut self, circuit: &mut Circuit, path: &mut Vec<Uuid>, inputs: &[usize]) -> Result<Vec<usize>, BatchError> {
+        let input_widths = circuit.get_input_widths();
+        let nodes: Vec<Uuid> = circuit.circuit_inputs.iter()
+            .chain(circuit.gates.iter())
+            .chain(circuit.circuit_outputs.iter())
+            .copied()
+            .collect();
+        let first_output = circuit.circuit_inputs.len() + circuit.gates.len();
+
+        let mut index_of: HashMap<Uuid, usize> = HashMap::new();
+        for (i, id) in nodes.iter().enumerate() {
+            index_of.entry(*id).or_insert(i);
+        }
+        let edges: Vec<(usize, usize, Connection)> = circuit.connections.iter()
+            .filter_map(|conn| {
+                let src = index_of.get(&conn.get_input_id())?;
+                let dest = index_of.get(&conn.get_output_id())?;
+                Some((*src, *dest, conn.clone()))
+            })
+            .collect();
+
+        let infos: Vec<GateInfo> = nodes.iter()
+            .map(|id| GateInfo { id: *id, name: circuit.arena.get(id).map_or(String::new(), |gate| gate.get_name()) })
+            .collect();
+        let order = topological_order(&infos, first_output, &edges)?;
+
+        let mut node_inputs: Vec<Vec<Option<usize>>> = nodes.iter()
+            .map(|id| vec![None; circuit.arena.get(id).map_or(0, |gate| gate.get_input_num())])
+            .collect();
+        let info = |i: usize| infos[i].clone();
+
+        let mut circuit_outputs = Vec::new();
+        for n in order {
+            let outputs = if n < circuit.circuit_inputs.len() {
+                let offset = pin_offset(&input_widths, n);
+                inputs[offset..offset + input_widths[n]].to_vec()
+            }
+            else {
+                let driven: Vec<usize> = node_inputs[n].iter().enumerate()
+                    .map(|(bit, s)| s.ok_or_else(|| BatchError::Undriven { gate: info(n), bit }))
+                    .collect::<Result<_, _>>()?;
+
+                if n >= first_output {
+                    circuit_outputs.push((n, driven.clone()));
+                    driven
+                }
+                else {
+                    let gate = circuit.arena.get_mut(&nodes[n]).expect("node of the circuit");
+                    path.push(nodes[n]);
+                    let outputs = match gate.as_circuit_mut() {
+                        Some(nested) => self.flatten(nested, path, &driven),
+                        None => self.add_gate(gate, path.clone(), info(n), driven),
+                    };
+                    path.pop();
+                    outputs?
+                }
+            };
+
+            for (_, dest, conn) in edges.iter().filter(|e| e.0 == n) {
+                for bit in 0..conn.get_width() {
+                    let dest_bit = conn.get_dest_offset() + bit;
+                    if node_inputs[*dest][dest_bit].is_some() {
+                        return Err(BatchError::MultipleDrivers { gate: info(*dest), bit: dest_bit });
+                    }
+                    node_inputs[*dest][dest_bit] = Some(outputs[conn.get_src_offset() + bit]);
+                }
+            }
+        }
+
+        circuit_outputs.sort_by_key(|(n, _)| *n);
+        Ok(circuit_outputs.into_iter().flat_map(|(_, signals)| signals).collect())
+    }
+
+    fn add_gate(&mut self, gate: &mut dyn LogicGate, path: Vec<Uuid>, info: GateInfo, inputs: Vec<usize>) -> Result<Vec<usize>, BatchError> {
+        if !gate.compilable() {
+            return Err(BatchError::Stateful { gate: info });
+        }
+
+        let table = if inputs.len() <= MAX_TABLE_INPUTS {
+            // Compiling a lua gate runs it on every row, the circuit shouldn't notice
+            let saved_ins = gate.get_input_values();
+            let saved_outs = gate.get_output_values();
+            let table = gate.compile().ok();
+            restore_values(gate, &saved_ins, &saved_outs);
+            table.filter(|t| t.map.len() == 1 << inputs.len())
+        }
+        else {
+            None
+        };
+
+        let Some(table) = table else {
+            let outputs: Vec<usize> = (0..gate.get_output_num()).map(|_| self.new_signal()).collect();
+            self.steps.push(Step::PerVector { path, info, inputs, outputs: outputs.clone() });
+            return Ok(outputs);
+        };
+
+        let mut rows: Rows = table.map.into_iter().collect();
+        rows.sort();
+        let sops = match self.sop_cache.get(&rows) {
+            Some(sops) => sops.clone(),
+            None => {
+                let table = TruthTable { map: rows.iter().cloned().collect() };
+                let sops = minimize_table(&table);
+                self.sop_cache.insert(rows, sops.clone());
+                sops
+            },
+        };
+
+        let mut negated: HashMap<usize, usize> = HashMap::new();
+        let mut outputs = Vec::new();
+        for sop in sops.iter() {
+            let mut sum = ZERO_SIGNAL;
+            for cube in sop.cubes.iter() {
+                let mut product = ONE_SIGNAL;
+                for (literal, &s) in cube.0.iter().zip(inputs.iter()) {
+                    let term = match literal {
+                        Some(true) => s,
+                        Some(false) => match negated.get(&s) {
+                            Some(&not_s) => not_s,
+                            None => {
+                                let not_s = self.not(s);
+                                negated.insert(s, not_s);
+                                not_s
+                            },
+                        },
+                        None => continue,
+                    };
+                    product = self.and(product, term);
+                }
+                sum = self.or(sum, product);
+            }
+            outputs.push(sum);
+        }
+
+        Ok(outputs)
+    }
+}
+
+// Kahn's algorithm over the nodes the circuit outputs depend on
+fn topological_order(nodes: &[GateInfo], first_output: usize, edges: &[(usize, usize, Connection)]) -> Result<Vec<usize>, BatchError> {
+    // Gates that don't lead to an output are left out
+    let mut needed: HashSet<usize> = (first_output..nodes.len()).collect();
+    let mut queue: VecDeque<usize> = needed.iter().copied().collect();
+    while let Some(n) = queue.pop_front() {
+        for (src, _, _) in edges.iter().filter(|e| e.1 == n) {
+            if needed.insert(*src) {
+                queue.push_back(*src);
+            }
+        }
+    }
+
+    let mut fanin = vec![0; nodes.len()];
+    for (src, dest, _) in edges.iter() {
+        if needed.contains(src) && needed.contains(dest) {
+            fanin[*dest] += 1;
+        }
+    }
+
+    let mut ready: VecDeque<usize> = (0..nodes.len()).filter(|n| needed.contains(n) && fanin[*n] == 0).collect();
+    let mut order = Vec::new();
+    while let Some(n) = ready.pop_front() {
+        order.push(n);
+        for (_, dest, _) in edges.iter().filter(|e| e.0 == n) {
+            if needed.contains(dest) {
+                fanin[*dest] -= 1;
+                if fanin[*dest] == 0 {
+                    ready.push_back(*dest);
+                }
+            }
+        }
+    }
+
+    if order.len() < needed.len() {
+        let mut gates: Vec<usize> = needed.into_iter().filter(|n| fanin[*n] > 0).collect();
+        gates.sort();
+        let gates = gates.into_iter().map(|n| nodes[n].clone()).collect();
+        return Err(BatchError::CombinationalLoop { gates });
+    }
+
+    Ok(order)
+}
@@ -0,0 +1,246 @@
+// Headless simulator, runs a saved circuit or a single component without any window
+//
+// simulate <circuit.json | component.lua | netlist.blif | netlist.v> [--inputs FILE] [--steps N] [--truth-table] [--vcd FILE] [--equiv OTHER] [--test BENCH.lua]... [--jobs N]
+//
+// Every line of the input file (or stdin) is one input vector, e.g. "0110" or "0 1 X Z".
+// Empty lines and everything after a '#' are ignored. A circuit without inputs takes every
+// empty line as its vector and runs once if there are none.
+// Each step of a circuit is one tick, so clocks and the gates they drive move on.
+// --vcd records every step of a circuit as a Value Change Dump, one tick per step.
+// --equiv checks that both compute the same function and prints an input vector they disagree on.
+// --test runs a lua test bench against a fresh copy of the circuit, it can be given more than once.
+// --jobs sets how many benches run at the same time, by default one per core.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use new_logic_gates::circuit_file::load_circuit;
+use new_logic_gates::equiv::check_equivalence;
+use new_logic_gates::logic::logic_vec_to_string;
+use new_logic_gates::netlist::load_netlist;
+use new_logic_gates::parallel::{self, JobControl};
+use new_logic_gates::vcd::Recording;
+use new_logic_gates::{BasicGate, Logic, LogicGate};
+
+const USAGE: &str = "Usage: simulate <circuit.json | component.lua | netlist.blif | netlist.v> [--inputs FILE] [--steps N] [--truth-table] [--vcd FILE] [--equiv OTHER] [--test BENCH.lua]... [--jobs N]";
+
+struct Args {
+    circuit: PathBuf,
+    inputs: Option<PathBuf>,
+    steps: usize,
+    truth_table: bool,
+    vcd: Option<PathBuf>,
+    equiv: Option<PathBuf>,
+    tests: Vec<PathBuf>,
+    jobs: usize,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, Box<dyn Error>> {
+    let mut circuit = None;
+    let mut inputs = None;
+    let mut steps = 1;
+    let mut truth_table = false;
+    let mut vcd = None;
+    let mut equiv = None;
+    let mut tests = Vec::new();
+    let mut jobs = parallel::default_threads();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--inputs" | "-i" => {
+                let path = args.next().ok_or("--inputs needs a file, use - for stdin")?;
+                if path != "-" {
+                    inputs = Some(PathBuf::from(path));
+                }
+            },
+            "--steps" | "-n" => {
+                let n = args.next().ok_or("--steps needs a number")?;
+                steps = n.parse().map_err(|_| format!("Invalid number of steps: {}", n))?;
+            },
+            "--truth-table" | "-t" => truth_table = true,
+            "--vcd" => vcd = Some(PathBuf::from(args.next().ok_or("--vcd needs a file")?)),
+            "--equiv" | "-e" => equiv = Some(PathBuf::from(args.next().ok_or("--equiv needs a second circuit or component")?)),
+            "--test" => tests.push(PathBuf::from(args.next().ok_or("--test needs a lua test bench")?)),
+            "--jobs" | "-j" => {
+                let n = args.next().ok_or("--jobs needs a number")?;
+                jobs = n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("Invalid number of jobs: {}", n))?;
+            },
+            "--help" | "-h" => return Err(USAGE.into()),
+            _ if circuit.is_none() && !arg.starts_with('-') => circuit = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unknown argument: {}\n{}", arg, USAGE).into()),
+        }
+    }
+
+    Ok(Args {
+        circuit: circuit.ok_or(USAGE)?,
+        inputs,
+        steps,
+        truth_table,
+        vcd,
+        equiv,
+        tests,
+        jobs,
+    })
+}
+
+fn load(path: &Path) -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("lua") => {
+            let name = path.file_stem().map_or("GATE".to_string(), |n| n.to_string_lossy().to_ascii_uppercase());
+            Ok(Box::new(BasicGate::from_lua(name, path.into())?))
+        },
+        Some("blif") | Some("v") => Ok(Box::new(load_netlist(path, Path::new("comps"))?)),
+        _ => Ok(Box::new(load_circuit(path)?)),
+    }
+}
+
+fn bits_to_string(bits: &[bool]) -> String {
+    bits.iter().map(|&b| if b { '1' } else { '0' }).collect()
+}
+
+fn parse_vector(full_line: &str, line_num: usize, width: usize) -> Result<Option<Vec<Logic>>, Box<dyn Error>> {
+    let line = full_line.split('#').next().unwrap_or("");
+
+    let mut bits = Vec::new();
+    for c in line.chars().filter(|c| !c.is_whitespace() && *c != ',') {
+        let bit = Logic::from_char(c).ok_or_else(|| format!("line {}: invalid bit '{}'", line_num, c))?;
+        bits.push(bit);
+    }
+
+    // Only a line without a comment is the vector of a circuit without inputs
+    if bits.is_empty() && (width > 0 || !full_line.trim().is_empty()) {
+        return Ok(None);
+    }
+    if bits.len() != width {
+        return Err(format!("line {}: expected {} inputs, got {}", line_num, width, bits.len()).into());
+    }
+
+    Ok(Some(bits))
+}
+
+fn print_truth_table(gate: &mut Box<dyn LogicGate>) -> Result<(), Box<dyn Error>> {
+    let table = gate.compile()?;
+
+    let mut rows: Vec<_> = table.map.iter().collect();
+    rows.sort();
+
+    for (inputs, outputs) in rows {
+        println!("{} -> {}", bits_to_string(inputs), bits_to_string(outputs));
+    }
+
+    Ok(())
+}
+
+fn simulate(gate: &mut Box<dyn LogicGate>, reader: impl BufRead, steps: usize, mut recording: Option<&mut Recording>) -> Result<(), Box<dyn Error>> {
+    let width = gate.get_input_num();
+    let mut step = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let Some(vector) = parse_vector(&line?, i + 1, width)? else {
+            continue;
+        };
+        run_vector(gate, &vector, steps, &mut step, recording.as_deref_mut())?;
+    }
+
+    if width == 0 && step == 0 {
+        run_vector(gate, &[], steps, &mut step, recording)?;
+    }
+
+    Ok(())
+}
+
+fn run_vector(gate: &mut Box<dyn LogicGate>, vector: &[Logic], steps: usize, step: &mut usize, mut recording: Option<&mut Recording>) -> Result<(), Box<dyn Error>> {
+    for (index, value) in vector.iter().enumerate() {
+        gate.set_input_value(index, *value);
+    }
+
+    for _ in 0..steps {
+        // A single component has no clock, it is only calculated again
+        match gate.as_circuit_mut() {
+            Some(circuit) => circuit.tick()?,
+            None => gate.calculate()?,
+        }
+        println!("{}: {} -> {}", step, logic_vec_to_string(vector), logic_vec_to_string(&gate.get_output_values()));
+        if let (Some(recording), Some(circuit)) = (recording.as_deref_mut(), gate.as_circuit()) {
+            recording.sample(circuit, circuit.get_time());
+        }
+        *step += 1;
+    }
+
+    Ok(())
+}
+
+// Every bench gets a freshly loaded gate so they can't see each others state,
+// that also lets them run on different threads
+fn run_tests(path: &Path, benches: &[PathBuf], jobs: usize) -> Result<(), Box<dyn Error>> {
+    let mut named = Vec::new();
+    for bench in benches {
+        let name = bench.file_name().map_or("bench".to_string(), |n| n.to_string_lossy().to_string());
+        let code = std::fs::read_to_string(bench).map_err(|err| format!("Can't read {}: {}", bench.display(), err))?;
+        named.push((name, code));
+    }
+
+    let reports = parallel::run_test_benches(|| load(path).map_err(|err| err.to_string()), &named, jobs, &JobControl::new())?;
+
+    let mut failed = 0;
+    for ((name, _), report) in named.iter().zip(&reports) {
+        match &report.failure {
+            None => println!("PASS {} ({} checks, {} steps)", name, report.checks, report.steps),
+            Some(failure) => {
+                println!("FAIL {} at {}", name, failure);
+                failed += 1;
+            },
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} test benches failed", failed, benches.len()).into());
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = parse_args(std::env::args().skip(1))?;
+    if !args.tests.is_empty() {
+        return run_tests(&args.circuit, &args.tests, args.jobs);
+    }
+
+    let mut gate = load(&args.circuit)?;
+
+    if args.truth_table {
+        return print_truth_table(&mut gate);
+    }
+
+    if let Some(other) = &args.equiv {
+        let mut other = load(other)?;
+        return match check_equivalence(gate.as_mut(), other.as_mut())? {
+            None => {
+                println!("Equivalent");
+                Ok(())
+            },
+            Some(counterexample) => Err(format!("Not equivalent: {}", counterexample).into()),
+        };
+    }
+
+    let reader: Box<dyn Read> = match &args.inputs {
+        Some(path) => Box::new(std::fs::File::open(path)?),
+        None => Box::new(std::io::stdin()),
+    };
+
+    let Some(vcd) = &args.vcd else {
+        return simulate(&mut gate, BufReader::new(reader), args.steps, None);
+    };
+
+    let circuit = gate.as_circuit().ok_or("--vcd needs a circuit or netlist, not a single component")?;
+    let mut recording = Recording::new(circuit);
+    simulate(&mut gate, BufReader::new(reader), args.steps, Some(&mut recording))?;
+    recording.save_vcd(vcd)
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
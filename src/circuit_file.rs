@@ -0,0 +1,274 @@
+use core::fmt;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::clock::ClockGate;
+use crate::component::{ComponentInfo, Symbol};
+use crate::{BasicGate, Circuit, CircuitBus, LogicGate, LuaCode, TruthTable};
+
+// Bump this whenever the document layout changes and add a migration below
+pub const FORMAT_VERSION: u32 = 1;
+
+// Migrations from one format version to the next, MIGRATIONS[0] lifts a
+// version 1 document to version 2 and so on. Nested circuits are part of
+// the same document and have to be migrated by the same function.
+const MIGRATIONS: &[Migration] = &[];
+
+type Migration = fn(&mut Value) -> Result<(), CircuitFileError>;
+
+#[derive(Debug)]
+pub struct CircuitFileError {
+    err: String,
+}
+
+impl CircuitFileError {
+    fn new(err: String) -> Self {
+        Self { err }
+    }
+}
+
+impl Error for CircuitFileError {}
+
+impl fmt::Display for CircuitFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid circuit file: {}", self.err)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CircuitDocument {
+    pub version: u32,
+    pub name: String,
+    pub gates: Vec<GateEntry>,
+    pub connections: Vec<ConnectionEntry>,
+    // Only set for circuits that are used as components
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<Symbol>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<ComponentInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GateRole {
+    Input,
+    Output,
+    Gate,
+}
+
+// Where a gate's behaviour comes from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum GateSource {
+    Lua { path: PathBuf },
+    LuaCode { code: String },
+    TruthTable { table: TruthTable },
+    Circuit { circuit: Box<CircuitDocument> },
+    Bus {
+        #[serde(default = "default_bus_width")]
+        width: usize,
+    },
+    Clock {
+        period: u64,
+        #[serde(default = "default_duty_cycle")]
+        duty_cycle: f32,
+    },
+}
+
+fn default_bus_width() -> usize {
+    1
+}
+
+fn default_duty_cycle() -> f32 {
+    0.5
+}
+
+// Position and size on the canvas, not needed to simulate
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GateLayout {
+    pub pos: (f32, f32),
+    pub size: (f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GateEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub role: GateRole,
+    pub source: GateSource,
+    #[serde(default)]
+    pub memory: Vec<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<GateLayout>,
+    // Only set when the instance overrides the DELAY of its component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConnectionEntry {
+    pub src: Uuid,
+    pub src_index: usize,
+    pub dest: Uuid,
+    pub dest_index: usize,
+}
+
+impl GateSource {
+    pub fn build(&self, name: String) -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+        let gate: Box<dyn LogicGate> = match self {
+            GateSource::Lua { path } => Box::new(BasicGate::from_lua(name, path.clone().into_boxed_path())?),
+            GateSource::LuaCode { code } => Box::new(BasicGate::from_lua_code(name, LuaCode(code.clone()))?),
+            GateSource::TruthTable { table } => Box::new(BasicGate::from_truth_table(name, table.clone())),
+            GateSource::Circuit { circuit } => Box::new(Circuit::from_document(circuit)?),
+            GateSource::Bus { width } => Box::new(CircuitBus::with_width(*width)),
+            GateSource::Clock { period, duty_cycle } => Box::new(ClockGate::new(name, *period, *duty_cycle)),
+        };
+
+        Ok(gate)
+    }
+}
+
+impl CircuitDocument {
+    pub fn get_gate(&self, id: &Uuid) -> Option<&GateEntry> {
+        self.gates.iter().find(|g| g.id == *id)
+    }
+}
+
+impl Circuit {
+    pub fn to_document(&self) -> Result<CircuitDocument, CircuitFileError> {
+        let mut ids: HashSet<Uuid> = HashSet::new();
+        let mut gates = Vec::new();
+
+        let all_gates = self.circuit_inputs.iter().map(|id| (id, GateRole::Input))
+            .chain(self.gates.iter().map(|id| (id, GateRole::Gate)))
+            .chain(self.circuit_outputs.iter().map(|id| (id, GateRole::Output)));
+
+        for (id, role) in all_gates {
+            let Some(gate_ref) = self.arena.get(id) else {
+                continue;
+            };
+            let source = gate_ref.get_source()
+                .ok_or_else(|| CircuitFileError::new(format!("Gate {} ({}) can't be saved", gate_ref.get_name(), id)))?;
+
+            ids.insert(*id);
+            gates.push(GateEntry {
+                id: *id,
+                name: gate_ref.get_name(),
+                role,
+                source,
+                memory: gate_ref.get_memory().unwrap_or_default(),
+                layout: self.layouts.get(id).copied(),
+                delay: gate_ref.get_delay_override(),
+            });
+        }
+
+        let mut connections = Vec::new();
+        for conn in self.connections.iter() {
+            let (src, dest) = (conn.get_input_id(), conn.get_output_id());
+
+            if ids.contains(&src) && ids.contains(&dest) {
+                connections.push(ConnectionEntry {
+                    src,
+                    src_index: conn.get_input_index(),
+                    dest,
+                    dest_index: conn.get_output_index(),
+                });
+            }
+        }
+
+        Ok(CircuitDocument {
+            version: FORMAT_VERSION,
+            name: self.name.clone(),
+            gates,
+            connections,
+            symbol: None,
+            info: None,
+        })
+    }
+
+    // Builds the circuit without any ui, the layout is only kept to be saved again
+    pub fn from_document(doc: &CircuitDocument) -> Result<Circuit, Box<dyn Error>> {
+        let mut circuit = Circuit::new(doc.name.clone());
+
+        for entry in doc.gates.iter() {
+            let mut gate = entry.source.build(entry.name.clone())?;
+            restore_memory(&mut gate, &entry.memory);
+            if entry.delay.is_some() {
+                gate.set_delay(entry.delay);
+            }
+
+            match entry.role {
+                GateRole::Input => circuit.add_input(gate, entry.id),
+                GateRole::Output => circuit.add_output(gate, entry.id),
+                GateRole::Gate => circuit.add_gate(gate, entry.id),
+            };
+            if let Some(layout) = entry.layout {
+                circuit.layouts.insert(entry.id, layout);
+            }
+        }
+
+        for conn in doc.connections.iter() {
+            if circuit.get_gate(&conn.src).is_none() {
+                return Err(CircuitFileError::new(format!("Connection from unknown gate {}", conn.src)).into());
+            }
+            if circuit.get_gate(&conn.dest).is_none() {
+                return Err(CircuitFileError::new(format!("Connection to unknown gate {}", conn.dest)).into());
+            }
+
+            circuit.connect(conn.src, conn.src_index, conn.dest, conn.dest_index)?;
+        }
+
+        Ok(circuit)
+    }
+}
+
+// Restores as much memory as still fits, the component may have changed since saving
+pub fn restore_memory(gate: &mut Box<dyn LogicGate>, memory: &[bool]) {
+    let len = gate.get_memory().map_or(0, |m| m.len()).min(memory.len());
+    for (i, value) in memory.iter().take(len).enumerate() {
+        gate.set_memory(i, *value);
+    }
+}
+
+fn migrate(mut value: Value) -> Result<Value, CircuitFileError> {
+    let version = value.get("version")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| CircuitFileError::new("missing format version".to_string()))? as u32;
+
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(CircuitFileError::new(format!("unsupported format version {}", version)));
+    }
+
+    for migration in MIGRATIONS.iter().skip(version as usize - 1) {
+        migration(&mut value)?;
+    }
+    value["version"] = Value::from(FORMAT_VERSION);
+
+    Ok(value)
+}
+
+pub fn save_document(doc: &CircuitDocument, path: &Path) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(doc)?;
+    std::fs::write(path, json)?;
+
+    Ok(())
+}
+
+pub fn load_document(path: &Path) -> Result<CircuitDocument, Box<dyn Error>> {
+    let json = std::fs::read_to_string(path)?;
+    let value = migrate(serde_json::from_str(&json)?)?;
+
+    Ok(serde_json::from_value(value)?)
+}
+
+pub fn save_circuit(circuit: &Circuit, path: &Path) -> Result<(), Box<dyn Error>> {
+    save_document(&circuit.to_document()?, path)
+}
+
+pub fn load_circuit(path: &Path) -> Result<Circuit, Box<dyn Error>> {
+    Circuit::from_document(&load_document(path)?)
+}
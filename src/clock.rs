@@ -0,0 +1,113 @@
+use std::error::Error;
+
+use crate::circuit_file::GateSource;
+use crate::{CantCompileGate, Logic, LogicGate, TruthTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+impl Edge {
+    // Detects an edge between two samples of a clock signal, X and Z never make an edge
+    pub fn between(last: Logic, current: Logic) -> Option<Edge> {
+        match (last, current) {
+            (Logic::Zero, Logic::One) => Some(Edge::Rising),
+            (Logic::One, Logic::Zero) => Some(Edge::Falling),
+            _ => None,
+        }
+    }
+
+    // Name passed to the OnClock hook of lua components
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Edge::Rising => "rising",
+            Edge::Falling => "falling",
+        }
+    }
+}
+
+// Clock source driven by the simulation time of the circuit it sits in.
+// The output is high for the first duty_cycle part of every period.
+pub struct ClockGate {
+    name: String,
+    period: u64,
+    duty_cycle: f32,
+    output: Logic,
+}
+
+impl ClockGate {
+    // period is in ticks, duty_cycle is the high part between 0.0 and 1.0
+    pub fn new(name: String, period: u64, duty_cycle: f32) -> Self {
+        let mut clock = Self {
+            name,
+            period: period.max(1),
+            duty_cycle: duty_cycle.clamp(0.0, 1.0),
+            output: Logic::Zero,
+        };
+        clock.set_time(0);
+        clock
+    }
+
+    pub fn get_period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn get_duty_cycle(&self) -> f32 {
+        self.duty_cycle
+    }
+
+    fn high_ticks(&self) -> u64 {
+        (self.period as f32 * self.duty_cycle).round() as u64
+    }
+}
+
+impl LogicGate for ClockGate {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_inputs(&self) -> Vec<bool> {
+        Vec::new()
+    }
+
+    fn get_outputs(&self) -> Vec<bool> {
+        vec![self.output.to_bool_lossy()]
+    }
+
+    fn get_output_values(&self) -> Vec<Logic> {
+        vec![self.output]
+    }
+
+    fn set_input(&mut self, _index: usize, _value: bool) {}
+
+    fn set_output(&mut self, _index: usize, value: bool) {
+        self.output = Logic::from(value);
+    }
+
+    fn set_output_value(&mut self, _index: usize, value: Logic) {
+        self.output = value;
+    }
+
+    fn calculate(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // The output depends on the time, not on any input
+    fn compilable(&self) -> bool {
+        false
+    }
+
+    fn compile(&mut self) -> Result<TruthTable, CantCompileGate> {
+        Err(CantCompileGate)
+    }
+
+    fn set_time(&mut self, time: u64) {
+        self.output = Logic::from(time % self.period < self.high_ticks());
+    }
+
+    fn get_source(&self) -> Option<GateSource> {
+        Some(GateSource::Clock { period: self.period, duty_cycle: self.duty_cycle })
+    }
+}
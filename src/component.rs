@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use mlua::Lua;
+use serde::{Deserialize, Serialize};
+
+use crate::circuit_file::{load_document, save_document, CircuitDocument, GateRole, GateSource};
+use crate::sandbox::sandboxed_lua;
+use crate::{Circuit, LogicGate};
+
+// Lua components, named after their file
+pub const LUA_DIR: &str = "./comps";
+
+// Saved canvases that can be placed like the lua gates in LUA_DIR
+pub const COMPONENT_DIR: &str = "./comps/circuits";
+
+// Components without a CATEGORY are listed here
+pub const DEFAULT_CATEGORY: &str = "Other";
+pub const CIRCUIT_CATEGORY: &str = "Circuits";
+
+// Describes a component in the gate selector, lua components set it with the
+// optional DESCRIPTION, CATEGORY, AUTHOR, VERSION and ICON globals. ICON is a
+// short text like "&" that is shown in front of the name.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ComponentInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+impl ComponentInfo {
+    // Numbers are fine too, e.g. VERSION = 1.2
+    pub fn from_lua(lua: &Lua) -> mlua::Result<Self> {
+        let globals = lua.globals();
+        let text = |name: &str| globals.get::<_, Option<String>>(name);
+
+        Ok(Self {
+            description: text("DESCRIPTION")?,
+            category: text("CATEGORY")?,
+            author: text("AUTHOR")?,
+            version: text("VERSION")?,
+            icon: text("ICON")?,
+        })
+    }
+
+    pub fn from_lua_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let lua = sandboxed_lua()?;
+        lua.load(&std::fs::read_to_string(path)?).exec()?;
+
+        Ok(Self::from_lua(&lua)?)
+    }
+
+    pub fn get_category(&self) -> &str {
+        self.category.as_deref().unwrap_or(DEFAULT_CATEGORY)
+    }
+
+    // Every word of the query has to be in the name, category, description or author, case doesn't matter
+    pub fn matches(&self, name: &str, query: &str) -> bool {
+        let fields = [Some(name), Some(self.get_category()), self.description.as_deref(), self.author.as_deref()];
+        let text = fields.iter().flatten().map(|field| field.to_lowercase()).collect::<Vec<_>>().join(" ");
+
+        query.to_lowercase().split_whitespace().all(|word| text.contains(word))
+    }
+}
+
+// In grid steps, like WIDTH in a lua component
+const SYMBOL_WIDTH: u16 = 4;
+
+// How a component looks when it is placed, the positions are counted around
+// the border like INPUT_POSITIONS and OUTPUT_POSITIONS of a lua component
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub width: u16,
+    pub height: u16,
+    pub inputs_pos: Vec<u16>,
+    pub outputs_pos: Vec<u16>,
+}
+
+// Inputs go on the left and outputs on the right edge, one row each. They are
+// in the same top to bottom order as their ports on the canvas, without a
+// layout the order of the pins is kept.
+pub fn derive_symbol(doc: &CircuitDocument) -> Symbol {
+    let rows = |role: GateRole| -> Vec<u16> {
+        let mut ports: Vec<(usize, f32)> = doc.gates.iter()
+            .filter(|entry| entry.role == role)
+            .enumerate()
+            .map(|(pin, entry)| (pin, entry.layout.map_or(pin as f32, |layout| layout.pos.1)))
+            .collect();
+        ports.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut rows = vec![0; ports.len()];
+        for (row, (pin, _)) in ports.into_iter().enumerate() {
+            rows[pin] = row as u16 + 1;
+        }
+        rows
+    };
+    let (input_rows, output_rows) = (rows(GateRole::Input), rows(GateRole::Output));
+
+    let width = SYMBOL_WIDTH;
+    let height = input_rows.len().max(output_rows.len()) as u16 + 1;
+
+    Symbol {
+        width,
+        height,
+        // The left edge is counted from the bottom up
+        inputs_pos: input_rows.iter().map(|row| 2 * width + 2 * height - row).collect(),
+        outputs_pos: output_rows.iter().map(|row| width + row).collect(),
+    }
+}
+
+// Turns a canvas into a component. BUTTON and LAMP can't be driven from the
+// outside, so every port becomes a one bit bus at the same place.
+pub fn make_component(doc: &CircuitDocument, name: &str) -> CircuitDocument {
+    let mut component = doc.clone();
+    component.name = name.to_string();
+
+    for entry in component.gates.iter_mut().filter(|entry| entry.role != GateRole::Gate) {
+        if !matches!(entry.source, GateSource::Bus { .. }) {
+            entry.name = "BUS".to_string();
+            entry.source = GateSource::Bus { width: 1 };
+            entry.memory.clear();
+        }
+    }
+
+    component.symbol = Some(derive_symbol(&component));
+    component
+}
+
+pub fn component_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name.to_ascii_lowercase() + ".json")
+}
+
+pub fn save_component(doc: &CircuitDocument, path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    save_document(doc, path)
+}
+
+// Components saved without a symbol get the derived one, without a category they go with the other circuits
+pub fn load_component(path: &Path) -> Result<(Circuit, Symbol, ComponentInfo), Box<dyn Error>> {
+    let doc = load_document(path)?;
+    let symbol = doc.symbol.clone().unwrap_or_else(|| derive_symbol(&doc));
+
+    let mut info = doc.info.clone().unwrap_or_default();
+    info.category.get_or_insert_with(|| CIRCUIT_CATEGORY.to_string());
+
+    // A port added or removed after the symbol was made has nowhere to go on the canvas
+    let circuit = Circuit::from_document(&doc)?;
+    let (ins, outs) = (circuit.get_input_widths().len(), circuit.get_output_widths().len());
+    if symbol.inputs_pos.len() != ins || symbol.outputs_pos.len() != outs {
+        return Err(format!(
+            "The symbol of {} has {} inputs and {} outputs, the circuit has {} and {}",
+            doc.name, symbol.inputs_pos.len(), symbol.outputs_pos.len(), ins, outs,
+        ).into());
+    }
+
+    Ok((circuit, symbol, info))
+}
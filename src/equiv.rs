@@ -0,0 +1,266 @@
+use core::fmt;
+use std::error::Error;
+
+use crate::batch::{exhaustive_inputs, restore_values, BatchNetlist, Step, BATCH_SIZE, FIRST_INPUT_SIGNAL, ONE_SIGNAL, ZERO_SIGNAL};
+use crate::logic::logic_vec_to_string;
+use crate::sat::{Lit, SatResult, Solver};
+use crate::{BasicGate, Circuit, Logic, LogicGate, TruthTable};
+
+// Up to this many input bits every combination is simulated, wider gates go to the SAT solver
+pub const EXHAUSTIVE_MAX_INPUTS: usize = 16;
+
+// Conflicts the SAT solver may run into before the check gives up
+pub const SAT_MAX_CONFLICTS: u64 = 1_000_000;
+
+// Random batches simulated before the SAT solver is started, most differences show up here
+const RANDOM_BATCHES: usize = 16;
+
+// An input vector the two gates disagree on and what each of them put out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    pub inputs: Vec<bool>,
+    pub left: Vec<Logic>,
+    pub right: Vec<Logic>,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inputs: Vec<Logic> = self.inputs.iter().map(|&b| Logic::from(b)).collect();
+        write!(f, "{} -> {} vs {}", logic_vec_to_string(&inputs), logic_vec_to_string(&self.left), logic_vec_to_string(&self.right))
+    }
+}
+
+#[derive(Debug)]
+pub enum EquivError {
+    // Input and output bits of both gates
+    PinMismatch { left: (usize, usize), right: (usize, usize) },
+    // Gates with memory don't have a single function to compare
+    Stateful { name: String },
+    // Wide gates can only be compared if they flatten to plain logic
+    Unsupported { name: String, reason: String },
+    GaveUp { conflicts: u64 },
+}
+
+impl Error for EquivError {}
+
+impl fmt::Display for EquivError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EquivError::PinMismatch { left, right } => write!(
+                f, "Gates have different pins: {} inputs and {} outputs vs {} inputs and {} outputs",
+                left.0, left.1, right.0, right.1
+            ),
+            EquivError::Stateful { name } => write!(f, "Gate {} keeps state, only combinational gates can be compared", name),
+            EquivError::Unsupported { name, reason } => write!(f, "Can't check {} with the SAT solver: {}", name, reason),
+            EquivError::GaveUp { conflicts } => write!(f, "SAT solver gave up after {} conflicts", conflicts),
+        }
+    }
+}
+
+// Checks that both gates compute the same function. Returns None if they do and
+// an input vector they disagree on otherwise.
+pub fn check_equivalence(left: &mut dyn LogicGate, right: &mut dyn LogicGate) -> Result<Option<Counterexample>, Box<dyn Error>> {
+    let left_pins = (left.get_input_num(), left.get_output_num());
+    let right_pins = (right.get_input_num(), right.get_output_num());
+    if left_pins != right_pins {
+        return Err(Box::new(EquivError::PinMismatch { left: left_pins, right: right_pins }));
+    }
+
+    for gate in [&*left, &*right] {
+        if !gate.compilable() {
+            return Err(Box::new(EquivError::Stateful { name: gate.get_name() }));
+        }
+    }
+
+    if left_pins.0 <= EXHAUSTIVE_MAX_INPUTS {
+        check_exhaustive(left, right)
+    }
+    else {
+        check_sat(left, right, SAT_MAX_CONFLICTS)
+    }
+}
+
+// Compares a gate with a reference truth table, e.g. one loaded from comps/*.json
+pub fn check_against_table(gate: &mut dyn LogicGate, table: &TruthTable) -> Result<Option<Counterexample>, Box<dyn Error>> {
+    let mut reference = BasicGate::from_truth_table("TRUTH_TABLE".to_string(), table.clone());
+    check_equivalence(gate, &mut reference)
+}
+
+// Simulates every input combination, circuits 64 at a time
+pub fn check_exhaustive(left: &mut dyn LogicGate, right: &mut dyn LogicGate) -> Result<Option<Counterexample>, Box<dyn Error>> {
+    let saved = [
+        (left.get_input_values(), left.get_output_values()),
+        (right.get_input_values(), right.get_output_values()),
+    ];
+
+    let input_num = left.get_input_num();
+    let total = 1_usize << input_num;
+    let result = {
+        let mut left = Model::new(left);
+        let mut right = Model::new(right);
+
+        (0..total.div_ceil(BATCH_SIZE)).try_fold(None, |found, batch| {
+            if found.is_some() {
+                return Ok(found);
+            }
+            let inputs = exhaustive_inputs(input_num, batch);
+            compare(&mut left, &mut right, &inputs, BATCH_SIZE.min(total - batch * BATCH_SIZE))
+        })
+    };
+
+    // The gates shouldn't look like they saw all those vectors
+    restore_values(left, &saved[0].0, &saved[0].1);
+    restore_values(right, &saved[1].0, &saved[1].1);
+    result
+}
+
+// Proves equivalence with a miter: a SAT solver looks for inputs where any output pair differs
+pub fn check_sat(left: &mut dyn LogicGate, right: &mut dyn LogicGate, max_conflicts: u64) -> Result<Option<Counterexample>, Box<dyn Error>> {
+    let (left_netlist, left_circuit) = sat_netlist(left)?;
+    let (right_netlist, right_circuit) = sat_netlist(right)?;
+    let input_num = left_netlist.get_input_num();
+
+    let mut solver = Solver::new();
+    let inputs: Vec<Lit> = (0..input_num).map(|_| Lit::new(solver.new_var(), false)).collect();
+    let left_outputs = encode(&mut solver, &left_netlist, &inputs);
+    let right_outputs = encode(&mut solver, &right_netlist, &inputs);
+
+    // At least one output pair has to differ
+    let differences: Vec<Lit> = left_outputs.into_iter().zip(right_outputs)
+        .map(|(a, b)| {
+            let d = Lit::new(solver.new_var(), false);
+            solver.add_clause(&[!d, a, b]);
+            solver.add_clause(&[!d, !a, !b]);
+            d
+        })
+        .collect();
+    solver.add_clause(&differences);
+
+    let mut left = Model::Netlist(left_netlist, left_circuit);
+    let mut right = Model::Netlist(right_netlist, right_circuit);
+
+    // Cheap random vectors first
+    let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+    for _ in 0..RANDOM_BATCHES {
+        let inputs: Vec<u64> = (0..input_num).map(|_| xorshift(&mut seed)).collect();
+        if let Some(counterexample) = compare(&mut left, &mut right, &inputs, BATCH_SIZE)? {
+            return Ok(Some(counterexample));
+        }
+    }
+
+    match solver.solve(max_conflicts) {
+        SatResult::Unsat => Ok(None),
+        SatResult::Unknown => Err(Box::new(EquivError::GaveUp { conflicts: max_conflicts })),
+        SatResult::Sat(model) => {
+            let inputs: Vec<u64> = inputs.iter().map(|lit| model[lit.var()] as u64).collect();
+            let counterexample = compare(&mut left, &mut right, &inputs, 1)?;
+            // The model satisfies the miter, so simulation has to agree
+            Ok(Some(counterexample.expect("SAT model is no counterexample")))
+        },
+    }
+}
+
+fn sat_netlist(gate: &mut dyn LogicGate) -> Result<(BatchNetlist, &mut Circuit), EquivError> {
+    let name = gate.get_name();
+    let unsupported = |reason: String| EquivError::Unsupported { name: name.clone(), reason };
+    let circuit = gate.as_circuit_mut().ok_or_else(|| unsupported(format!("gates with more than {} inputs have to be circuits", EXHAUSTIVE_MAX_INPUTS)))?;
+    let netlist = BatchNetlist::new(circuit).map_err(|err| unsupported(err.to_string()))?;
+
+    if let Some(Step::PerVector { info, .. }) = netlist.get_steps().iter().find(|step| matches!(step, Step::PerVector { .. })) {
+        return Err(unsupported(format!("gate {} has no truth table", info)));
+    }
+    Ok((netlist, circuit))
+}
+
+// Tseitin encoding of the netlist, returns the literal of every output bit
+fn encode(solver: &mut Solver, netlist: &BatchNetlist, inputs: &[Lit]) -> Vec<Lit> {
+    let one = Lit::new(solver.new_var(), false);
+    solver.add_clause(&[one]);
+
+    let mut lits: Vec<Lit> = vec![one; netlist.get_signal_num()];
+    lits[ZERO_SIGNAL] = !one;
+    lits[ONE_SIGNAL] = one;
+    lits[FIRST_INPUT_SIGNAL..FIRST_INPUT_SIGNAL + inputs.len()].copy_from_slice(inputs);
+
+    for step in netlist.get_steps() {
+        match step {
+            Step::Not { a, dest } => lits[*dest] = !lits[*a],
+            Step::And { a, b, dest } => {
+                let (a, b, d) = (lits[*a], lits[*b], Lit::new(solver.new_var(), false));
+                solver.add_clause(&[!d, a]);
+                solver.add_clause(&[!d, b]);
+                solver.add_clause(&[d, !a, !b]);
+                lits[*dest] = d;
+            },
+            Step::Or { a, b, dest } => {
+                let (a, b, d) = (lits[*a], lits[*b], Lit::new(solver.new_var(), false));
+                solver.add_clause(&[d, !a]);
+                solver.add_clause(&[d, !b]);
+                solver.add_clause(&[!d, a, b]);
+                lits[*dest] = d;
+            },
+            Step::PerVector { .. } => unreachable!("sat_netlist only lets plain logic through"),
+        }
+    }
+
+    netlist.get_outputs().iter().map(|&s| lits[s]).collect()
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// One side of the comparison, circuits that flatten to plain logic run 64 vectors per pass
+enum Model<'a> {
+    // The netlist and the circuit it was built from
+    Netlist(BatchNetlist, &'a mut Circuit),
+    Gate(&'a mut dyn LogicGate),
+}
+
+impl<'a> Model<'a> {
+    fn new(gate: &'a mut dyn LogicGate) -> Self {
+        if gate.as_circuit().is_none() {
+            return Model::Gate(gate);
+        }
+
+        let circuit = gate.as_circuit_mut().unwrap();
+        match BatchNetlist::new(circuit) {
+            Ok(netlist) => Model::Netlist(netlist, circuit),
+            Err(_) => Model::Gate(circuit),
+        }
+    }
+
+    // Outputs of every lane
+    fn eval(&mut self, inputs: &[u64], lanes: usize) -> Result<Vec<Vec<Logic>>, Box<dyn Error>> {
+        match self {
+            Model::Netlist(netlist, circuit) => {
+                let outputs = netlist.eval(circuit, inputs, lanes)?;
+                Ok((0..lanes).map(|lane| outputs.iter().map(|word| Logic::from(word >> lane & 1 == 1)).collect()).collect())
+            },
+            Model::Gate(gate) => {
+                (0..lanes).map(|lane| {
+                    for (i, word) in inputs.iter().enumerate() {
+                        gate.set_input(i, word >> lane & 1 == 1);
+                    }
+                    gate.calculate()?;
+                    Ok(gate.get_output_values())
+                }).collect()
+            },
+        }
+    }
+}
+
+fn compare(left: &mut Model, right: &mut Model, inputs: &[u64], lanes: usize) -> Result<Option<Counterexample>, Box<dyn Error>> {
+    let left_outputs = left.eval(inputs, lanes)?;
+    let right_outputs = right.eval(inputs, lanes)?;
+
+    let lane = (0..lanes).find(|&lane| left_outputs[lane] != right_outputs[lane]);
+    Ok(lane.map(|lane| Counterexample {
+        inputs: inputs.iter().map(|word| word >> lane & 1 == 1).collect(),
+        left: left_outputs[lane].clone(),
+        right: right_outputs[lane].clone(),
+    }))
+}
@@ -0,0 +1,54 @@
+use core::fmt;
+use std::error::Error;
+
+use uuid::Uuid;
+
+use crate::Circuit;
+
+// Where the circuit of a tab lives, the tab it was opened from and the id of
+// the circuit gate on every level below
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstancePath {
+    pub root: Uuid,
+    pub gates: Vec<Uuid>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InstanceError {
+    RootClosed,
+    // A circuit gate on the way was removed or replaced
+    NotNested,
+}
+
+impl Error for InstanceError {}
+
+impl fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceError::RootClosed => write!(f, "The tab of the outer circuit was closed"),
+            InstanceError::NotNested => write!(f, "The subcircuit isn't part of the outer circuit anymore"),
+        }
+    }
+}
+
+impl InstancePath {
+    // The circuit the path leads to, root is the circuit of the outer tab while it is open
+    pub fn resolve<'a>(&self, root: Option<&'a Circuit>) -> Result<&'a Circuit, InstanceError> {
+        let root = root.ok_or(InstanceError::RootClosed)?;
+        root.get_nested(&self.gates).ok_or(InstanceError::NotNested)
+    }
+
+    // Names from the outer tab down to the instance, each with the path to open it
+    pub fn breadcrumbs(&self, root_name: &str, root: Option<&Circuit>) -> Result<Vec<(String, InstancePath)>, InstanceError> {
+        let mut circuit = root.ok_or(InstanceError::RootClosed)?;
+
+        let mut crumbs = vec![(root_name.to_string(), InstancePath { root: self.root, gates: vec![] })];
+        for (depth, id) in self.gates.iter().enumerate() {
+            let gate = circuit.get_gate(id).ok_or(InstanceError::NotNested)?;
+            crumbs.push((gate.get_name(), InstancePath { root: self.root, gates: self.gates[..=depth].to_vec() }));
+            circuit = gate.as_circuit().ok_or(InstanceError::NotNested)?;
+        }
+
+        Ok(crumbs)
+    }
+}
@@ -1,16 +1,55 @@
 use core::fmt;
 use std::error::Error;
 use std::path::Path;
-use std::{cell::RefCell, vec};
-use std::rc::Rc;
-use std::collections::{HashMap, HashSet};
+use std::vec;
+use std::collections::HashMap;
 use mlua::{
-    Function, Lua,
+    Function,
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
 
+use sandbox::{start_call, LuaLimits};
+
 mod ui;
+pub mod arena;
+pub mod scheduler;
+pub mod circuit_file;
+pub mod logic;
+pub mod verilog;
+pub mod netlist;
+pub mod minimize;
+pub mod clock;
+pub mod timing;
+pub mod vcd;
+pub mod sandbox;
+pub mod script;
+pub mod batch;
+pub mod sat;
+pub mod equiv;
+pub mod testbench;
+pub mod parallel;
+pub mod component;
+pub mod reload;
+pub mod instance;
+
+use arena::GateArena;
+use scheduler::Scheduler;
+use timing::EventScheduler;
+use vcd::Recording;
+use batch::BatchNetlist;
+use parallel::{JobControl, ParallelError};
+use circuit_file::{GateLayout, GateSource};
+pub use logic::Logic;
+pub use script::LuaScript;
+use clock::Edge;
+
+// Default for how often a feedback loop may be re-evaluated in one calculate call
+pub const MAX_DELTA_CYCLES: usize = 1000;
+
+// Two-valued gates are evaluated for every assignment of their unknown inputs,
+// above this many unknown bits all outputs are X instead
+pub const MAX_UNKNOWN_INPUTS: usize = 8;
 
 #[derive(Debug, Clone)]
 pub struct TruthTable{
@@ -31,6 +70,70 @@ impl TruthTable {
     pub fn get(&self, inputs: Vec<bool>) -> Vec<bool> {
         self.map.get(&inputs).unwrap().clone()
     }
+
+    // Like get, but reports a missing input combination instead of panicking
+    pub fn try_get(&self, inputs: &[bool]) -> Result<Vec<bool>, MissingTableEntry> {
+        match self.map.get(inputs) {
+            Some(outputs) => Ok(outputs.clone()),
+            None => Err(MissingTableEntry { inputs: inputs.to_vec() }),
+        }
+    }
+
+    // Looks up four-valued inputs, X and Z inputs are tried as both 0 and 1
+    // and every output bit that doesn't depend on them keeps its value
+    pub fn get_values(&self, inputs: &[Logic]) -> Result<Vec<Logic>, MissingTableEntry> {
+        for_each_assignment(inputs, |bits| {
+            Ok(self.try_get(bits)?.into_iter().map(Logic::from).collect())
+        })
+    }
+
+    pub fn get_input_num(&self) -> usize {
+        self.map.keys().next().map_or(0, |inputs| inputs.len())
+    }
+
+    pub fn get_output_num(&self) -> usize {
+        self.map.values().next().map_or(0, |outputs| outputs.len())
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingTableEntry {
+    inputs: Vec<bool>,
+}
+
+impl Error for MissingTableEntry {}
+
+impl fmt::Display for MissingTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Truth table has no entry for inputs {}", vec_bool_to_string(&self.inputs))
+    }
+}
+
+// Calls calc for every way the unknown inputs could be 0 or 1 and merges the results
+fn for_each_assignment<E>(inputs: &[Logic], mut calc: impl FnMut(&[bool]) -> Result<Vec<Logic>, E>) -> Result<Vec<Logic>, E> {
+    let unknown: Vec<usize> = (0..inputs.len()).filter(|&i| !inputs[i].is_known()).collect();
+    let mut bits: Vec<bool> = inputs.iter().map(|v| v.to_bool_lossy()).collect();
+
+    if unknown.len() > MAX_UNKNOWN_INPUTS {
+        // Too many combinations, only the output count is of interest
+        let outputs = calc(&bits)?;
+        return Ok(vec![Logic::X; outputs.len()]);
+    }
+
+    let mut merged: Option<Vec<Logic>> = None;
+    for assignment in 0..1_usize << unknown.len() {
+        for (bit, &index) in unknown.iter().enumerate() {
+            bits[index] = assignment & (1 << bit) != 0;
+        }
+
+        let outputs = calc(&bits)?;
+        merged = Some(match merged {
+            Some(merged) => merged.into_iter().zip(outputs).map(|(a, b)| a.merge(b)).collect(),
+            None => outputs,
+        });
+    }
+
+    Ok(merged.unwrap_or_default())
 }
 
 // Helper function to convert Vec<bool> to a string representation.
@@ -73,6 +176,69 @@ impl<'de> Deserialize<'de> for TruthTable {
     }
 }
 
+// Identifies a gate in diagnostics
+#[derive(Debug, Clone, PartialEq)]
+pub struct GateInfo {
+    pub id: Uuid,
+    pub name: String,
+}
+
+impl fmt::Display for GateInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+fn gate_list_to_string(gates: &[GateInfo]) -> String {
+    gates.iter().map(|g| g.to_string()).collect::<Vec<String>>().join(" -> ")
+}
+
+#[derive(Debug)]
+pub enum LoopError {
+    // Feedback loops made only of stateless gates
+    Combinational { cycles: Vec<Vec<GateInfo>> },
+    // A feedback loop that was still changing after the iteration limit
+    Oscillation { cycle: Vec<GateInfo>, iterations: usize },
+}
+
+impl Error for LoopError {}
+
+impl fmt::Display for LoopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopError::Combinational { cycles } => {
+                write!(f, "Found {} combinational loop(s):", cycles.len())?;
+                for cycle in cycles {
+                    write!(f, " [{}]", gate_list_to_string(cycle))?;
+                }
+                Ok(())
+            },
+            LoopError::Oscillation { cycle, iterations } => {
+                write!(f, "Circuit did not settle after {} delta cycles, oscillating: [{}]", iterations, gate_list_to_string(cycle))
+            },
+        }
+    }
+}
+
+// An error a gate ran into, e.g. a lua script that ran out of time
+#[derive(Debug)]
+pub struct GateError {
+    pub gate: GateInfo,
+    pub source: Box<dyn Error>,
+}
+
+impl Error for GateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl fmt::Display for GateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.gate, self.source)
+    }
+}
+
 #[derive(Debug)]
 pub struct CantConnect {
     err: String,
@@ -86,17 +252,59 @@ impl fmt::Display for CantConnect {
     }
 }
 
+// Checks that both pins exist and carry the same number of bits
+fn check_widths(src: &dyn LogicGate, src_index: usize, dest: &dyn LogicGate, dest_index: usize) -> Result<(), CantConnect> {
+    let src_width = src.get_output_widths().get(src_index).copied()
+        .ok_or_else(|| CantConnect { err: format!("Gate {} has no output {}", src.get_name(), src_index) })?;
+    let dest_width = dest.get_input_widths().get(dest_index).copied()
+        .ok_or_else(|| CantConnect { err: format!("Gate {} has no input {}", dest.get_name(), dest_index) })?;
+
+    if src_width != dest_width {
+        return Err(CantConnect { err: format!(
+            "Output {} of {} is {} bit(s) wide, but input {} of {} is {} bit(s) wide",
+            src_index, src.get_name(), src_width, dest_index, dest.get_name(), dest_width
+        ) });
+    }
+
+    Ok(())
+}
+
+// Bit offset of a pin in the flat inputs/outputs vector
+fn pin_offset(widths: &[usize], index: usize) -> usize {
+    widths.iter().take(index).sum()
+}
+
+// Pin and bit inside that pin for an index into the flat inputs/outputs vector
+fn locate_bit(widths: &[usize], index: usize) -> (usize, usize) {
+    let mut bit = index;
+    for (pin, width) in widths.iter().enumerate() {
+        if bit < *width {
+            return (pin, bit);
+        }
+        bit -= width;
+    }
+    panic!("Bit index {} out of range for {} bits", index, widths.iter().sum::<usize>());
+}
+
 pub struct CircuitBus {
-    mem: bool,
+    mem: Vec<Logic>,
 }
 
 impl CircuitBus {
     pub fn new() -> Self {
+        Self::with_width(1)
+    }
+
+    // A bus carrying several bits on one pin, X until something drives it
+    pub fn with_width(width: usize) -> Self {
         Self {
-            mem: false,
+            mem: vec![Logic::X; width],
         }
     }
 
+    pub fn get_width(&self) -> usize {
+        self.mem.len()
+    }
 }
 
 impl LogicGate for CircuitBus {
@@ -105,19 +313,43 @@ impl LogicGate for CircuitBus {
     }
 
     fn get_inputs(&self) -> Vec<bool> {
-        vec![self.mem]
+        self.mem.iter().map(|v| v.to_bool_lossy()).collect()
     }
 
     fn get_outputs(&self) -> Vec<bool> {
-        vec![self.mem]
+        self.mem.iter().map(|v| v.to_bool_lossy()).collect()
+    }
+
+    fn get_input_values(&self) -> Vec<Logic> {
+        self.mem.clone()
+    }
+
+    fn get_output_values(&self) -> Vec<Logic> {
+        self.mem.clone()
     }
 
-    fn set_input(&mut self, _index: usize, value: bool) {
-        self.mem = value;
+    fn get_input_widths(&self) -> Vec<usize> {
+        vec![self.mem.len()]
     }
 
-    fn set_output(&mut self, _index: usize, value: bool) {
-        self.mem = value;
+    fn get_output_widths(&self) -> Vec<usize> {
+        vec![self.mem.len()]
+    }
+
+    fn set_input(&mut self, index: usize, value: bool) {
+        self.mem[index] = Logic::from(value);
+    }
+
+    fn set_output(&mut self, index: usize, value: bool) {
+        self.mem[index] = Logic::from(value);
+    }
+
+    fn set_input_value(&mut self, index: usize, value: Logic) {
+        self.mem[index] = value;
+    }
+
+    fn set_output_value(&mut self, index: usize, value: Logic) {
+        self.mem[index] = value;
     }
 
     fn calculate(&mut self) -> Result<(), Box<dyn Error>> {
@@ -130,108 +362,367 @@ impl LogicGate for CircuitBus {
 
     fn compile(&mut self) -> Result<TruthTable, CantCompileGate> {
         let mut tt = TruthTable::new();
-        tt.add(vec![false], vec![false]);
-        tt.add(vec![true], vec![true]);
+        for i in 0..2_usize.pow(self.mem.len() as u32) {
+            let binary = format!("{:0width$b}", i, width = self.mem.len());
+            let bits: Vec<bool> = binary.chars().map(|c| c == '1').collect();
+            tt.add(bits.clone(), bits);
+        }
         Ok(tt)
     }
+
+    fn get_source(&self) -> Option<GateSource> {
+        Some(GateSource::Bus { width: self.mem.len() })
+    }
 }
 
 // Structure that holds many gates and can be compiled to a new gate
 pub struct Circuit {
     name: String,
-    pub gates: Vec<(Rc<RefCell<Box<dyn LogicGate>>>, Uuid)>,
+    // Owns every gate, the lists below only hold ids
+    arena: GateArena,
+    gates: Vec<Uuid>,
     connections: Vec<Connection>,
-    circuit_inputs: Vec<Rc<RefCell<Box<dyn LogicGate>>>>,
-    circuit_outputs: Vec<Rc<RefCell<Box<dyn LogicGate>>>>,
+    // In pin order
+    circuit_inputs: Vec<Uuid>,
+    circuit_outputs: Vec<Uuid>,
+    // Evaluation order, rebuilt whenever gates or connections change
+    scheduler: Option<Scheduler>,
+    max_iterations: usize,
+    // Simulation time in ticks, drives every clock in the circuit
+    time: u64,
+    // With delays on, gate outputs change get_delay() ticks after their inputs
+    timed: bool,
+    events: Option<EventScheduler>,
+    // Value changes since start_recording
+    recording: Option<Recording>,
+    // Where the gates were drawn when the circuit was loaded, so nested
+    // circuits can be shown again the way they were saved
+    layouts: HashMap<Uuid, GateLayout>,
 }
 
 impl Circuit {
     pub fn new(name: String) -> Self {
         Self {
             name,
+            arena: GateArena::new(),
             gates: Vec::new(),
             connections: Vec::new(),
             circuit_inputs: Vec::new(),
             circuit_outputs: Vec::new(),
+            scheduler: None,
+            max_iterations: MAX_DELTA_CYCLES,
+            time: 0,
+            timed: false,
+            events: None,
+            recording: None,
+            layouts: HashMap::new(),
+        }
+    }
+
+    pub fn add_input(&mut self, input: Box<dyn LogicGate>, id: Uuid) -> Uuid {
+        self.insert(input, id);
+        self.circuit_inputs.push(id);
+        id
+    }
+
+    pub fn add_output(&mut self, output: Box<dyn LogicGate>, id: Uuid) -> Uuid {
+        self.insert(output, id);
+        self.circuit_outputs.push(id);
+        id
+    }
+
+    pub fn add_gate(&mut self, gate: Box<dyn LogicGate>, id: Uuid) -> Uuid {
+        self.insert(gate, id);
+        self.gates.push(id);
+        id
+    }
+
+    // A gate that already had the id is replaced, its connections go with it
+    fn insert(&mut self, gate: Box<dyn LogicGate>, id: Uuid) {
+        self.remove_gate(&id);
+        self.arena.insert(id, gate);
+        // A nested circuit runs in the same mode as the one it is part of
+        if let Some(circuit) = self.arena.get_mut(&id).and_then(|gate| gate.as_circuit_mut()) {
+            circuit.set_timed(self.timed);
+        }
+        self.topology_changed();
+    }
+
+    // Both schedulers belong to the old topology, pending events are dropped with them
+    fn topology_changed(&mut self) {
+        self.scheduler = None;
+        self.events = None;
+    }
+
+    // How often a feedback loop may be re-evaluated before calculate gives up
+    pub fn set_max_iterations(&mut self, max_iterations: usize) {
+        self.max_iterations = max_iterations;
+    }
+
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+
+    // Switches between settling instantly and the discrete event simulation with gate delays
+    pub fn set_timed(&mut self, timed: bool) {
+        self.timed = timed;
+        self.events = None;
+        for (_, gate) in self.arena.iter_mut() {
+            if let Some(circuit) = gate.as_circuit_mut() {
+                circuit.set_timed(timed);
+            }
+        }
+    }
+
+    pub fn is_timed(&self) -> bool {
+        self.timed
+    }
+
+    // Gate output changes that are scheduled but haven't happened yet, including the ones in nested circuits
+    pub fn pending_events(&self) -> usize {
+        let nested: usize = self.arena.iter().map(|(_, gate)| gate.pending_events()).sum();
+        self.events.as_ref().map_or(0, |events| events.pending_events()) + nested
+    }
+
+    // Ticks until no more changes are scheduled and returns how many ticks that took,
+    // None if the circuit is still switching after max_ticks (e.g. an oscillator)
+    pub fn settle(&mut self, max_ticks: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        self.calculate()?;
+
+        let mut ticks = 0;
+        while self.pending_events() > 0 {
+            if ticks >= max_ticks {
+                return Ok(None);
+            }
+            self.tick()?;
+            ticks += 1;
+        }
+
+        Ok(Some(ticks))
+    }
+
+    // Advances the simulation time by one tick and lets the circuit settle
+    pub fn tick(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_time(self.time + 1);
+        self.calculate()
+    }
+
+    // Fires the OnClock hook of every gate that saw an edge on its clock input.
+    // All edges are collected first, so every gate samples its inputs from before any of them switched.
+    fn fire_clock_edges(&mut self) -> Result<bool, Box<dyn Error>> {
+        let edges: Vec<(Uuid, Edge)> = self.gates.iter()
+            .filter_map(|id| {
+                let edge = self.arena.get_mut(id)?.take_clock_edge();
+                edge.map(|edge| (*id, edge))
+            })
+            .collect();
+
+        for (id, edge) in edges.iter() {
+            let gate = self.arena.get_mut(id).expect("gate with a clock edge");
+            let result = gate.on_clock(*edge);
+            result.map_err(|source| GateError { gate: GateInfo { id: *id, name: gate.get_name() }, source })?;
         }
+
+        Ok(!edges.is_empty())
+    }
+
+    fn evaluate(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.timed {
+            if self.events.is_none() {
+                self.events = Some(EventScheduler::new(self.build_scheduler(), &self.arena));
+            }
+            return self.events.as_mut().unwrap().run_until(self.time, &mut self.arena, &self.connections, self.max_iterations);
+        }
+
+        if self.scheduler.is_none() {
+            self.scheduler = Some(self.build_scheduler());
+        }
+
+        self.scheduler.as_mut().unwrap().run(&mut self.arena, &self.connections, self.max_iterations)?;
+
+        // Edges can cause further edges, e.g. in a ripple counter
+        let mut iterations = 0;
+        while self.fire_clock_edges()? {
+            self.scheduler.as_mut().unwrap().run(&mut self.arena, &self.connections, self.max_iterations)?;
+
+            iterations += 1;
+            if iterations >= self.max_iterations {
+                let cycle = self.gates.iter()
+                    .filter_map(|id| Some((*id, self.arena.get(id)?)))
+                    .filter(|(_, gate)| gate.is_clocked())
+                    .map(|(id, gate)| GateInfo { id, name: gate.get_name() })
+                    .collect();
+                return Err(Box::new(LoopError::Oscillation { cycle, iterations }));
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn add_input(&mut self, input: Rc<RefCell<Box<dyn LogicGate>>>) -> Rc<RefCell<Box<dyn LogicGate>>> {
-        self.circuit_inputs.push(input.clone());
-        input
+    fn build_scheduler(&self) -> Scheduler {
+        Scheduler::new(&self.arena, &self.circuit_inputs, &self.gates, &self.circuit_outputs, &self.connections)
     }
 
-    pub fn add_output(&mut self, output: Rc<RefCell<Box<dyn LogicGate>>>) -> Rc<RefCell<Box<dyn LogicGate>>> {
-        self.circuit_outputs.push(output.clone());
-        output
+    // Lists every feedback loop that isn't broken up by a stateful gate
+    pub fn find_combinational_loops(&self) -> Vec<Vec<GateInfo>> {
+        self.build_scheduler().combinational_loops(&self.arena)
     }
 
-    pub fn add_gate(&mut self, gate: Rc<RefCell<Box<dyn LogicGate>>>, id: Uuid) -> Rc<RefCell<Box<dyn LogicGate>>> {
-        self.gates.push((gate.clone(), id));
-        gate
+    pub fn check_combinational_loops(&self) -> Result<(), LoopError> {
+        let cycles = self.find_combinational_loops();
+        if cycles.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(LoopError::Combinational { cycles })
+        }
     }
 
     pub fn get_gate_num(&self) -> usize {
         self.gates.len()
     }
 
-    pub fn conn_input_to_gate(&mut self, input_num: usize, gate: Rc<RefCell<Box<dyn LogicGate>>>, dest_in_num: usize) -> Result<(), CantConnect> {
+    // Any gate of the circuit, inputs and outputs included
+    pub fn get_gate(&self, id: &Uuid) -> Option<&dyn LogicGate> {
+        self.arena.get(id)
+    }
+
+    pub fn get_gate_mut(&mut self, id: &Uuid) -> Option<&mut dyn LogicGate> {
+        self.arena.get_mut(id)
+    }
+
+    // Follows circuit gates down by their ids, an empty path is this circuit
+    pub fn get_nested(&self, path: &[Uuid]) -> Option<&Circuit> {
+        path.iter().try_fold(self, |circuit, id| circuit.get_gate(id)?.as_circuit())
+    }
+
+    pub fn get_nested_mut(&mut self, path: &[Uuid]) -> Option<&mut Circuit> {
+        path.iter().try_fold(self, |circuit, id| circuit.get_gate_mut(id)?.as_circuit_mut())
+    }
+
+    pub fn get_arena(&self) -> &GateArena {
+        &self.arena
+    }
+
+    pub fn get_input_ids(&self) -> &[Uuid] {
+        &self.circuit_inputs
+    }
+
+    // Gates that are neither inputs nor outputs, in the order they were added
+    pub fn get_gate_ids(&self) -> &[Uuid] {
+        &self.gates
+    }
+
+    pub fn get_output_ids(&self) -> &[Uuid] {
+        &self.circuit_outputs
+    }
+
+    pub fn get_connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    // Removes a gate, input or output together with all of its connections
+    pub fn remove_gate(&mut self, id: &Uuid) {
+        if self.arena.remove(id).is_none() {
+            return;
+        }
+
+        self.connections.retain(|conn| conn.get_input_id() != *id && conn.get_output_id() != *id);
+        self.circuit_inputs.retain(|g| g != id);
+        self.gates.retain(|g| g != id);
+        self.circuit_outputs.retain(|g| g != id);
+        self.layouts.remove(id);
+        self.topology_changed();
+    }
+
+    // Swaps in a new version of a gate under the same id. Connections to pins that
+    // still exist with the same width are kept, the others are dropped and returned.
+    pub fn replace_gate(&mut self, id: &Uuid, gate: Box<dyn LogicGate>) -> Option<Vec<Connection>> {
+        if !self.arena.contains(id) {
+            return None;
+        }
+        self.arena.insert(*id, gate);
+
+        let mut dropped = Vec::new();
+        let connections = std::mem::take(&mut self.connections);
+        for conn in connections {
+            if conn.get_input_id() != *id && conn.get_output_id() != *id {
+                self.connections.push(conn);
+                continue;
+            }
+
+            let (src, src_index) = (conn.get_input_id(), conn.get_input_index());
+            let (dest, dest_index) = (conn.get_output_id(), conn.get_output_index());
+            let rebuilt = Connection::new(&self.arena, src, src_index, dest, dest_index).and_then(|new_conn| {
+                check_widths(self.arena.get(&src).unwrap(), src_index, self.arena.get(&dest).unwrap(), dest_index)?;
+                Ok(new_conn)
+            });
+
+            match rebuilt {
+                Ok(new_conn) => self.connections.push(new_conn),
+                Err(_) => dropped.push(conn),
+            }
+        }
+
+        self.topology_changed();
+        Some(dropped)
+    }
+
+    pub fn conn_input_to_gate(&mut self, input_num: usize, gate: Uuid, dest_in_num: usize) -> Result<(), CantConnect> {
         // Return error if "input_num" is out of range
         if input_num >= self.circuit_inputs.len() {
             return Err(CantConnect { err: "Input number out of range".to_string() });
         }
     
-        // Check if "gate" is in self.gates by comparing Rc pointers
-        let gate_in_circuit = self.gates.iter().any(|g| Rc::ptr_eq(&g.0, &gate));
-    
-        if !gate_in_circuit {
+        if !self.gates.contains(&gate) {
             return Err(CantConnect { err: "Gate not in circuit".to_string() });
         }
     
-        let conn = Connection::new(self.circuit_inputs[input_num].clone(), 0, gate, dest_in_num);
-        self.connections.push(conn);
-
-        Ok(())
+        self.connect(self.circuit_inputs[input_num], 0, gate, dest_in_num)
     }
 
-    pub fn conn_gate_to_output(&mut self, output_num: usize, gate: Rc<RefCell<Box<dyn LogicGate>>>, src_out_num: usize) -> Result<(), CantConnect> {
+    pub fn conn_gate_to_output(&mut self, output_num: usize, gate: Uuid, src_out_num: usize) -> Result<(), CantConnect> {
         // Return error if "output_num" is out of range
         if output_num >= self.circuit_outputs.len() {
             return Err(CantConnect { err: format!("Output number: {} out of range", output_num).to_string() });
         }
     
-        // Check if "gate" is in self.gates by comparing Rc pointers
-        let gate_in_circuit = self.gates.iter().any(|g| Rc::ptr_eq(&g.0, &gate));
-    
-        if !gate_in_circuit {
-            return Err(CantConnect { err: format!("Gate {} not in circuit", gate.borrow().get_name()).to_string() });
+        if !self.gates.contains(&gate) {
+            return Err(CantConnect { err: format!("Gate {} not in circuit", gate).to_string() });
         }
 
         //Check if any gates are already connected to the output
-        for conn in &self.connections {
-            if Rc::ptr_eq(&conn.get_output_gate(), &self.circuit_outputs[output_num]) {
-                return Err(CantConnect { err: format!("Gate {} already connected to output {}", gate.borrow().get_name(), output_num).to_string() });
-            }
+        let output = self.circuit_outputs[output_num];
+        if self.connections.iter().any(|conn| conn.get_output_id() == output) {
+            let name = self.arena.get(&gate).map_or(String::new(), |g| g.get_name());
+            return Err(CantConnect { err: format!("Gate {} already connected to output {}", name, output_num).to_string() });
         }
     
-        let conn = Connection::new(gate, src_out_num, self.circuit_outputs[output_num].clone(), 0);
-        self.connections.push(conn);
-
-        Ok(())
+        self.connect(gate, src_out_num, output, 0)
     }
     
     pub fn compilable(&self) -> bool {
-        for gate in self.gates.iter() {
-            if !gate.0.borrow().compilable() {
-                return false;
-            }
-        }
-        true
+        self.gates.iter().filter_map(|id| self.arena.get(id)).all(|gate| gate.compilable())
     }
 
-    pub fn connect(&mut self, src_gate: Rc<RefCell<Box<dyn LogicGate>>>, src_index: usize, dest_gate: Rc<RefCell<Box<dyn LogicGate>>>, dest_index: usize) {
-        let connection = Connection::new(src_gate, src_index, dest_gate, dest_index);
+    pub fn connect(&mut self, src_gate: Uuid, src_index: usize, dest_gate: Uuid, dest_index: usize) -> Result<(), CantConnect> {
+        let connection = Connection::new(&self.arena, src_gate, src_index, dest_gate, dest_index)?;
+        check_widths(self.arena.get(&src_gate).unwrap(), src_index, self.arena.get(&dest_gate).unwrap(), dest_index)?;
+
         self.connections.push(connection);
+        self.topology_changed();
+
+        Ok(())
+    }
+
+    // Gate inside nested circuits, path holds the id of every circuit on the way and the gate's own id last
+    pub(crate) fn get_nested_gate_mut(&mut self, path: &[Uuid]) -> Option<&mut dyn LogicGate> {
+        let (id, rest) = path.split_first()?;
+        let gate = self.arena.get_mut(id)?;
+        if rest.is_empty() {
+            return Some(gate);
+        }
+        gate.as_circuit_mut()?.get_nested_gate_mut(rest)
     }
 }
 
@@ -241,55 +732,59 @@ impl LogicGate for Circuit {
     }
 
     fn get_inputs(&self) -> Vec<bool> {
-        self.circuit_inputs.iter().map(|gate| gate.borrow().get_inputs()[0]).collect()
+        self.io_gates(&self.circuit_inputs).flat_map(|gate| gate.get_inputs()).collect()
     }
 
     fn get_outputs(&self) -> Vec<bool> {
-        self.circuit_outputs.iter().map(|gate| gate.borrow().get_outputs()[0]).collect()
+        self.io_gates(&self.circuit_outputs).flat_map(|gate| gate.get_outputs()).collect()
     }
 
-    fn set_input(&mut self, index: usize, value: bool) {
-        self.circuit_inputs[index].borrow_mut().set_input(0, value);
+    fn get_input_values(&self) -> Vec<Logic> {
+        self.io_gates(&self.circuit_inputs).flat_map(|gate| gate.get_input_values()).collect()
     }
 
-    fn set_output(&mut self, index: usize, value: bool) {
-        self.circuit_outputs[index].borrow_mut().set_output(0, value);
+    fn get_output_values(&self) -> Vec<Logic> {
+        self.io_gates(&self.circuit_outputs).flat_map(|gate| gate.get_output_values()).collect()
     }
 
-    fn calculate(&mut self) -> Result<(), Box<dyn Error>> {
-        // Set of gates already updated in this cycle to avoid infinite loops
-        let mut updated_gates = HashSet::new();
-
-        // Start with input gates
-        let mut to_update: Vec<Rc<RefCell<Box<dyn LogicGate>>>> = self.circuit_inputs.clone();
-        let mut to_update_next: Vec<Rc<RefCell<Box<dyn LogicGate>>>>;
+    // Every input bus of the circuit is one pin
+    fn get_input_widths(&self) -> Vec<usize> {
+        self.io_gates(&self.circuit_inputs).map(|gate| gate.get_input_num()).collect()
+    }
 
-        while !to_update.is_empty() {
-            to_update_next = Vec::new();
+    fn get_output_widths(&self) -> Vec<usize> {
+        self.io_gates(&self.circuit_outputs).map(|gate| gate.get_output_num()).collect()
+    }
 
-            for gate_rc in to_update.iter() {
-                // Skip if this gate has already been updated
-                if updated_gates.contains(&(Rc::as_ptr(gate_rc) as *const _)) {
-                    continue;
-                }
+    fn set_input(&mut self, index: usize, value: bool) {
+        let (pin, bit) = locate_bit(&self.get_input_widths(), index);
+        let id = self.circuit_inputs[pin];
+        self.arena.get_mut(&id).unwrap().set_input(bit, value);
+    }
 
-                // Calculate based on current inputs, which may internally update the gate's state
-                gate_rc.borrow_mut().calculate()?;
-                updated_gates.insert(Rc::as_ptr(gate_rc) as *const _);
+    fn set_output(&mut self, index: usize, value: bool) {
+        let (pin, bit) = locate_bit(&self.get_output_widths(), index);
+        let id = self.circuit_outputs[pin];
+        self.arena.get_mut(&id).unwrap().set_output(bit, value);
+    }
 
-                // For each connection where this gate is the source, add the destination gate to the update list
-                for conn in self.connections.iter_mut() {
-                    conn.update();
-                    if Rc::ptr_eq(&conn.get_input_gate(), gate_rc) {
-                        to_update_next.push(conn.get_output_gate().clone());
-                    }
-                }
-            }
+    fn set_input_value(&mut self, index: usize, value: Logic) {
+        let (pin, bit) = locate_bit(&self.get_input_widths(), index);
+        let id = self.circuit_inputs[pin];
+        self.arena.get_mut(&id).unwrap().set_input_value(bit, value);
+    }
 
-            to_update = to_update_next;
-        }
+    fn set_output_value(&mut self, index: usize, value: Logic) {
+        let (pin, bit) = locate_bit(&self.get_output_widths(), index);
+        let id = self.circuit_outputs[pin];
+        self.arena.get_mut(&id).unwrap().set_output_value(bit, value);
+    }
 
-        Ok(())
+    fn calculate(&mut self) -> Result<(), Box<dyn Error>> {
+        let result = self.evaluate();
+        // Whatever the circuit got to is recorded, also if it didn't settle
+        self.sample_recording();
+        result
     }
 
     fn compilable(&self) -> bool {
@@ -300,36 +795,86 @@ impl LogicGate for Circuit {
         if !self.compilable() {
             return Err(CantCompileGate);
         }
-        let ins = self.get_inputs();
-        let outs = self.get_outputs();
+
+        // 64 rows per pass, circuits it can't handle (e.g. with loops or tri-state buses) take the slow path
+        if let Ok(netlist) = BatchNetlist::new(self) {
+            return netlist.truth_table(self).map_err(|_| CantCompileGate);
+        }
+
+        // The rows don't depend on each other, so bigger tables are split over every core
+        if self.get_input_num() >= parallel::MIN_PARALLEL_INPUTS {
+            match parallel::truth_table(self, parallel::default_threads(), &JobControl::new()) {
+                // Gates that can't be saved can't be copied to the workers either,
+                // and loops have to see the rows in order like below
+                Err(ParallelError::CantCopy(_)) | Err(ParallelError::Stateful) => {},
+                result => return result.map_err(|_| CantCompileGate),
+            }
+        }
+
+        let ins = self.get_input_values();
+        let outs = self.get_output_values();
 
         let mut table = TruthTable::new();
 
-        for i in 0..2_usize.pow(self.circuit_inputs.len() as u32) {
-            let binary = format!("{:0width$b}", i, width = self.get_inputs().len());
+        for i in 0..2_usize.pow(ins.len() as u32) {
+            let binary = format!("{:0width$b}", i, width = ins.len());
             let inputs: Vec<bool> = binary.chars().map(|c| c == '1').collect();
-            // Change all input gates to the current input
-            for (i, input) in self.circuit_inputs.iter_mut().enumerate() {
-                input.borrow_mut().set_input(0, inputs[i]);
+            // Change all input bits to the current input
+            for (i, value) in inputs.iter().enumerate() {
+                self.set_input(i, *value);
             }
-            if let Err(_) = self.calculate() {
-                return Err(CantCompileGate);
-            }
-            let outputs = self.get_outputs();
-            table.add(inputs, outputs);
+            // Trying every input combination isn't part of the recorded history
+            self.evaluate().map_err(|_| CantCompileGate)?;
+            // X or Z can't be stored in a truth table
+            let outputs: Option<Vec<bool>> = self.get_output_values().iter().map(|v| v.to_bool()).collect();
+            table.add(inputs, outputs.ok_or(CantCompileGate)?);
         }
 
         //Set inputs back to start
-        for (i, input) in self.circuit_inputs.iter_mut().enumerate() {
-            input.borrow_mut().set_input(i, ins[i]);
+        for (i, value) in ins.iter().enumerate() {
+            self.set_input_value(i, *value);
         }
         //Set ouputs back to start
-        for (i, output) in self.circuit_outputs.iter_mut().enumerate() {
-            output.borrow_mut().set_output(i, outs[i]);
+        for (i, value) in outs.iter().enumerate() {
+            self.set_output_value(i, *value);
         }
 
         Ok(table)
     }
+
+    fn get_source(&self) -> Option<GateSource> {
+        let doc = self.to_document().ok()?;
+        Some(GateSource::Circuit { circuit: Box::new(doc) })
+    }
+
+    fn as_circuit(&self) -> Option<&Circuit> {
+        Some(self)
+    }
+
+    fn as_circuit_mut(&mut self) -> Option<&mut Circuit> {
+        Some(self)
+    }
+
+    fn pending_events(&self) -> usize {
+        Circuit::pending_events(self)
+    }
+
+    // Nested circuits run on the time of the circuit they are part of
+    fn set_time(&mut self, time: u64) {
+        self.time = time;
+        for id in self.gates.iter() {
+            if let Some(gate) = self.arena.get_mut(id) {
+                gate.set_time(time);
+            }
+        }
+    }
+}
+
+impl Circuit {
+    // The gates behind a list of input or output ids, in pin order
+    fn io_gates<'a>(&'a self, ids: &'a [Uuid]) -> impl Iterator<Item = &'a dyn LogicGate> {
+        ids.iter().filter_map(|id| self.arena.get(id))
+    }
 }
 
 pub fn compile_gate_to_truth_table(gate: &mut Gate, code: &LuaCode) -> Result<TruthTable, CantCompileGate> {
@@ -339,14 +884,18 @@ pub fn compile_gate_to_truth_table(gate: &mut Gate, code: &LuaCode) -> Result<Tr
         return Err(CantCompileGate);
     }
 
+    // The script is loaded once for all rows, one that fails or hits the sandbox limits can't be compiled
+    let calc = CalcMode::Lua(LuaScript::new(code.clone()).map_err(|_| CantCompileGate)?);
+
     // Iterate over all possible input combinations
     for i in 0..2_usize.pow(gate.inputs.len() as u32) {
         let binary = format!("{:0width$b}", i, width = gate.inputs.len());
         let inputs: Vec<bool> = binary.chars().map(|c| c == '1').collect();
-        gate.inputs = inputs.clone();  // Make sure the gate's inputs are updated for each iteration
-        gate.calculate(&CalcMode::Lua(code.clone(), Lua::new())).unwrap();
-        let outputs = gate.outputs.clone();
-        table.add(inputs, outputs);
+        gate.inputs = inputs.iter().map(|&b| Logic::from(b)).collect();  // Make sure the gate's inputs are updated for each iteration
+        gate.calculate(&calc).map_err(|_| CantCompileGate)?;
+        // A gate that outputs X or Z for known inputs (e.g. a tri-state buffer) has no truth table
+        let outputs: Option<Vec<bool>> = gate.outputs.iter().map(|v| v.to_bool()).collect();
+        table.add(inputs, outputs.ok_or(CantCompileGate)?);
     }
 
     Ok(table)
@@ -354,33 +903,38 @@ pub fn compile_gate_to_truth_table(gate: &mut Gate, code: &LuaCode) -> Result<Tr
 
 pub struct Gate {
     name: String,
-    inputs: Vec<bool>,
-    outputs: Vec<bool>,
+    inputs: Vec<Logic>,
+    outputs: Vec<Logic>,
     memory: Vec<bool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LuaCode(pub String);
 pub enum CalcMode {
-    Lua(LuaCode, Lua),
+    Lua(LuaScript),
     TruthTable(TruthTable),
 }
 
 impl Gate {
     pub fn new(name: String, inputs: Vec<bool>, outputs: Vec<bool>) -> Self {
+        Self::with_buffer(name, inputs, outputs, vec![])
+    }
+
+    pub fn with_buffer(name: String, inputs: Vec<bool>, outputs: Vec<bool>, memory: Vec<bool>) -> Self {
         Self {
             name,
-            inputs,
-            outputs,
-            memory: vec![],
+            inputs: inputs.into_iter().map(Logic::from).collect(),
+            outputs: outputs.into_iter().map(Logic::from).collect(),
+            memory,
         }
     }
 
-    pub fn with_buffer(name: String, inputs: Vec<bool>, outputs: Vec<bool>, memory: Vec<bool>) -> Self {
+    // A gate whose inputs and outputs are all X until something drives them
+    pub fn undriven(name: String, input_num: usize, output_num: usize, memory: Vec<bool>) -> Self {
         Self {
             name,
-            inputs,
-            outputs,
+            inputs: vec![Logic::X; input_num],
+            outputs: vec![Logic::X; output_num],
             memory,
         }
     }
@@ -390,18 +944,34 @@ impl Gate {
     }
 
     pub fn get_inputs(&self) -> Vec<bool> {
-        self.inputs.clone().to_vec()
+        self.inputs.iter().map(|v| v.to_bool_lossy()).collect()
     }
 
     pub fn get_outputs(&self) -> Vec<bool> {
-        self.outputs.clone().to_vec()
+        self.outputs.iter().map(|v| v.to_bool_lossy()).collect()
+    }
+
+    pub fn get_input_values(&self) -> Vec<Logic> {
+        self.inputs.clone()
+    }
+
+    pub fn get_output_values(&self) -> Vec<Logic> {
+        self.outputs.clone()
     }
 
     pub fn set_input(&mut self, index: usize, value: bool) {
-        self.inputs[index] = value;
+        self.inputs[index] = Logic::from(value);
     }
 
     pub fn set_output(&mut self, index: usize, value: bool) {
+        self.outputs[index] = Logic::from(value);
+    }
+
+    pub fn set_input_value(&mut self, index: usize, value: Logic) {
+        self.inputs[index] = value;
+    }
+
+    pub fn set_output_value(&mut self, index: usize, value: Logic) {
         self.outputs[index] = value;
     }
 
@@ -423,35 +993,86 @@ impl Gate {
 
     pub fn calculate(&mut self, calc: &CalcMode) -> mlua::Result<()> {
         match calc {
-            CalcMode::Lua(lua_code, lua) => {
-                let globals = lua.globals();
-                
-                // Lade den lua code
-                lua.load(&lua_code.0).exec()?;
-
-                // Check for needed variables
-                globals.get::<_, u8>("NUM_OF_INS").expect("NUM_OF_INS is needed in every gate");
-                globals.get::<_, u8>("NUM_OF_OUTS").expect("NUM_OF_OUTS is needed in every gate");
-
-                if self.memory.len() != 0 {
-                    globals.set("memory", self.memory.clone())?;
+            CalcMode::Lua(script) => {
+                let globals = script.get_lua().globals();
+                start_call(script.get_lua())?;
+
+                // Purely edge triggered components only have OnClock and keep their outputs in between
+                let Some(calculate) = script.get_calculate()? else {
+                    return Ok(());
+                };
+
+                if script.is_four_valued() {
+                    let inputs: Vec<String> = self.inputs.iter().map(|v| v.to_string()).collect();
+                    self.outputs = self.call_with_memory(&globals, &calculate, inputs)?;
                 }
-
-                let calculate: Function = globals.get("Calculate")?;
-                let output = calculate.call::<_, Vec<bool>>(self.inputs.clone())?;
-                self.outputs = output;
-
-                if self.memory.len() != 0 {
-                    self.memory = globals.get::<_, Vec<bool>>("memory")?;
+                else if self.inputs.iter().all(|v| v.is_known()) {
+                    let inputs = self.get_inputs();
+                    self.outputs = self.call_with_memory(&globals, &calculate, inputs)?;
+                }
+                else if self.memory.is_empty() {
+                    let inputs = self.inputs.clone();
+                    self.outputs = for_each_assignment(&inputs, |bits| self.call_with_memory(&globals, &calculate, bits.to_vec()))?;
+                }
+                else {
+                    // Unknown inputs would leave the memory in an unknown state, keep it and report X
+                    self.outputs = vec![Logic::X; self.outputs.len()];
                 }
             },
-            CalcMode::TruthTable(_table) => {
-                todo!("TruthTable")
+            CalcMode::TruthTable(table) => {
+                self.outputs = table.get_values(&self.inputs).map_err(mlua::Error::external)?;
             },
         }
 
         Ok(())
     }
+
+    // Runs the OnClock hook of the lua code, returning nil from it keeps the outputs as they are
+    pub fn on_clock(&mut self, calc: &CalcMode, edge: Edge) -> mlua::Result<()> {
+        let CalcMode::Lua(script) = calc else {
+            return Ok(());
+        };
+
+        let globals = script.get_lua().globals();
+        start_call(script.get_lua())?;
+
+        let Some(on_clock) = script.get_on_clock()? else {
+            return Ok(());
+        };
+
+        let outputs: Option<Vec<Logic>> = if script.is_four_valued() {
+            let inputs: Vec<String> = self.inputs.iter().map(|v| v.to_string()).collect();
+            self.call_with_memory(&globals, &on_clock, (inputs, edge.as_str()))?
+        }
+        else if self.inputs.iter().all(|v| v.is_known()) {
+            let inputs = self.get_inputs();
+            self.call_with_memory(&globals, &on_clock, (inputs, edge.as_str()))?
+        }
+        else {
+            // Same as in calculate, an unknown input can't be stored
+            Some(vec![Logic::X; self.outputs.len()])
+        };
+
+        if let Some(outputs) = outputs {
+            self.outputs = outputs;
+        }
+
+        Ok(())
+    }
+
+    fn call_with_memory<'lua, R: mlua::FromLuaMulti<'lua>>(&mut self, globals: &mlua::Table<'lua>, func: &Function<'lua>, args: impl mlua::IntoLuaMulti<'lua>) -> mlua::Result<R> {
+        if !self.memory.is_empty() {
+            globals.set("memory", self.memory.clone())?;
+        }
+
+        let outputs = func.call::<_, R>(args)?;
+
+        if !self.memory.is_empty() {
+            self.memory = globals.get::<_, Vec<bool>>("memory")?;
+        }
+
+        Ok(outputs)
+    }
 }
 
 #[derive(Debug)]
@@ -487,45 +1108,146 @@ pub fn load_truth_table(path: &str) -> Result<TruthTable, Box<dyn Error>> {
 pub struct BasicGate {
     gate: Gate,
     calc_mode: CalcMode,
+    // File the lua code was read from, if any
+    lua_path: Option<Box<Path>>,
+    // Bits per pin, the gate's inputs and outputs hold all bits back to back
+    input_widths: Vec<usize>,
+    output_widths: Vec<usize>,
+    // Input bit OnClock listens to and the value it had when last checked
+    clock_input: Option<usize>,
+    last_clock: Logic,
+    // DELAY of the component and the per instance override
+    delay: u64,
+    delay_override: Option<u64>,
 }
 
 impl BasicGate {
     pub fn from_gate(gate: Gate, calc_mode: CalcMode) -> Self {
+        let input_widths = vec![1; gate.inputs.len()];
+        let output_widths = vec![1; gate.outputs.len()];
+
         Self {
             gate,
             calc_mode,
+            lua_path: None,
+            input_widths,
+            output_widths,
+            clock_input: None,
+            last_clock: Logic::X,
+            delay: 0,
+            delay_override: None,
         }
     }
 
     pub fn from_lua(name: String, code: Box<Path>) -> mlua::Result<Self> {
         let code_contents = std::fs::read(&code)?;
-        let lua = Lua::new();
+
+        let mut gate = Self::from_lua_code(name, LuaCode(String::from_utf8(code_contents).unwrap()))?;
+        gate.lua_path = Some(code);
+
+        Ok(gate)
+    }
+
+    pub fn from_lua_code(name: String, code: LuaCode) -> mlua::Result<Self> {
+        Self::from_lua_code_with_limits(name, code, LuaLimits::default())
+    }
+
+    // Runs the component script in a sandbox with the given limits instead of the default ones
+    pub fn from_lua_code_with_limits(name: String, code: LuaCode, limits: LuaLimits) -> mlua::Result<Self> {
+        // The script runs once here, its functions are kept for every later call
+        let script = LuaScript::with_limits(code, limits)?;
     
         // Limit the scope of the globals borrow
-        let (input_num, output_num, memory_len) = {
-            let globals = lua.globals();
-            lua.load(&String::from_utf8(code_contents.clone()).unwrap()).exec()?;
+        let (input_widths, output_widths, memory_len, clock_input, delay) = {
+            let globals = script.get_lua().globals();
     
             let input_num = globals.get::<_, u8>("NUM_OF_INS")?;
             let output_num = globals.get::<_, u8>("NUM_OF_OUTS")?;
             let memory_len = globals.get::<_, u8>("MEMORY_SIZE")?;
+
+            // Pins are one bit wide unless the component says otherwise
+            let input_widths = read_widths(&globals, "INPUT_WIDTHS", input_num as usize)?;
+            let output_widths = read_widths(&globals, "OUTPUT_WIDTHS", output_num as usize)?;
+
+            let clock_input = read_clock_input(&globals, input_widths.iter().sum())?;
+            let delay = globals.get::<_, Option<u64>>("DELAY")?.unwrap_or(0);
     
-            (input_num, output_num, memory_len)
+            (input_widths, output_widths, memory_len, clock_input, delay)
         };
     
-        // Create a gate with the given name, input and output numbers
-        let gate = Gate::with_buffer(name, vec![false; input_num as usize], vec![false; output_num as usize], vec![false; memory_len as usize]);
+        // Create a gate with one input and output per bit
+        let input_bits = input_widths.iter().sum();
+        let output_bits = output_widths.iter().sum();
+        let gate = Gate::undriven(name, input_bits, output_bits, vec![false; memory_len as usize]);
     
-        let calc_mode = CalcMode::Lua(LuaCode(String::from_utf8(code_contents).unwrap()), lua);
+        let calc_mode = CalcMode::Lua(script);
         Ok(Self {
             gate,
             calc_mode,
+            lua_path: None,
+            input_widths,
+            output_widths,
+            clock_input,
+            last_clock: Logic::X,
+            delay,
+            delay_override: None,
         })
     }
+
+    // Builds a gate that runs purely from a truth table, no lua vm involved
+    pub fn from_truth_table(name: String, table: TruthTable) -> Self {
+        let gate = Gate::undriven(name, table.get_input_num(), table.get_output_num(), vec![]);
+
+        Self {
+            input_widths: vec![1; table.get_input_num()],
+            output_widths: vec![1; table.get_output_num()],
+            gate,
+            calc_mode: CalcMode::TruthTable(table),
+            lua_path: None,
+            clock_input: None,
+            last_clock: Logic::X,
+            delay: 0,
+            delay_override: None,
+        }
+    }
+
+    // Loads a truth table saved with save_truth_table, e.g. ./comps/and.json
+    pub fn from_json(name: String, path: &str) -> Result<Self, Box<dyn Error>> {
+        let table = load_truth_table(path)?;
+        Ok(Self::from_truth_table(name, table))
+    }
 }
 
+// CLOCK_INPUT is the 1 based input bit OnClock listens to, like the inputs table in lua
+fn read_clock_input(globals: &mlua::Table, input_bits: usize) -> mlua::Result<Option<usize>> {
+    let has_calculate = globals.get::<_, Option<Function>>("Calculate")?.is_some();
+    let has_on_clock = globals.get::<_, Option<Function>>("OnClock")?.is_some();
+
+    match globals.get::<_, Option<usize>>("CLOCK_INPUT")? {
+        Some(bit) if bit == 0 || bit > input_bits => Err(mlua::Error::RuntimeError(
+            format!("CLOCK_INPUT is {}, but the gate only has {} input bits", bit, input_bits)
+        )),
+        Some(_) if !has_on_clock => Err(mlua::Error::RuntimeError("CLOCK_INPUT is set, but there is no OnClock function".to_string())),
+        Some(bit) => Ok(Some(bit - 1)),
+        None if has_on_clock => Err(mlua::Error::RuntimeError("OnClock needs CLOCK_INPUT to know which input is the clock".to_string())),
+        None if !has_calculate => Err(mlua::Error::RuntimeError("Calculate is needed in every gate without OnClock".to_string())),
+        None => Ok(None),
+    }
+}
 
-pub trait LogicGate {
+fn read_widths(globals: &mlua::Table, key: &str, pin_num: usize) -> mlua::Result<Vec<usize>> {
+    match globals.get::<_, Option<Vec<usize>>>(key)? {
+        Some(widths) if widths.len() != pin_num => Err(mlua::Error::RuntimeError(
+            format!("{} has {} entries, but the gate has {} pins", key, widths.len(), pin_num)
+        )),
+        Some(widths) => Ok(widths),
+        None => Ok(vec![1; pin_num]),
+    }
+}
+
+
+// Gates are Send so a whole circuit can be handed to another thread
+pub trait LogicGate: Send {
     fn get_name(&self) -> String;
     fn get_inputs(&self) -> Vec<bool>;
     fn get_outputs(&self) -> Vec<bool>;
@@ -535,6 +1257,26 @@ pub trait LogicGate {
     fn get_output_num(&self) -> usize {
         self.get_outputs().len()
     }
+    // Four-valued view of the pins, the bool versions read X and Z as 0
+    fn get_input_values(&self) -> Vec<Logic> {
+        self.get_inputs().into_iter().map(Logic::from).collect()
+    }
+    fn get_output_values(&self) -> Vec<Logic> {
+        self.get_outputs().into_iter().map(Logic::from).collect()
+    }
+    fn set_input_value(&mut self, index: usize, value: Logic) {
+        self.set_input(index, value.to_bool_lossy());
+    }
+    fn set_output_value(&mut self, index: usize, value: Logic) {
+        self.set_output(index, value.to_bool_lossy());
+    }
+    // Bits per input pin, get_inputs holds the bits of all pins back to back
+    fn get_input_widths(&self) -> Vec<usize> {
+        vec![1; self.get_input_num()]
+    }
+    fn get_output_widths(&self) -> Vec<usize> {
+        vec![1; self.get_output_num()]
+    }
     fn set_input(&mut self, index: usize, value: bool);
     fn set_output(&mut self, index: usize, value: bool);
     fn calculate(&mut self) -> Result<(), Box<dyn Error>>;
@@ -544,9 +1286,45 @@ pub trait LogicGate {
     fn get_memory(&self) -> Option<Vec<bool>> {
         None
     }
-    fn get_lua_env(&mut self) -> Option<(&mut Lua, &LuaCode)> {
+    fn get_lua_script(&self) -> Option<&LuaScript> {
+        None
+    }
+    // Describes how to rebuild this gate, used when saving circuits
+    fn get_source(&self) -> Option<GateSource> {
+        None
+    }
+    fn as_circuit(&self) -> Option<&Circuit> {
+        None
+    }
+    fn as_circuit_mut(&mut self) -> Option<&mut Circuit> {
         None
     }
+    // Called by the circuit whenever the simulation time changes, e.g. for clock sources
+    fn set_time(&mut self, _time: u64) {}
+    // Edge triggered gates have a clock input and react to its edges in on_clock
+    fn is_clocked(&self) -> bool {
+        false
+    }
+    // Edge on the clock input since the last call, if any
+    fn take_clock_edge(&mut self) -> Option<Edge> {
+        None
+    }
+    fn on_clock(&mut self, _edge: Edge) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    // Ticks between an input change and the matching output change in a timed circuit
+    fn get_delay(&self) -> u64 {
+        0
+    }
+    // Overrides the delay of the component for this instance, None goes back to the default
+    fn set_delay(&mut self, _delay: Option<u64>) {}
+    fn get_delay_override(&self) -> Option<u64> {
+        None
+    }
+    // Output changes scheduled inside the gate, a nested timed circuit keeps its own events
+    fn pending_events(&self) -> usize {
+        0
+    }
 }
 
 impl LogicGate for BasicGate {
@@ -562,6 +1340,22 @@ impl LogicGate for BasicGate {
         self.gate.get_outputs()
     }
 
+    fn get_input_widths(&self) -> Vec<usize> {
+        self.input_widths.clone()
+    }
+
+    fn get_output_widths(&self) -> Vec<usize> {
+        self.output_widths.clone()
+    }
+
+    fn get_input_values(&self) -> Vec<Logic> {
+        self.gate.get_input_values()
+    }
+
+    fn get_output_values(&self) -> Vec<Logic> {
+        self.gate.get_output_values()
+    }
+
     fn set_input(&mut self, index: usize, value: bool) {
         self.gate.set_input(index, value)
     }
@@ -570,20 +1364,28 @@ impl LogicGate for BasicGate {
         self.gate.set_output(index, value)
     }
 
+    fn set_input_value(&mut self, index: usize, value: Logic) {
+        self.gate.set_input_value(index, value)
+    }
+
+    fn set_output_value(&mut self, index: usize, value: Logic) {
+        self.gate.set_output_value(index, value)
+    }
+
     fn calculate(&mut self) -> Result<(), Box<dyn Error>> {
         self.gate.calculate(&self.calc_mode)?;
         Ok(())
     }
 
     fn compilable(&self) -> bool {
-        !self.gate.is_stateful()
+        !self.gate.is_stateful() && self.clock_input.is_none()
     }
 
     fn compile(&mut self) -> Result<TruthTable, CantCompileGate> {
         if self.compilable() {
             match &self.calc_mode {
-                CalcMode::Lua(code, _lua) => {
-                    compile_gate_to_truth_table(&mut self.gate, &code)
+                CalcMode::Lua(script) => {
+                    compile_gate_to_truth_table(&mut self.gate, script.get_code())
                 },
                 CalcMode::TruthTable(tt) => {
                     Ok(tt.clone())
@@ -604,47 +1406,102 @@ impl LogicGate for BasicGate {
         Some(self.gate.get_memory())
     }
 
-    fn get_lua_env(&mut self) -> Option<(&mut Lua, &LuaCode)> {
-        match &mut self.calc_mode {
-            CalcMode::Lua(code, lua) => {
-                Some((lua, code))
+    fn get_lua_script(&self) -> Option<&LuaScript> {
+        match &self.calc_mode {
+            CalcMode::Lua(script) => {
+                Some(script)
             },
             CalcMode::TruthTable(_tt) => {
                 None
             }
         }
     }
+
+    fn get_source(&self) -> Option<GateSource> {
+        match (&self.calc_mode, &self.lua_path) {
+            (CalcMode::Lua(_script), Some(path)) => Some(GateSource::Lua { path: path.to_path_buf() }),
+            (CalcMode::Lua(script), None) => Some(GateSource::LuaCode { code: script.get_code().0.clone() }),
+            (CalcMode::TruthTable(table), _) => Some(GateSource::TruthTable { table: table.clone() }),
+        }
+    }
+
+    fn is_clocked(&self) -> bool {
+        self.clock_input.is_some()
+    }
+
+    fn take_clock_edge(&mut self) -> Option<Edge> {
+        let clock = self.gate.inputs[self.clock_input?];
+        let edge = Edge::between(self.last_clock, clock);
+        self.last_clock = clock;
+        edge
+    }
+
+    fn on_clock(&mut self, edge: Edge) -> Result<(), Box<dyn Error>> {
+        self.gate.on_clock(&self.calc_mode, edge)?;
+        Ok(())
+    }
+
+    fn get_delay(&self) -> u64 {
+        self.delay_override.unwrap_or(self.delay)
+    }
+
+    fn set_delay(&mut self, delay: Option<u64>) {
+        self.delay_override = delay;
+    }
+
+    fn get_delay_override(&self) -> Option<u64> {
+        self.delay_override
+    }
 }
 
 
+// A wire between an output pin and an input pin, both gates are referred to by id
+#[derive(Debug, Clone, PartialEq)]
 pub struct Connection {
-    src_gate: Rc<RefCell<Box<dyn LogicGate>>>,
+    src_gate: Uuid,
     src_index: usize,
-    dest_gate: Rc<RefCell<Box<dyn LogicGate>>>,
+    dest_gate: Uuid,
     dest_index: usize,
+    // Where the pins start in the flat bit vectors and how many bits they carry
+    src_offset: usize,
+    dest_offset: usize,
+    width: usize,
 }
 
 impl Connection {
     pub fn new(
-        src_gate: Rc<RefCell<Box<dyn LogicGate>>>,
+        gates: &GateArena,
+        src_gate: Uuid,
         src_index: usize,
-        dest_gate: Rc<RefCell<Box<dyn LogicGate>>>,
+        dest_gate: Uuid,
         dest_index: usize,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, CantConnect> {
+        let not_found = |id: Uuid| CantConnect { err: format!("Gate {} not in circuit", id) };
+        let src_widths = gates.get(&src_gate).ok_or_else(|| not_found(src_gate))?.get_output_widths();
+        let dest_widths = gates.get(&dest_gate).ok_or_else(|| not_found(dest_gate))?.get_input_widths();
+
+        let src_offset = pin_offset(&src_widths, src_index);
+        let dest_offset = pin_offset(&dest_widths, dest_index);
+        let width = src_widths.get(src_index).copied().unwrap_or(1)
+            .min(dest_widths.get(dest_index).copied().unwrap_or(1));
+
+        Ok(Self {
             src_gate,
             src_index, 
             dest_gate,
             dest_index,
-        }
+            src_offset,
+            dest_offset,
+            width,
+        })
     }
 
-    pub fn get_input_gate(&self) -> Rc<RefCell<Box<dyn LogicGate>>> {
-        self.src_gate.clone()
+    pub fn get_input_id(&self) -> Uuid {
+        self.src_gate
     }
 
-    pub fn get_output_gate(&self) -> Rc<RefCell<Box<dyn LogicGate>>> {
-        self.dest_gate.clone()
+    pub fn get_output_id(&self) -> Uuid {
+        self.dest_gate
     }
 
     pub fn get_input_index(&self) -> usize {
@@ -655,9 +1512,44 @@ impl Connection {
         self.dest_index
     }
 
-    pub fn update(&mut self) {
-        let input = self.src_gate.borrow().get_outputs()[self.src_index];
-        self.dest_gate.borrow_mut().set_input(self.dest_index, input);
+    // Number of bits carried by this connection
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    // Current value of the bits this connection carries, Z if the source is gone
+    pub fn read(&self, gates: &GateArena) -> Vec<Logic> {
+        match gates.get(&self.src_gate) {
+            Some(src) => self.read_from(src),
+            None => vec![Logic::Z; self.width],
+        }
+    }
+
+    // First output bit of the source gate this connection reads
+    pub fn get_src_offset(&self) -> usize {
+        self.src_offset
+    }
+
+    // First input bit of the destination gate this connection drives
+    pub fn get_dest_offset(&self) -> usize {
+        self.dest_offset
+    }
+
+    pub fn update(&self, gates: &mut GateArena) {
+        let bits = self.read(gates);
+        if let Some(dest) = gates.get_mut(&self.dest_gate) {
+            self.write_to(dest, bits);
+        }
+    }
+
+    pub(crate) fn read_from(&self, src: &dyn LogicGate) -> Vec<Logic> {
+        src.get_output_values()[self.src_offset..self.src_offset + self.width].to_vec()
+    }
+
+    pub(crate) fn write_to(&self, dest: &mut dyn LogicGate, bits: Vec<Logic>) {
+        for (i, bit) in bits.into_iter().enumerate() {
+            dest.set_input_value(self.dest_offset + i, bit);
+        }
     }
 }
 
@@ -0,0 +1,129 @@
+use core::fmt;
+
+use mlua::{FromLua, Lua, Value};
+
+// Value on a single wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Logic {
+    Zero,
+    One,
+    // Unknown, e.g. an undriven input or two drivers fighting
+    #[default]
+    X,
+    // High impedance, nothing is driving the wire
+    Z,
+}
+
+impl Logic {
+    pub fn is_known(&self) -> bool {
+        matches!(self, Logic::Zero | Logic::One)
+    }
+
+    pub fn to_bool(&self) -> Option<bool> {
+        match self {
+            Logic::Zero => Some(false),
+            Logic::One => Some(true),
+            Logic::X | Logic::Z => None,
+        }
+    }
+
+    // For the plain bool api, everything that isn't a clean 1 reads as 0
+    pub fn to_bool_lossy(&self) -> bool {
+        *self == Logic::One
+    }
+
+    // Combines two drivers of the same wire, Z gives way to everything else
+    pub fn resolve(self, other: Logic) -> Logic {
+        match (self, other) {
+            (Logic::Z, v) | (v, Logic::Z) => v,
+            (a, b) if a == b => a,
+            _ => Logic::X,
+        }
+    }
+
+    // Combines the results of several possible input assignments, anything that differs is unknown
+    pub fn merge(self, other: Logic) -> Logic {
+        if self == other { self } else { Logic::X }
+    }
+
+    pub fn to_char(&self) -> char {
+        match self {
+            Logic::Zero => '0',
+            Logic::One => '1',
+            Logic::X => 'X',
+            Logic::Z => 'Z',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Logic> {
+        match c {
+            '0' => Some(Logic::Zero),
+            '1' => Some(Logic::One),
+            'x' | 'X' => Some(Logic::X),
+            'z' | 'Z' => Some(Logic::Z),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Logic {
+    fn from(value: bool) -> Self {
+        if value { Logic::One } else { Logic::Zero }
+    }
+}
+
+impl fmt::Display for Logic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
+
+// Lua components may return booleans, 0/1 or one of the strings "0", "1", "X" and "Z"
+impl<'lua> FromLua<'lua> for Logic {
+    fn from_lua(value: Value<'lua>, _lua: &'lua Lua) -> mlua::Result<Self> {
+        let conversion_error = |value: &Value, message: &str| mlua::Error::FromLuaConversionError {
+            from: value.type_name(),
+            to: "Logic",
+            message: Some(message.to_string()),
+        };
+
+        match &value {
+            Value::Boolean(b) => Ok(Logic::from(*b)),
+            Value::Integer(0) => Ok(Logic::Zero),
+            Value::Integer(1) => Ok(Logic::One),
+            Value::String(s) => {
+                let s = s.to_str()?;
+                let mut chars = s.chars();
+                match (chars.next().and_then(Logic::from_char), chars.next()) {
+                    (Some(logic), None) => Ok(logic),
+                    _ => Err(conversion_error(&value, "expected \"0\", \"1\", \"X\" or \"Z\"")),
+                }
+            },
+            _ => Err(conversion_error(&value, "expected a boolean, 0, 1 or \"0\", \"1\", \"X\", \"Z\"")),
+        }
+    }
+}
+
+pub fn logic_vec_to_string(values: &[Logic]) -> String {
+    values.iter().map(|v| v.to_char()).collect()
+}
+
+// Bus value in hex, most significant digit first. A digit with a Z in every bit
+// shows as Z, any other digit with an unknown bit as X.
+pub fn logic_vec_to_hex(values: &[Logic]) -> String {
+    values.chunks(4).rev().map(|nibble| {
+        if nibble.iter().all(|v| *v == Logic::Z) {
+            return 'Z';
+        }
+
+        let mut digit = 0;
+        for (i, v) in nibble.iter().enumerate() {
+            match v.to_bool() {
+                Some(true) => digit |= 1 << i,
+                Some(false) => {},
+                None => return 'X',
+            }
+        }
+        std::char::from_digit(digit, 16).unwrap().to_ascii_uppercase()
+    }).collect()
+}
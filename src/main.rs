@@ -15,6 +15,14 @@ pub use new_logic_gates::LuaCode;
 pub use new_logic_gates::Circuit;
 pub use new_logic_gates::TruthTable;
 pub use new_logic_gates::CantCompileGate;
+pub use new_logic_gates::circuit_file;
+pub use new_logic_gates::logic;
+pub use new_logic_gates::vcd;
+pub use new_logic_gates::sandbox;
+pub use new_logic_gates::component;
+pub use new_logic_gates::reload;
+pub use new_logic_gates::instance;
+pub use new_logic_gates::Logic;
 
 
 #[cfg(not(target_env = "msvc"))]
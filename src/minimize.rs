@@ -0,0 +1,478 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{BasicGate, CantCompileGate, Circuit, CircuitBus, LogicGate, TruthTable};
+
+// Above this many inputs the prime implicants are no longer enumerated and
+// the espresso style heuristic is used instead
+pub const QM_MAX_INPUTS: usize = 12;
+
+// The exact cover search gives up and goes greedy above this many candidate primes
+const EXACT_COVER_MAX_PRIMES: usize = 24;
+
+// A product term with one entry per input: Some(true) is x, Some(false) is ~x and None means the input doesn't matter
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cube(pub Vec<Option<bool>>);
+
+impl Cube {
+    fn from_bits(value: u64, mask: u64, input_num: usize) -> Self {
+        Cube((0..input_num).map(|i| {
+            if mask & (1 << i) != 0 { None } else { Some(value & (1 << i) != 0) }
+        }).collect())
+    }
+
+    pub fn literal_num(&self) -> usize {
+        self.0.iter().filter(|l| l.is_some()).count()
+    }
+
+    pub fn contains(&self, inputs: &[bool]) -> bool {
+        self.0.iter().zip(inputs).all(|(l, b)| l.is_none_or(|l| l == *b))
+    }
+}
+
+// OR of AND terms
+#[derive(Debug, Clone, PartialEq)]
+pub struct SumOfProducts {
+    pub input_num: usize,
+    pub cubes: Vec<Cube>,
+}
+
+// AND of OR clauses, in a clause Some(true) is x and Some(false) is ~x
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProductOfSums {
+    pub input_num: usize,
+    pub clauses: Vec<Cube>,
+}
+
+fn default_names(input_num: usize) -> Vec<String> {
+    (0..input_num).map(|i| format!("in_{}", i)).collect()
+}
+
+fn literal(name: &str, value: bool) -> String {
+    if value { name.to_string() } else { format!("~{}", name) }
+}
+
+fn join_literals(cube: &Cube, names: &[String], op: &str) -> (String, usize) {
+    let literals: Vec<String> = cube.0.iter().zip(names)
+        .filter_map(|(l, name)| l.map(|l| literal(name, l)))
+        .collect();
+    (literals.join(op), literals.len())
+}
+
+impl SumOfProducts {
+    pub fn eval(&self, inputs: &[bool]) -> bool {
+        self.cubes.iter().any(|cube| cube.contains(inputs))
+    }
+
+    pub fn literal_num(&self) -> usize {
+        self.cubes.iter().map(|c| c.literal_num()).sum()
+    }
+
+    // e.g. "in_0 & ~in_1 | in_2"
+    pub fn to_string_with_names(&self, names: &[String]) -> String {
+        if self.cubes.is_empty() {
+            return "0".to_string();
+        }
+        if self.cubes.iter().any(|c| c.literal_num() == 0) {
+            return "1".to_string();
+        }
+
+        let terms: Vec<String> = self.cubes.iter().map(|cube| {
+            let (term, len) = join_literals(cube, names, " & ");
+            if len > 1 && self.cubes.len() > 1 { format!("({})", term) } else { term }
+        }).collect();
+        terms.join(" | ")
+    }
+}
+
+impl fmt::Display for SumOfProducts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with_names(&default_names(self.input_num)))
+    }
+}
+
+impl ProductOfSums {
+    pub fn eval(&self, inputs: &[bool]) -> bool {
+        self.clauses.iter().all(|clause| {
+            clause.0.iter().zip(inputs).any(|(l, b)| *l == Some(*b))
+        })
+    }
+
+    // e.g. "(in_0 | ~in_1) & in_2"
+    pub fn to_string_with_names(&self, names: &[String]) -> String {
+        if self.clauses.is_empty() {
+            return "1".to_string();
+        }
+        if self.clauses.iter().any(|c| c.literal_num() == 0) {
+            return "0".to_string();
+        }
+
+        let clauses: Vec<String> = self.clauses.iter().map(|clause| {
+            let (sum, len) = join_literals(clause, names, " | ");
+            if len > 1 && self.clauses.len() > 1 { format!("({})", sum) } else { sum }
+        }).collect();
+        clauses.join(" & ")
+    }
+}
+
+impl fmt::Display for ProductOfSums {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with_names(&default_names(self.input_num)))
+    }
+}
+
+fn minterm(inputs: &[bool]) -> u64 {
+    inputs.iter().enumerate().fold(0, |m, (i, b)| if *b { m | (1 << i) } else { m })
+}
+
+// On-set and don't care set of one output, rows missing from the table are don't cares
+fn split_table(table: &TruthTable, output: usize, value: bool) -> (usize, Vec<u64>, Vec<u64>) {
+    let input_num = table.get_input_num();
+    let mut on: Vec<u64> = table.map.iter()
+        .filter(|row| row.1[output] == value)
+        .map(|row| minterm(row.0))
+        .collect();
+    on.sort();
+
+    let known: HashSet<u64> = table.map.keys().map(|k| minterm(k)).collect();
+    let dc = (0..1_u64 << input_num).filter(|m| !known.contains(m)).collect();
+
+    (input_num, on, dc)
+}
+
+// Minimal sum of products for one output of the table
+pub fn minimize_sop(table: &TruthTable, output: usize) -> SumOfProducts {
+    let (input_num, on, dc) = split_table(table, output, true);
+    let cubes = minimize_cover(input_num, &on, &dc);
+    SumOfProducts { input_num, cubes }
+}
+
+// Minimal product of sums, found as the negated sum of products of the complement
+pub fn minimize_pos(table: &TruthTable, output: usize) -> ProductOfSums {
+    let (input_num, off, dc) = split_table(table, output, false);
+    let clauses = minimize_cover(input_num, &off, &dc).into_iter()
+        .map(|cube| Cube(cube.0.into_iter().map(|l| l.map(|l| !l)).collect()))
+        .collect();
+    ProductOfSums { input_num, clauses }
+}
+
+// One sum of products per output
+pub fn minimize_table(table: &TruthTable) -> Vec<SumOfProducts> {
+    (0..table.get_output_num()).map(|output| minimize_sop(table, output)).collect()
+}
+
+fn minimize_cover(input_num: usize, on: &[u64], dc: &[u64]) -> Vec<Cube> {
+    let mut cubes = if input_num <= QM_MAX_INPUTS {
+        quine_mccluskey(input_num, on, dc)
+    }
+    else {
+        espresso(input_num, on, dc)
+    };
+    // Terms on earlier inputs come first, which reads the most naturally
+    cubes.sort_by_key(|cube| cube.0.iter().map(|l| match l {
+        Some(true) => 0,
+        Some(false) => 1,
+        None => 2,
+    }).collect::<Vec<u8>>());
+    cubes
+}
+
+// Cube as (value, mask) where set mask bits are don't cares
+type Implicant = (u64, u64);
+
+fn covers(implicant: Implicant, minterm: u64) -> bool {
+    minterm & !implicant.1 == implicant.0
+}
+
+fn cost(cubes: &[Implicant], input_num: usize) -> (usize, usize) {
+    let literals = cubes.iter().map(|c| input_num - c.1.count_ones() as usize).sum();
+    (cubes.len(), literals)
+}
+
+pub fn quine_mccluskey(input_num: usize, on: &[u64], dc: &[u64]) -> Vec<Cube> {
+    if on.is_empty() {
+        return Vec::new();
+    }
+
+    // Merge implicants that differ in exactly one bit until nothing merges anymore
+    let mut current: HashSet<Implicant> = on.iter().chain(dc.iter()).map(|&m| (m, 0)).collect();
+    let mut primes: Vec<Implicant> = Vec::new();
+
+    while !current.is_empty() {
+        let mut merged_any: HashSet<Implicant> = HashSet::new();
+        let mut next: HashSet<Implicant> = HashSet::new();
+
+        let list: Vec<Implicant> = current.iter().copied().collect();
+        for (i, a) in list.iter().enumerate() {
+            for b in list.iter().skip(i + 1) {
+                let diff = a.0 ^ b.0;
+                if a.1 == b.1 && diff.count_ones() == 1 {
+                    next.insert((a.0 & !diff, a.1 | diff));
+                    merged_any.insert(*a);
+                    merged_any.insert(*b);
+                }
+            }
+        }
+
+        primes.extend(list.into_iter().filter(|imp| !merged_any.contains(imp)));
+        current = next;
+    }
+
+    // Only primes that cover part of the on-set are of interest
+    primes.retain(|p| on.iter().any(|&m| covers(*p, m)));
+    primes.sort();
+
+    select_cover(&primes, on, input_num).into_iter()
+        .map(|(value, mask)| Cube::from_bits(value, mask, input_num))
+        .collect()
+}
+
+// Picks the essential primes, then searches the cheapest cover for the rest
+fn select_cover(primes: &[Implicant], on: &[u64], input_num: usize) -> Vec<Implicant> {
+    let mut chosen: Vec<Implicant> = Vec::new();
+
+    for &m in on {
+        let covering: Vec<&Implicant> = primes.iter().filter(|p| covers(**p, m)).collect();
+        if covering.len() == 1 && !chosen.contains(covering[0]) {
+            chosen.push(*covering[0]);
+        }
+    }
+
+    let uncovered: Vec<u64> = on.iter().copied().filter(|&m| !chosen.iter().any(|c| covers(*c, m))).collect();
+    if uncovered.is_empty() {
+        return chosen;
+    }
+
+    let candidates: Vec<Implicant> = primes.iter().copied()
+        .filter(|p| !chosen.contains(p) && uncovered.iter().any(|&m| covers(*p, m)))
+        .collect();
+
+    let rest = if candidates.len() <= EXACT_COVER_MAX_PRIMES {
+        let mut best = None;
+        exact_cover(&candidates, &uncovered, &mut Vec::new(), &mut best, input_num);
+        best.unwrap_or_default()
+    }
+    else {
+        greedy_cover(&candidates, &uncovered)
+    };
+
+    chosen.extend(rest);
+    chosen
+}
+
+// Branch and bound over the primes that cover the hardest minterm
+fn exact_cover(candidates: &[Implicant], uncovered: &[u64], current: &mut Vec<Implicant>, best: &mut Option<Vec<Implicant>>, input_num: usize) {
+    if let Some(best) = best {
+        if cost(current, input_num) >= cost(best, input_num) {
+            return;
+        }
+    }
+
+    let left: Vec<u64> = uncovered.iter().copied().filter(|&m| !current.iter().any(|c| covers(*c, m))).collect();
+    let Some(&hardest) = left.iter().min_by_key(|&&m| candidates.iter().filter(|c| covers(**c, m)).count()) else {
+        *best = Some(current.clone());
+        return;
+    };
+
+    for candidate in candidates.iter().filter(|c| covers(**c, hardest)) {
+        current.push(*candidate);
+        exact_cover(candidates, &left, current, best, input_num);
+        current.pop();
+    }
+}
+
+fn greedy_cover(candidates: &[Implicant], uncovered: &[u64]) -> Vec<Implicant> {
+    let mut left: Vec<u64> = uncovered.to_vec();
+    let mut chosen = Vec::new();
+
+    while !left.is_empty() {
+        let Some(best) = candidates.iter().max_by_key(|c| {
+            (left.iter().filter(|&&m| covers(**c, m)).count(), c.1.count_ones())
+        }) else {
+            break;
+        };
+        chosen.push(*best);
+        left.retain(|&m| !covers(*best, m));
+    }
+
+    chosen
+}
+
+// Espresso style loop of expand and irredundant on an explicit on-set. Not guaranteed
+// to be minimal, but doesn't enumerate every prime implicant.
+pub fn espresso(input_num: usize, on: &[u64], dc: &[u64]) -> Vec<Cube> {
+    let care: HashSet<u64> = on.iter().chain(dc.iter()).copied().collect();
+    let off: Vec<u64> = (0..1_u64 << input_num).filter(|m| !care.contains(m)).collect();
+
+    let mut cover: Vec<Implicant> = on.iter().map(|&m| (m, 0)).collect();
+    let mut best_cost = cost(&cover, input_num);
+
+    loop {
+        cover = expand(&cover, &off, input_num);
+        cover = irredundant(&cover, on);
+
+        let new_cost = cost(&cover, input_num);
+        if new_cost >= best_cost {
+            break;
+        }
+        best_cost = new_cost;
+    }
+
+    cover.into_iter().map(|(value, mask)| Cube::from_bits(value, mask, input_num)).collect()
+}
+
+// Raises every literal of every cube that can be dropped without covering the off-set
+fn expand(cover: &[Implicant], off: &[u64], input_num: usize) -> Vec<Implicant> {
+    let mut expanded: Vec<Implicant> = Vec::new();
+
+    // Big cubes first, they are the most likely to swallow others
+    let mut order: Vec<Implicant> = cover.to_vec();
+    order.sort_by_key(|c| std::cmp::Reverse(c.1.count_ones()));
+
+    for cube in order {
+        if expanded.iter().any(|e| cube.1 & !e.1 == 0 && covers(*e, cube.0)) {
+            continue;
+        }
+
+        let mut cube = cube;
+        for bit in 0..input_num {
+            let raised = (cube.0 & !(1 << bit), cube.1 | (1 << bit));
+            if cube.1 & (1 << bit) == 0 && !off.iter().any(|&m| covers(raised, m)) {
+                cube = raised;
+            }
+        }
+        expanded.push(cube);
+    }
+
+    expanded
+}
+
+// Drops cubes whose on-set minterms are all covered by the other cubes
+fn irredundant(cover: &[Implicant], on: &[u64]) -> Vec<Implicant> {
+    let mut result: Vec<Implicant> = cover.to_vec();
+    result.sort_by_key(|c| c.1.count_ones());
+
+    let mut i = 0;
+    while i < result.len() {
+        let cube = result[i];
+        let redundant = on.iter().filter(|&&m| covers(cube, m)).all(|&m| {
+            result.iter().enumerate().any(|(j, other)| j != i && covers(*other, m))
+        });
+
+        if redundant {
+            result.remove(i);
+        }
+        else {
+            i += 1;
+        }
+    }
+
+    result
+}
+
+fn table_gate(name: &str, input_num: usize, output: impl Fn(&[bool]) -> bool) -> Box<dyn LogicGate> {
+    let mut table = TruthTable::new();
+    for i in 0..1_usize << input_num {
+        let inputs: Vec<bool> = (0..input_num).map(|bit| i & (1 << bit) != 0).collect();
+        let value = output(&inputs);
+        table.add(inputs, vec![value]);
+    }
+
+    Box::new(BasicGate::from_truth_table(name.to_string(), table))
+}
+
+// Widths are all 1 in the rebuilt circuit, so connecting can't fail
+fn connect(circuit: &mut Circuit, src: &Uuid, dest: &Uuid, pin: usize) {
+    circuit.connect(*src, 0, *dest, pin).expect("single bit connection");
+}
+
+// Two level AND/OR circuit for the given expressions, with one single bit input per variable.
+// Inverted inputs and identical product terms are shared between outputs.
+pub fn circuit_from_sop(name: String, outputs: &[SumOfProducts]) -> Circuit {
+    let mut circuit = Circuit::new(name);
+    let input_num = outputs.first().map_or(0, |o| o.input_num);
+
+    let inputs: Vec<Uuid> = (0..input_num).map(|_| {
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4())
+    }).collect();
+
+    let mut inverted: HashMap<usize, Uuid> = HashMap::new();
+    let mut terms: HashMap<Cube, Uuid> = HashMap::new();
+
+    for sop in outputs {
+        let output = circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+
+        let is_const = sop.cubes.is_empty() || sop.cubes.iter().any(|c| c.literal_num() == 0);
+        if is_const {
+            let value = !sop.cubes.is_empty();
+            let gate = circuit.add_gate(table_gate(if value { "ONE" } else { "ZERO" }, 0, |_| value), Uuid::new_v4());
+            connect(&mut circuit, &gate, &output, 0);
+            continue;
+        }
+
+        let mut products = Vec::new();
+        for cube in sop.cubes.iter() {
+            let mut literals = Vec::new();
+            for (i, l) in cube.0.iter().enumerate() {
+                match l {
+                    Some(true) => literals.push(inputs[i]),
+                    Some(false) => {
+                        let not = inverted.entry(i).or_insert_with(|| {
+                            let not = circuit.add_gate(table_gate("NOT", 1, |b| !b[0]), Uuid::new_v4());
+                            circuit.connect(inputs[i], 0, not, 0).expect("single bit connection");
+                            not
+                        });
+                        literals.push(*not);
+                    },
+                    None => {},
+                }
+            }
+
+            if literals.len() == 1 {
+                products.push(literals.remove(0));
+                continue;
+            }
+
+            let and = match terms.get(cube) {
+                Some(and) => *and,
+                None => {
+                    let and = circuit.add_gate(table_gate("AND", literals.len(), |b| b.iter().all(|x| *x)), Uuid::new_v4());
+                    for (pin, literal) in literals.iter().enumerate() {
+                        connect(&mut circuit, literal, &and, pin);
+                    }
+                    terms.insert(cube.clone(), and);
+                    and
+                },
+            };
+            products.push(and);
+        }
+
+        if products.len() == 1 {
+            connect(&mut circuit, &products[0], &output, 0);
+            continue;
+        }
+
+        let or = circuit.add_gate(table_gate("OR", products.len(), |b| b.iter().any(|x| *x)), Uuid::new_v4());
+        for (pin, product) in products.iter().enumerate() {
+            connect(&mut circuit, product, &or, pin);
+        }
+        connect(&mut circuit, &or, &output, 0);
+    }
+
+    circuit
+}
+
+impl Circuit {
+    // Minimal sum of products for every output bit
+    pub fn minimize(&mut self) -> Result<Vec<SumOfProducts>, CantCompileGate> {
+        Ok(minimize_table(&self.compile()?))
+    }
+
+    // Rebuilds the circuit as a two level AND/OR network of its minimised outputs
+    pub fn optimize(&mut self) -> Result<Circuit, CantCompileGate> {
+        let outputs = self.minimize()?;
+        Ok(circuit_from_sop(format!("{} (optimised)", self.name), &outputs))
+    }
+}
@@ -0,0 +1,813 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::{BasicGate, Circuit, CircuitBus, LogicGate, LuaCode, TruthTable};
+
+// Flip-flop used for BLIF latches when the component library has no buffer.lua,
+// the output is whatever the input was on the previous calculate pass
+const LATCH_CODE: &str = r#"
+NUM_OF_INS = 1
+NUM_OF_OUTS = 1
+MEMORY_SIZE = 1
+
+function Calculate(inputs)
+    local result = memory[1]
+    memory[1] = inputs[1]
+    return {result}
+end
+"#;
+
+#[derive(Debug)]
+pub struct NetlistError {
+    pub line: usize,
+    err: String,
+}
+
+impl NetlistError {
+    fn new(line: usize, err: String) -> Self {
+        Self { line, err }
+    }
+}
+
+impl Error for NetlistError {}
+
+impl fmt::Display for NetlistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Netlist error on line {}: {}", self.line, self.err)
+    }
+}
+
+// Loads a .blif or .v file, gates are looked up by name in the comps directory first
+pub fn load_netlist(path: &Path, comps: &Path) -> Result<Circuit, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("blif") => Ok(import_blif(&text, comps)?),
+        Some("v") => Ok(import_verilog(&text, comps)?),
+        _ => Err(format!("Unknown netlist format: {}", path.display()).into()),
+    }
+}
+
+// Component from comps/<name>.lua if there is one with a matching number of pins
+fn library_gate(comps: &Path, name: &str, input_num: Option<usize>, output_num: Option<usize>) -> Option<Box<dyn LogicGate>> {
+    let path = comps.join(name.to_ascii_lowercase() + ".lua");
+    if !path.is_file() {
+        return None;
+    }
+
+    let gate = BasicGate::from_lua(name.to_ascii_uppercase(), path.into_boxed_path()).ok()?;
+    if input_num.is_some_and(|n| n != gate.get_input_num()) || output_num.is_some_and(|n| n != gate.get_output_num()) {
+        return None;
+    }
+
+    Some(Box::new(gate))
+}
+
+fn table_gate(name: &str, input_num: usize, output: impl Fn(&[bool]) -> bool) -> Box<dyn LogicGate> {
+    let mut table = TruthTable::new();
+    for i in 0..1_usize << input_num {
+        let inputs: Vec<bool> = (0..input_num).map(|bit| i & (1 << (input_num - 1 - bit)) != 0).collect();
+        let value = output(&inputs);
+        table.add(inputs, vec![value]);
+    }
+
+    Box::new(BasicGate::from_truth_table(name.to_string(), table))
+}
+
+// Collects gates and the signals they read and drive, connects everything once all drivers are known
+struct NetlistBuilder {
+    circuit: Circuit,
+    drivers: HashMap<String, (Uuid, usize)>,
+    // (signal, gate, input pin, line)
+    sinks: Vec<(String, Uuid, usize, usize)>,
+    outputs: Vec<(String, usize)>,
+}
+
+impl NetlistBuilder {
+    fn new(name: String) -> Self {
+        Self {
+            circuit: Circuit::new(name),
+            drivers: HashMap::new(),
+            sinks: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    fn drive(&mut self, signal: &str, gate: Uuid, pin: usize, line: usize) -> Result<(), NetlistError> {
+        if self.drivers.insert(signal.to_string(), (gate, pin)).is_some() {
+            return Err(NetlistError::new(line, format!("Signal {} has more than one driver", signal)));
+        }
+        Ok(())
+    }
+
+    fn add_input(&mut self, signal: &str, line: usize) -> Result<(), NetlistError> {
+        let bus = self.circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        self.drive(signal, bus, 0, line)
+    }
+
+    fn add_output(&mut self, signal: &str, line: usize) {
+        self.outputs.push((signal.to_string(), line));
+    }
+
+    fn add_gate(&mut self, gate: Box<dyn LogicGate>, inputs: &[String], outputs: &[String], line: usize) -> Result<(), NetlistError> {
+        let gate = self.circuit.add_gate(gate, Uuid::new_v4());
+
+        for (pin, signal) in inputs.iter().enumerate() {
+            self.sinks.push((signal.clone(), gate, pin, line));
+        }
+        for (pin, signal) in outputs.iter().enumerate() {
+            self.drive(signal, gate, pin, line)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Circuit, NetlistError> {
+        for (signal, line) in std::mem::take(&mut self.outputs) {
+            let bus = self.circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+            self.sinks.push((signal, bus, 0, line));
+        }
+
+        for (signal, gate, pin, line) in std::mem::take(&mut self.sinks) {
+            let (driver, driver_pin) = self.drivers.get(&signal)
+                .ok_or_else(|| NetlistError::new(line, format!("Unknown signal {}", signal)))?;
+
+            self.circuit.connect(*driver, *driver_pin, gate, pin)
+                .map_err(|e| NetlistError::new(line, e.to_string()))?;
+        }
+
+        Ok(self.circuit)
+    }
+}
+
+// BLIF
+
+pub fn import_blif(text: &str, comps: &Path) -> Result<Circuit, NetlistError> {
+    // Join continued lines and drop comments, keeping the number of the first line
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+    for (i, raw) in text.lines().enumerate() {
+        let content = raw.split('#').next().unwrap_or("").trim_end();
+        let (content, continues) = match content.strip_suffix('\\') {
+            Some(content) => (content, true),
+            None => (content, false),
+        };
+
+        match lines.last_mut() {
+            Some(last) if continued => {
+                last.1.push(' ');
+                last.1.push_str(content);
+            },
+            _ => lines.push((i + 1, content.to_string())),
+        }
+        continued = continues;
+    }
+    lines.retain(|line| !line.1.trim().is_empty());
+
+    let mut builder: Option<NetlistBuilder> = None;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let (line, content) = &lines[i];
+        let line = *line;
+        let mut words = content.split_whitespace();
+        let directive = words.next().unwrap_or("");
+        let args: Vec<String> = words.map(|w| w.to_string()).collect();
+        i += 1;
+
+        if directive == ".model" {
+            if builder.is_some() {
+                return Err(NetlistError::new(line, "Only one .model per file is supported".to_string()));
+            }
+            builder = Some(NetlistBuilder::new(args.first().cloned().unwrap_or("BLIF".to_string())));
+            continue;
+        }
+
+        let b = builder.get_or_insert_with(|| NetlistBuilder::new("BLIF".to_string()));
+        match directive {
+            ".inputs" => {
+                for signal in args.iter() {
+                    b.add_input(signal, line)?;
+                }
+            },
+            ".outputs" => {
+                for signal in args.iter() {
+                    b.add_output(signal, line);
+                }
+            },
+            ".names" => {
+                let Some((output, inputs)) = args.split_last() else {
+                    return Err(NetlistError::new(line, ".names needs at least an output".to_string()));
+                };
+
+                let mut cover = Vec::new();
+                while i < lines.len() && !lines[i].1.trim_start().starts_with('.') {
+                    cover.push(parse_cover_row(&lines[i].1, inputs.len(), lines[i].0)?);
+                    i += 1;
+                }
+
+                // All rows have to agree on whether they list the on-set or the off-set
+                let on_set = cover.first().is_none_or(|row| row.1);
+                if cover.iter().any(|row| row.1 != on_set) {
+                    return Err(NetlistError::new(line, format!("Cover of {} mixes on-set and off-set rows", output)));
+                }
+
+                let gate = table_gate(output, inputs.len(), |bits| {
+                    let matched = cover.iter().any(|(plane, _)| {
+                        plane.iter().zip(bits).all(|(p, b)| p.is_none_or(|p| p == *b))
+                    });
+                    // An empty cover is constant 0
+                    if cover.is_empty() { false } else { matched == on_set }
+                });
+                b.add_gate(gate, inputs, std::slice::from_ref(output), line)?;
+            },
+            ".latch" => {
+                if args.len() < 2 {
+                    return Err(NetlistError::new(line, ".latch needs an input and an output".to_string()));
+                }
+
+                let mut gate = match library_gate(comps, "buffer", Some(1), Some(1)) {
+                    Some(gate) => gate,
+                    None => Box::new(BasicGate::from_lua_code("LATCH".to_string(), LuaCode(LATCH_CODE.to_string()))
+                        .map_err(|e| NetlistError::new(line, e.to_string()))?),
+                };
+
+                // The optional initial value is the last argument, 2 and 3 mean unknown
+                if args.len() == 3 || args.len() == 5 {
+                    gate.set_memory(0, args[args.len() - 1] == "1");
+                }
+
+                b.add_gate(gate, &args[0..1], &args[1..2], line)?;
+            },
+            ".end" => break,
+            _ => return Err(NetlistError::new(line, format!("Unsupported BLIF directive {}", directive))),
+        }
+    }
+
+    builder.unwrap_or_else(|| NetlistBuilder::new("BLIF".to_string())).finish()
+}
+
+// One row of a .names cover, '-' means the input doesn't matter
+fn parse_cover_row(row: &str, input_num: usize, line: usize) -> Result<(Vec<Option<bool>>, bool), NetlistError> {
+    let words: Vec<&str> = row.split_whitespace().collect();
+    let (plane, output) = match (input_num, words.as_slice()) {
+        (0, [output]) => ("", *output),
+        (_, [plane, output]) => (*plane, *output),
+        _ => return Err(NetlistError::new(line, format!("Invalid cover row: {}", row.trim()))),
+    };
+
+    if plane.len() != input_num {
+        return Err(NetlistError::new(line, format!("Cover row has {} inputs, expected {}", plane.len(), input_num)));
+    }
+
+    let plane = plane.chars().map(|c| match c {
+        '0' => Ok(Some(false)),
+        '1' => Ok(Some(true)),
+        '-' => Ok(None),
+        _ => Err(NetlistError::new(line, format!("Invalid character '{}' in cover row", c))),
+    }).collect::<Result<Vec<Option<bool>>, NetlistError>>()?;
+
+    let output = match output {
+        "1" => true,
+        "0" => false,
+        _ => return Err(NetlistError::new(line, format!("Invalid cover output: {}", output))),
+    };
+
+    Ok((plane, output))
+}
+
+// Verilog
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Sym(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, NetlistError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        }
+        else if c.is_whitespace() {
+            i += 1;
+        }
+        else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        }
+        else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        }
+        else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+        }
+        else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '\'') {
+                i += 1;
+            }
+            tokens.push((Token::Number(chars[start..i].iter().collect()), line));
+        }
+        else if "(),;.=~&|^[]:".contains(c) {
+            tokens.push((Token::Sym(c), line));
+            i += 1;
+        }
+        else {
+            return Err(NetlistError::new(line, format!("Unexpected character '{}'", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Signal(String),
+    Const(bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn signals(&self, found: &mut Vec<String>) {
+        match self {
+            Expr::Signal(name) => {
+                if !found.contains(name) {
+                    found.push(name.clone());
+                }
+            },
+            Expr::Const(_) => {},
+            Expr::Not(e) => e.signals(found),
+            Expr::And(a, b) | Expr::Or(a, b) | Expr::Xor(a, b) => {
+                a.signals(found);
+                b.signals(found);
+            },
+        }
+    }
+
+    fn eval(&self, signals: &[String], values: &[bool]) -> bool {
+        match self {
+            Expr::Signal(name) => signals.iter().position(|s| s == name).is_some_and(|i| values[i]),
+            Expr::Const(value) => *value,
+            Expr::Not(e) => !e.eval(signals, values),
+            Expr::And(a, b) => a.eval(signals, values) && b.eval(signals, values),
+            Expr::Or(a, b) => a.eval(signals, values) || b.eval(signals, values),
+            Expr::Xor(a, b) => a.eval(signals, values) ^ b.eval(signals, values),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug)]
+enum Connections {
+    Positional(Vec<Option<String>>),
+    Named(Vec<(String, Option<String>, usize)>),
+}
+
+#[derive(Debug)]
+enum Item {
+    Assign { lhs: String, expr: Expr, line: usize },
+    Instance { kind: String, connections: Connections, line: usize },
+}
+
+#[derive(Debug)]
+struct Module {
+    name: String,
+    ports: Vec<String>,
+    directions: HashMap<String, (Direction, usize)>,
+    items: Vec<Item>,
+    line: usize,
+}
+
+impl Module {
+    fn ports_with(&self, direction: Direction) -> Vec<String> {
+        self.ports.iter().filter(|p| self.directions.get(*p).map(|d| d.0) == Some(direction)).cloned().collect()
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(1, |t| t.1)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn next(&mut self) -> Result<Token, NetlistError> {
+        let token = self.tokens.get(self.pos).map(|t| t.0.clone())
+            .ok_or_else(|| NetlistError::new(self.line(), "Unexpected end of file".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_sym(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Sym(c))
+    }
+
+    fn eat_sym(&mut self, c: char) -> bool {
+        if self.is_sym(c) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_sym(&mut self, c: char) -> Result<(), NetlistError> {
+        let line = self.line();
+        match self.next()? {
+            Token::Sym(s) if s == c => Ok(()),
+            other => Err(NetlistError::new(line, format!("Expected '{}', found {:?}", c, other))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, NetlistError> {
+        let line = self.line();
+        match self.next()? {
+            Token::Ident(name) => Ok(name),
+            other => Err(NetlistError::new(line, format!("Expected a name, found {:?}", other))),
+        }
+    }
+
+    fn no_vector(&self) -> Result<(), NetlistError> {
+        if self.is_sym('[') {
+            return Err(NetlistError::new(self.line(), "Vectors aren't supported, use single bit signals".to_string()));
+        }
+        Ok(())
+    }
+
+    fn modules(&mut self) -> Result<Vec<Module>, NetlistError> {
+        let mut modules = Vec::new();
+        while self.peek().is_some() {
+            let line = self.line();
+            match self.next()? {
+                Token::Ident(kw) if kw == "module" => modules.push(self.module(line)?),
+                other => return Err(NetlistError::new(line, format!("Expected 'module', found {:?}", other))),
+            }
+        }
+        Ok(modules)
+    }
+
+    fn module(&mut self, line: usize) -> Result<Module, NetlistError> {
+        let mut module = Module {
+            name: self.expect_ident()?,
+            ports: Vec::new(),
+            directions: HashMap::new(),
+            items: Vec::new(),
+            line,
+        };
+
+        // Port list, either plain names or ANSI style declarations
+        if self.eat_sym('(') {
+            let mut direction = None;
+            while !self.eat_sym(')') {
+                let line = self.line();
+                let mut name = self.expect_ident()?;
+                if name == "input" || name == "output" {
+                    direction = Some(if name == "input" { Direction::Input } else { Direction::Output });
+                    name = self.expect_ident()?;
+                }
+                if name == "wire" {
+                    name = self.expect_ident()?;
+                }
+                self.no_vector()?;
+
+                if let Some(direction) = direction {
+                    module.directions.insert(name.clone(), (direction, line));
+                }
+                module.ports.push(name);
+
+                if !self.is_sym(')') {
+                    self.expect_sym(',')?;
+                }
+            }
+        }
+        self.expect_sym(';')?;
+
+        loop {
+            let line = self.line();
+            let kw = self.expect_ident()?;
+            match kw.as_str() {
+                "endmodule" => break,
+                "input" | "output" | "wire" => {
+                    let direction = match kw.as_str() {
+                        "input" => Some(Direction::Input),
+                        "output" => Some(Direction::Output),
+                        _ => None,
+                    };
+                    loop {
+                        let mut name = self.expect_ident()?;
+                        if name == "wire" {
+                            name = self.expect_ident()?;
+                        }
+                        self.no_vector()?;
+                        if let Some(direction) = direction {
+                            module.directions.insert(name, (direction, line));
+                        }
+                        if !self.eat_sym(',') {
+                            break;
+                        }
+                    }
+                    self.expect_sym(';')?;
+                },
+                "assign" => {
+                    let lhs = self.expect_ident()?;
+                    self.no_vector()?;
+                    self.expect_sym('=')?;
+                    let expr = self.expr()?;
+                    self.expect_sym(';')?;
+                    module.items.push(Item::Assign { lhs, expr, line });
+                },
+                _ => {
+                    // Instance name is optional for primitives
+                    if let Some(Token::Ident(_)) = self.peek() {
+                        self.expect_ident()?;
+                    }
+                    let connections = self.connections()?;
+                    self.expect_sym(';')?;
+                    module.items.push(Item::Instance { kind: kw, connections, line });
+                },
+            }
+        }
+
+        for port in module.ports.iter() {
+            if !module.directions.contains_key(port) {
+                return Err(NetlistError::new(module.line, format!("Port {} of module {} has no direction", port, module.name)));
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn connections(&mut self) -> Result<Connections, NetlistError> {
+        self.expect_sym('(')?;
+
+        if self.is_sym('.') {
+            let mut named = Vec::new();
+            while self.eat_sym('.') {
+                let line = self.line();
+                let port = self.expect_ident()?;
+                self.expect_sym('(')?;
+                let signal = if self.is_sym(')') { None } else { Some(self.expect_ident()?) };
+                self.no_vector()?;
+                self.expect_sym(')')?;
+                named.push((port, signal, line));
+                if !self.eat_sym(',') {
+                    break;
+                }
+            }
+            self.expect_sym(')')?;
+            return Ok(Connections::Named(named));
+        }
+
+        let mut positional = Vec::new();
+        while !self.eat_sym(')') {
+            if self.is_sym(',') {
+                positional.push(None);
+            }
+            else {
+                positional.push(Some(self.expect_ident()?));
+                self.no_vector()?;
+            }
+            if !self.is_sym(')') {
+                self.expect_sym(',')?;
+            }
+        }
+        Ok(Connections::Positional(positional))
+    }
+
+    // Precedence from low to high: | ^ & ~
+    fn expr(&mut self) -> Result<Expr, NetlistError> {
+        let mut lhs = self.xor_expr()?;
+        while self.eat_sym('|') {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.xor_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn xor_expr(&mut self) -> Result<Expr, NetlistError> {
+        let mut lhs = self.and_expr()?;
+        while self.eat_sym('^') {
+            lhs = Expr::Xor(Box::new(lhs), Box::new(self.and_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, NetlistError> {
+        let mut lhs = self.unary_expr()?;
+        while self.eat_sym('&') {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, NetlistError> {
+        let line = self.line();
+        match self.next()? {
+            Token::Sym('~') => Ok(Expr::Not(Box::new(self.unary_expr()?))),
+            Token::Sym('(') => {
+                let expr = self.expr()?;
+                self.expect_sym(')')?;
+                Ok(expr)
+            },
+            Token::Ident(name) => {
+                self.no_vector()?;
+                Ok(Expr::Signal(name))
+            },
+            Token::Number(n) => match n.as_str() {
+                "0" | "1'b0" => Ok(Expr::Const(false)),
+                "1" | "1'b1" => Ok(Expr::Const(true)),
+                _ => Err(NetlistError::new(line, format!("Unsupported constant {}", n))),
+            },
+            other => Err(NetlistError::new(line, format!("Unexpected {:?} in expression", other))),
+        }
+    }
+}
+
+pub fn import_verilog(text: &str, comps: &Path) -> Result<Circuit, NetlistError> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+    let modules = parser.modules()?;
+
+    // The top module is the last one that no other module instantiates
+    let instantiated: Vec<&str> = modules.iter()
+        .flat_map(|m| m.items.iter())
+        .filter_map(|item| match item {
+            Item::Instance { kind, .. } => Some(kind.as_str()),
+            _ => None,
+        })
+        .collect();
+    let top = modules.iter().rev().find(|m| !instantiated.contains(&m.name.as_str()))
+        .ok_or_else(|| NetlistError::new(1, "No top level module found".to_string()))?;
+
+    build_module(top, &modules, comps, &mut Vec::new())
+}
+
+fn build_module(module: &Module, modules: &[Module], comps: &Path, stack: &mut Vec<String>) -> Result<Circuit, NetlistError> {
+    if stack.contains(&module.name) {
+        return Err(NetlistError::new(module.line, format!("Module {} instantiates itself", module.name)));
+    }
+    stack.push(module.name.clone());
+
+    let mut builder = NetlistBuilder::new(module.name.clone());
+    for port in module.ports_with(Direction::Input) {
+        builder.add_input(&port, module.directions[&port].1)?;
+    }
+    for port in module.ports_with(Direction::Output) {
+        builder.add_output(&port, module.directions[&port].1);
+    }
+
+    for item in module.items.iter() {
+        match item {
+            Item::Assign { lhs, expr, line } => {
+                let mut signals = Vec::new();
+                expr.signals(&mut signals);
+
+                let gate = table_gate(lhs, signals.len(), |values| expr.eval(&signals, values));
+                builder.add_gate(gate, &signals, std::slice::from_ref(lhs), *line)?;
+            },
+            Item::Instance { kind, connections, line } => {
+                let line = *line;
+                let (gate, inputs, outputs) = if let Some(sub) = modules.iter().find(|m| m.name == *kind) {
+                    let circuit = build_module(sub, modules, comps, stack)?;
+                    // Positional connections follow the order the module declares its ports in
+                    let (inputs, outputs) = bind_ports(&sub.ports, &sub.ports_with(Direction::Input), &sub.ports_with(Direction::Output), connections, line)?;
+                    (Box::new(circuit) as Box<dyn LogicGate>, inputs, outputs)
+                }
+                else if let Some(gate) = primitive(kind, connections, comps, line)? {
+                    gate
+                }
+                else if let Some(gate) = library_gate(comps, kind, None, None) {
+                    let input_ports: Vec<String> = (0..gate.get_input_num()).map(|i| format!("in_{}", i)).collect();
+                    let output_ports: Vec<String> = (0..gate.get_output_num()).map(|i| format!("out_{}", i)).collect();
+                    // Outputs first, like the primitives
+                    let order: Vec<String> = output_ports.iter().chain(input_ports.iter()).cloned().collect();
+                    let (inputs, outputs) = bind_ports(&order, &input_ports, &output_ports, connections, line)?;
+                    (gate, inputs, outputs)
+                }
+                else {
+                    return Err(NetlistError::new(line, format!("Unknown module or component {}", kind)));
+                };
+
+                builder.add_gate(gate, &inputs, &outputs, line)?;
+            },
+        }
+    }
+
+    stack.pop();
+    builder.finish()
+}
+
+// Matches instance connections to input and output ports, positional connections are bound in the order of positional_ports
+fn bind_ports(positional_ports: &[String], input_ports: &[String], output_ports: &[String], connections: &Connections, line: usize) -> Result<(Vec<String>, Vec<String>), NetlistError> {
+    let mut inputs = vec![None; input_ports.len()];
+    let mut outputs = vec![None; output_ports.len()];
+
+    let named: Vec<(&String, &Option<String>, usize)> = match connections {
+        Connections::Positional(signals) => {
+            if signals.len() != positional_ports.len() {
+                return Err(NetlistError::new(line, format!(
+                    "Expected {} connections, found {}", positional_ports.len(), signals.len()
+                )));
+            }
+            positional_ports.iter().zip(signals).map(|(port, signal)| (port, signal, line)).collect()
+        },
+        Connections::Named(named) => named.iter().map(|(port, signal, port_line)| (port, signal, *port_line)).collect(),
+    };
+
+    for (port, signal, port_line) in named {
+        if let Some(i) = input_ports.iter().position(|p| p == port) {
+            inputs[i] = signal.clone();
+        }
+        else if let Some(i) = output_ports.iter().position(|p| p == port) {
+            outputs[i] = signal.clone();
+        }
+        else {
+            return Err(NetlistError::new(port_line, format!("Unknown port {}", port)));
+        }
+    }
+
+    // Unconnected inputs can't be left floating in a circuit, unconnected outputs are fine
+    let inputs = inputs.into_iter().enumerate()
+        .map(|(i, signal)| signal.ok_or_else(|| NetlistError::new(line, format!("Input {} isn't connected", input_ports[i]))))
+        .collect::<Result<Vec<String>, NetlistError>>()?;
+    let outputs = outputs.into_iter().enumerate()
+        .map(|(i, signal)| signal.unwrap_or_else(|| format!("$unconnected_{}_{}", line, i)))
+        .collect();
+
+    Ok((inputs, outputs))
+}
+
+type BoundGate = (Box<dyn LogicGate>, Vec<String>, Vec<String>);
+
+// Built in gate primitives, output first: and (y, a, b, ...)
+fn primitive(kind: &str, connections: &Connections, comps: &Path, line: usize) -> Result<Option<BoundGate>, NetlistError> {
+    let op: fn(&[bool]) -> bool = match kind {
+        "and" => |b| b.iter().all(|x| *x),
+        "nand" => |b| !b.iter().all(|x| *x),
+        "or" => |b| b.iter().any(|x| *x),
+        "nor" => |b| !b.iter().any(|x| *x),
+        "xor" => |b| b.iter().filter(|x| **x).count() % 2 == 1,
+        "xnor" => |b| b.iter().filter(|x| **x).count() % 2 == 0,
+        "not" => |b| !b[0],
+        "buf" => |b| b[0],
+        _ => return Ok(None),
+    };
+
+    let Connections::Positional(signals) = connections else {
+        return Err(NetlistError::new(line, format!("Primitive {} needs positional connections", kind)));
+    };
+    let signals = signals.iter().cloned().collect::<Option<Vec<String>>>()
+        .ok_or_else(|| NetlistError::new(line, format!("Primitive {} has an empty connection", kind)))?;
+
+    let input_num = signals.len().saturating_sub(1);
+    let valid = match kind {
+        "not" | "buf" => input_num == 1,
+        _ => input_num >= 1,
+    };
+    if !valid {
+        return Err(NetlistError::new(line, format!("Wrong number of connections for {}", kind)));
+    }
+
+    let gate = library_gate(comps, kind, Some(input_num), Some(1))
+        .unwrap_or_else(|| table_gate(&kind.to_ascii_uppercase(), input_num, op));
+
+    Ok(Some((gate, signals[1..].to_vec(), signals[..1].to_vec())))
+}
@@ -0,0 +1,223 @@
+use core::fmt;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::testbench::{run_test_bench, TestReport};
+use crate::{Circuit, Logic, LogicGate, TruthTable};
+
+// Below this many inputs copying the circuit to the workers costs more than it saves
+pub const MIN_PARALLEL_INPUTS: usize = 8;
+
+#[derive(Debug)]
+pub enum ParallelError {
+    // Every worker simulates its own copy, built from the circuit's document
+    CantCopy(String),
+    // Vectors run in any order on different copies, state would make the results depend on that.
+    // A combinational loop keeps state as well, e.g. a latch made of two NOR gates.
+    Stateful,
+    Failed { index: usize, message: String },
+    Cancelled,
+}
+
+impl Error for ParallelError {}
+
+impl fmt::Display for ParallelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParallelError::CantCopy(err) => write!(f, "Circuit can't be copied to the workers: {}", err),
+            ParallelError::Stateful => write!(f, "Circuit keeps state, its vectors can't be simulated independently"),
+            ParallelError::Failed { index, message } => write!(f, "Vector {} failed: {}", index, message),
+            ParallelError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct JobState {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+// Shared by whoever started a job and its workers, e.g. a progress bar can
+// poll it from the ui thread and cancel the job
+#[derive(Clone, Default)]
+pub struct JobControl {
+    state: Arc<JobState>,
+}
+
+impl JobControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Workers stop before their next item, the job returns ParallelError::Cancelled
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn get_done(&self) -> usize {
+        self.state.done.load(Ordering::Relaxed)
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.state.total.load(Ordering::Relaxed)
+    }
+
+    // Between 0 and 1
+    pub fn get_progress(&self) -> f32 {
+        match self.get_total() {
+            0 => 0.0,
+            total => self.get_done() as f32 / total as f32,
+        }
+    }
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// The error of the first failed vector wins, so the result doesn't depend on thread timing
+fn first_error(kept: Option<ParallelError>, new: ParallelError) -> ParallelError {
+    match (kept, new) {
+        (Some(ParallelError::Failed { index, message }), ParallelError::Failed { index: other, .. }) if index < other => {
+            ParallelError::Failed { index, message }
+        },
+        (Some(kept @ ParallelError::Failed { .. }), new) if !matches!(new, ParallelError::Failed { .. }) => kept,
+        (_, new) => new,
+    }
+}
+
+// Runs work on every item with up to threads workers and returns the results in
+// the order of the items. Each worker sets itself up once with init, e.g. by
+// copying the circuit. The first failure stops the other workers.
+pub fn run_jobs<T, W, R>(
+    items: &[T],
+    threads: usize,
+    control: &JobControl,
+    init: impl Fn() -> Result<W, ParallelError> + Sync,
+    work: impl Fn(&mut W, &T) -> Result<R, String> + Sync,
+) -> Result<Vec<R>, ParallelError>
+where
+    T: Sync,
+    R: Send,
+{
+    control.state.total.store(items.len(), Ordering::Relaxed);
+    control.state.done.store(0, Ordering::Relaxed);
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let workers = threads.clamp(1, items.len().max(1));
+
+    let finished: Vec<Result<Vec<(usize, R)>, ParallelError>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+            let mut state = init()?;
+            let mut results = Vec::new();
+
+            while !failed.load(Ordering::Relaxed) {
+                if control.is_cancelled() {
+                    return Err(ParallelError::Cancelled);
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+
+                match work(&mut state, item) {
+                    Ok(result) => results.push((index, result)),
+                    Err(message) => {
+                        failed.store(true, Ordering::Relaxed);
+                        return Err(ParallelError::Failed { index, message });
+                    },
+                }
+                control.state.done.fetch_add(1, Ordering::Relaxed);
+            }
+
+            Ok(results)
+        })).collect();
+
+        handles.into_iter().map(|handle| handle.join().expect("simulation worker panicked")).collect()
+    });
+
+    let mut ordered: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    let mut error = None;
+    for worker in finished {
+        match worker {
+            Ok(results) => {
+                for (index, result) in results {
+                    ordered[index] = Some(result);
+                }
+            },
+            Err(err) => error = Some(first_error(error, err)),
+        }
+    }
+
+    if let Some(err) = error {
+        return Err(err);
+    }
+    Ok(ordered.into_iter().map(|result| result.expect("every item was simulated")).collect())
+}
+
+// Output values of the circuit for every vector, in the same order as the vectors
+pub fn simulate_vectors(circuit: &Circuit, vectors: &[Vec<bool>], threads: usize, control: &JobControl) -> Result<Vec<Vec<Logic>>, ParallelError> {
+    if !circuit.compilable() || !circuit.find_combinational_loops().is_empty() {
+        return Err(ParallelError::Stateful);
+    }
+
+    let doc = circuit.to_document().map_err(|err| ParallelError::CantCopy(err.to_string()))?;
+    let input_num = circuit.get_input_num();
+
+    run_jobs(
+        vectors,
+        threads,
+        control,
+        || Circuit::from_document(&doc).map_err(|err| ParallelError::CantCopy(err.to_string())),
+        |copy, vector| {
+            if vector.len() != input_num {
+                return Err(format!("{} bits for {} inputs", vector.len(), input_num));
+            }
+            for (i, value) in vector.iter().enumerate() {
+                copy.set_input(i, *value);
+            }
+            copy.calculate().map_err(|err| err.to_string())?;
+            Ok(copy.get_output_values())
+        },
+    )
+}
+
+// Same rows as Circuit::compile, the first input is the highest bit of the row number
+pub fn truth_table(circuit: &Circuit, threads: usize, control: &JobControl) -> Result<TruthTable, ParallelError> {
+    let n = circuit.get_input_num();
+    let vectors: Vec<Vec<bool>> = (0..1_usize << n)
+        .map(|row| (0..n).map(|bit| row >> (n - 1 - bit) & 1 == 1).collect())
+        .collect();
+    let outputs = simulate_vectors(circuit, &vectors, threads, control)?;
+
+    let mut table = TruthTable::new();
+    for (index, (inputs, outputs)) in vectors.into_iter().zip(outputs).enumerate() {
+        // X or Z can't be stored in a truth table
+        let outputs: Option<Vec<bool>> = outputs.iter().map(|v| v.to_bool()).collect();
+        let outputs = outputs.ok_or_else(|| ParallelError::Failed { index, message: "Output is X or Z".to_string() })?;
+        table.add(inputs, outputs);
+    }
+    Ok(table)
+}
+
+// Runs every (name, code) bench against its own freshly loaded gate, reports come back in order
+pub fn run_test_benches(
+    load: impl Fn() -> Result<Box<dyn LogicGate>, String> + Sync,
+    benches: &[(String, String)],
+    threads: usize,
+    control: &JobControl,
+) -> Result<Vec<TestReport>, ParallelError> {
+    run_jobs(benches, threads, control, || Ok(()), |_, (name, code)| {
+        let mut gate = load()?;
+        run_test_bench(gate.as_mut(), code, name).map_err(|err| err.to_string())
+    })
+}
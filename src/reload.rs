@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+use crate::circuit_file::{restore_memory, GateSource};
+use crate::{BasicGate, Circuit, LogicGate};
+
+// How often the components directory is looked at, reading it every frame is wasteful
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Notices lua components that were added or saved since the last poll. The
+// modification time alone can miss two saves within its resolution, so the
+// size is compared as well.
+pub struct ComponentWatcher {
+    dir: PathBuf,
+    files: HashMap<PathBuf, (SystemTime, u64)>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl ComponentWatcher {
+    // Files that are already there count as seen
+    pub fn new(dir: &Path) -> Self {
+        Self::with_interval(dir, POLL_INTERVAL)
+    }
+
+    pub fn with_interval(dir: &Path, interval: Duration) -> Self {
+        let mut watcher = Self {
+            dir: dir.to_path_buf(),
+            files: HashMap::new(),
+            interval,
+            last_poll: Instant::now(),
+        };
+        watcher.changed();
+        watcher
+    }
+
+    // Empty until the interval has passed
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+        self.changed()
+    }
+
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+
+        let mut files = HashMap::new();
+        let mut changed = Vec::new();
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().is_none_or(|ext| ext != "lua") {
+                continue;
+            }
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+
+            let stamp = (meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len());
+            if self.files.get(&path) != Some(&stamp) {
+                changed.push(path.clone());
+            }
+            files.insert(path, stamp);
+        }
+
+        // Deleted files are forgotten, so they count as new when they come back
+        self.files = files;
+        changed.sort();
+        changed
+    }
+}
+
+// A placed gate that runs the reloaded script
+#[derive(Debug)]
+pub struct ReloadedGate {
+    // Ids of the circuits on the way down and the gate's own id last
+    pub path: Vec<Uuid>,
+    // The gate keeps the old script if the new one doesn't load
+    pub result: Result<(), String>,
+}
+
+// Loads the script again into every gate of the circuit and its nested circuits
+// that was made from it. Memory and the delay override are kept as long as the
+// pins stay the same, connections to pins that are gone or changed width are dropped.
+pub fn reload_lua_gates(circuit: &mut Circuit, script: &Path) -> Vec<ReloadedGate> {
+    let script = canonical(script);
+    let mut reloaded = Vec::new();
+    reload_nested(circuit, &script, &mut Vec::new(), &mut reloaded);
+    reloaded
+}
+
+fn reload_nested(circuit: &mut Circuit, script: &Path, path: &mut Vec<Uuid>, reloaded: &mut Vec<ReloadedGate>) {
+    let ids: Vec<Uuid> = circuit.get_arena().iter().map(|(id, _)| id).collect();
+
+    for id in ids {
+        path.push(id);
+
+        let gate = circuit.get_gate_mut(&id).expect("gate of the arena");
+        if let Some(nested) = gate.as_circuit_mut() {
+            reload_nested(nested, script, path, reloaded);
+        } else if let Some(GateSource::Lua { path: file }) = gate.get_source() {
+            if canonical(&file) == script {
+                let result = rebuild(gate, &file).map(|new_gate| {
+                    circuit.replace_gate(&id, new_gate);
+                });
+                reloaded.push(ReloadedGate { path: path.clone(), result });
+            }
+        }
+
+        path.pop();
+    }
+}
+
+// The gate keeps the path it was made from, so it is saved the same way as before
+fn rebuild(old: &dyn LogicGate, script: &Path) -> Result<Box<dyn LogicGate>, String> {
+    let gate = BasicGate::from_lua(old.get_name(), script.to_path_buf().into_boxed_path()).map_err(|e| e.to_string())?;
+    let mut gate: Box<dyn LogicGate> = Box::new(gate);
+
+    let same_pins = gate.get_input_widths() == old.get_input_widths() && gate.get_output_widths() == old.get_output_widths();
+    if same_pins {
+        restore_memory(&mut gate, &old.get_memory().unwrap_or_default());
+        if let Some(delay) = old.get_delay_override() {
+            gate.set_delay(Some(delay));
+        }
+    }
+
+    Ok(gate)
+}
+
+// Gates may refer to the same file as ./comps/and.lua or comps/and.lua
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,173 @@
+use core::fmt;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use mlua::{Function, HookTriggers, Lua, MultiValue, Table, Value};
+
+// Instructions between two checks of the limits
+const HOOK_INTERVAL: u32 = 1000;
+
+// Everything a component script needs, the rest of the standard library is hidden
+const DEFAULT_ALLOWED_GLOBALS: &[&str] = &[
+    "_VERSION", "assert", "error", "getmetatable", "ipairs", "next", "pairs", "pcall", "print",
+    "rawequal", "rawget", "rawlen", "rawset", "select", "setmetatable", "tonumber", "tostring",
+    "type", "xpcall", "math", "string", "table", "utf8",
+];
+
+// What a lua component script may use and how much it may do in one call
+#[derive(Debug, Clone)]
+pub struct LuaLimits {
+    pub max_instructions: u64,
+    // Bytes a single call may allocate on top of what the script already uses
+    pub max_memory: usize,
+    pub timeout: Duration,
+    // Globals left visible to scripts, "os.clock" only keeps that one function of a library
+    pub allowed_globals: Vec<String>,
+}
+
+impl Default for LuaLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: 10_000_000,
+            max_memory: 64 * 1024 * 1024,
+            timeout: Duration::from_millis(500),
+            allowed_globals: DEFAULT_ALLOWED_GLOBALS.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaLimitError {
+    Instructions { limit: u64 },
+    Timeout { limit: Duration },
+}
+
+impl Error for LuaLimitError {}
+
+impl fmt::Display for LuaLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaLimitError::Instructions { limit } => write!(f, "Lua script ran more than {} instructions", limit),
+            LuaLimitError::Timeout { limit } => write!(f, "Lua script timed out after {} ms", limit.as_millis()),
+        }
+    }
+}
+
+// What the current call has used up so far
+struct CallBudget {
+    limits: LuaLimits,
+    instructions: u64,
+    started: Instant,
+    // Set once a limit was hit, stays set until the next call starts
+    exceeded: Option<LuaLimitError>,
+}
+
+impl CallBudget {
+    fn check(&mut self) -> Result<(), LuaLimitError> {
+        if self.exceeded.is_none() {
+            if self.instructions > self.limits.max_instructions {
+                self.exceeded = Some(LuaLimitError::Instructions { limit: self.limits.max_instructions });
+            } else if self.started.elapsed() > self.limits.timeout {
+                self.exceeded = Some(LuaLimitError::Timeout { limit: self.limits.timeout });
+            }
+        }
+
+        match &self.exceeded {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+// A lua state for component scripts with the default limits
+pub fn sandboxed_lua() -> mlua::Result<Lua> {
+    sandboxed_lua_with(LuaLimits::default())
+}
+
+pub fn sandboxed_lua_with(limits: LuaLimits) -> mlua::Result<Lua> {
+    let lua = Lua::new();
+    restrict_globals(&lua, &limits.allowed_globals)?;
+
+    lua.set_app_data(CallBudget { limits, instructions: 0, started: Instant::now(), exceeded: None });
+    lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), |lua, _debug| {
+        let Some(mut budget) = lua.app_data_mut::<CallBudget>() else {
+            return Ok(());
+        };
+
+        budget.instructions += HOOK_INTERVAL as u64;
+        budget.check().map_err(mlua::Error::external)
+    });
+    guard_protected_calls(&lua)?;
+
+    start_call(&lua)?;
+    Ok(lua)
+}
+
+// Gives the script a fresh budget, has to be called before running anything in a sandboxed state
+pub fn start_call(lua: &Lua) -> mlua::Result<()> {
+    let max_memory = {
+        let Some(mut budget) = lua.app_data_mut::<CallBudget>() else {
+            return Ok(());
+        };
+        budget.instructions = 0;
+        budget.started = Instant::now();
+        budget.exceeded = None;
+        budget.limits.max_memory
+    };
+
+    lua.set_memory_limit(lua.used_memory() + max_memory)?;
+    Ok(())
+}
+
+// pcall and xpcall would catch the error of a spent budget like any other, so
+// the script could go on forever. They raise it again once they return.
+fn guard_protected_calls(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    for name in ["pcall", "xpcall"] {
+        let Some(original) = globals.get::<_, Option<Function>>(name)? else {
+            continue;
+        };
+        let original = lua.create_registry_value(original)?;
+
+        let guarded = lua.create_function(move |lua, args: MultiValue| {
+            let results = lua.registry_value::<Function>(&original)?.call::<_, MultiValue>(args)?;
+
+            if let Some(err) = lua.app_data_ref::<CallBudget>().and_then(|budget| budget.exceeded.clone()) {
+                return Err(mlua::Error::external(err));
+            }
+            Ok(results)
+        })?;
+        globals.set(name, guarded)?;
+    }
+
+    Ok(())
+}
+
+// Removes every global that isn't allowed, libraries only keep their allowed functions
+fn restrict_globals(lua: &Lua, allowed: &[String]) -> mlua::Result<()> {
+    let globals = lua.globals();
+    let all: Vec<(String, Value)> = globals.clone().pairs::<String, Value>().collect::<mlua::Result<_>>()?;
+
+    for (name, value) in all {
+        if allowed.contains(&name) {
+            continue;
+        }
+
+        // e.g. "os.clock" keeps a table os with only clock in it
+        let prefix = format!("{}.", name);
+        let members: Vec<&str> = allowed.iter().filter_map(|a| a.strip_prefix(&prefix)).collect();
+        match value {
+            Value::Table(library) if !members.is_empty() => {
+                let restricted: Table = lua.create_table()?;
+                for member in members {
+                    restricted.set(member, library.get::<_, Value>(member)?)?;
+                }
+                globals.set(name, restricted)?;
+            },
+            _ => globals.set(name, Value::Nil)?,
+        }
+    }
+
+    Ok(())
+}
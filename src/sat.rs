@@ -0,0 +1,386 @@
+use std::collections::BinaryHeap;
+use std::ops::Not;
+
+// Conflicts before the first restart, later restarts follow the Luby sequence
+const RESTART_BASE: u64 = 100;
+
+const ACTIVITY_DECAY: f64 = 0.95;
+const ACTIVITY_RESCALE: f64 = 1e100;
+
+// A variable or its negation, stored as 2 * var + negated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Lit(u32);
+
+impl Lit {
+    pub fn new(var: usize, negated: bool) -> Self {
+        Lit((var as u32) << 1 | negated as u32)
+    }
+
+    pub fn var(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    pub fn is_negated(self) -> bool {
+        self.0 & 1 == 1
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Not for Lit {
+    type Output = Lit;
+
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SatResult {
+    // Value of every variable
+    Sat(Vec<bool>),
+    Unsat,
+    // The conflict limit was reached before an answer was found
+    Unknown,
+}
+
+// A small CDCL solver: two watched literals, first UIP learning, VSIDS and Luby restarts
+pub struct Solver {
+    clauses: Vec<Vec<Lit>>,
+    // Per literal the clauses that have to be looked at when it becomes false
+    watches: Vec<Vec<usize>>,
+    assigns: Vec<Option<bool>>,
+    level: Vec<usize>,
+    reason: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    trail_lim: Vec<usize>,
+    qhead: usize,
+    activity: Vec<f64>,
+    var_inc: f64,
+    // Variables ordered by activity, entries with an old activity are skipped
+    order: BinaryHeap<(u64, usize)>,
+    polarity: Vec<bool>,
+    // An empty clause was added or derived
+    unsat: bool,
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self {
+            clauses: Vec::new(),
+            watches: Vec::new(),
+            assigns: Vec::new(),
+            level: Vec::new(),
+            reason: Vec::new(),
+            trail: Vec::new(),
+            trail_lim: Vec::new(),
+            qhead: 0,
+            activity: Vec::new(),
+            var_inc: 1.0,
+            order: BinaryHeap::new(),
+            polarity: Vec::new(),
+            unsat: false,
+        }
+    }
+
+    pub fn new_var(&mut self) -> usize {
+        let var = self.assigns.len();
+        self.assigns.push(None);
+        self.level.push(0);
+        self.reason.push(None);
+        self.activity.push(0.0);
+        self.polarity.push(false);
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        self.order.push((0, var));
+        var
+    }
+
+    pub fn get_var_num(&self) -> usize {
+        self.assigns.len()
+    }
+
+    pub fn add_clause(&mut self, lits: &[Lit]) {
+        if self.unsat {
+            return;
+        }
+
+        let mut clause: Vec<Lit> = Vec::with_capacity(lits.len());
+        for &lit in lits {
+            // Satisfied clauses and clauses with x and ~x are dropped, false literals left out
+            match self.value(lit) {
+                Some(true) => return,
+                Some(false) => continue,
+                None => {},
+            }
+            if clause.contains(&!lit) {
+                return;
+            }
+            if !clause.contains(&lit) {
+                clause.push(lit);
+            }
+        }
+
+        match clause.len() {
+            0 => self.unsat = true,
+            1 => {
+                self.enqueue(clause[0], None);
+                if self.propagate().is_some() {
+                    self.unsat = true;
+                }
+            },
+            _ => {
+                self.attach(clause);
+            },
+        }
+    }
+
+    pub fn solve(&mut self, max_conflicts: u64) -> SatResult {
+        if self.unsat {
+            return SatResult::Unsat;
+        }
+
+        let mut conflicts = 0;
+        let mut restart = 1;
+        let mut until_restart = RESTART_BASE;
+
+        loop {
+            if let Some(conflict) = self.propagate() {
+                conflicts += 1;
+                if self.trail_lim.is_empty() {
+                    self.unsat = true;
+                    return SatResult::Unsat;
+                }
+                if conflicts >= max_conflicts {
+                    self.backtrack(0);
+                    return SatResult::Unknown;
+                }
+
+                let (learnt, back_level) = self.analyze(conflict);
+                self.backtrack(back_level);
+                if learnt.len() == 1 {
+                    self.enqueue(learnt[0], None);
+                }
+                else {
+                    let asserting = learnt[0];
+                    let index = self.attach(learnt);
+                    self.enqueue(asserting, Some(index));
+                }
+                self.var_inc /= ACTIVITY_DECAY;
+
+                until_restart -= 1;
+                if until_restart == 0 {
+                    restart += 1;
+                    until_restart = luby(restart) * RESTART_BASE;
+                    self.backtrack(0);
+                }
+                continue;
+            }
+
+            let Some(var) = self.pick_branch_var() else {
+                let model = self.assigns.iter().map(|v| v.unwrap_or(false)).collect();
+                self.backtrack(0);
+                return SatResult::Sat(model);
+            };
+            self.trail_lim.push(self.trail.len());
+            self.enqueue(Lit::new(var, !self.polarity[var]), None);
+        }
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.assigns[lit.var()].map(|v| v != lit.is_negated())
+    }
+
+    fn attach(&mut self, clause: Vec<Lit>) -> usize {
+        let index = self.clauses.len();
+        self.watches[(!clause[0]).index()].push(index);
+        self.watches[(!clause[1]).index()].push(index);
+        self.clauses.push(clause);
+        index
+    }
+
+    fn enqueue(&mut self, lit: Lit, reason: Option<usize>) {
+        let var = lit.var();
+        self.assigns[var] = Some(!lit.is_negated());
+        self.level[var] = self.trail_lim.len();
+        self.reason[var] = reason;
+        self.trail.push(lit);
+    }
+
+    // Unit propagation, returns the clause that became false if there is one
+    fn propagate(&mut self) -> Option<usize> {
+        while self.qhead < self.trail.len() {
+            let lit = self.trail[self.qhead];
+            self.qhead += 1;
+            let false_lit = !lit;
+
+            let watching = std::mem::take(&mut self.watches[lit.index()]);
+            let mut kept = Vec::with_capacity(watching.len());
+            let mut conflict = None;
+
+            for (i, &index) in watching.iter().enumerate() {
+                if conflict.is_some() {
+                    kept.extend_from_slice(&watching[i..]);
+                    break;
+                }
+
+                // The false literal goes to position 1
+                if self.clauses[index][0] == false_lit {
+                    self.clauses[index].swap(0, 1);
+                }
+                let first = self.clauses[index][0];
+                if self.value(first) == Some(true) {
+                    kept.push(index);
+                    continue;
+                }
+
+                let replacement = (2..self.clauses[index].len()).find(|&k| self.value(self.clauses[index][k]) != Some(false));
+                if let Some(k) = replacement {
+                    self.clauses[index].swap(1, k);
+                    let new_watch = self.clauses[index][1];
+                    self.watches[(!new_watch).index()].push(index);
+                    continue;
+                }
+
+                kept.push(index);
+                if self.value(first) == Some(false) {
+                    conflict = Some(index);
+                }
+                else {
+                    self.enqueue(first, Some(index));
+                }
+            }
+
+            self.watches[lit.index()] = kept;
+            if conflict.is_some() {
+                self.qhead = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    // First UIP learning, the asserting literal is the first one of the learnt clause
+    fn analyze(&mut self, conflict: usize) -> (Vec<Lit>, usize) {
+        let current = self.trail_lim.len();
+        let mut seen = vec![false; self.assigns.len()];
+        let mut learnt = vec![Lit(0)];
+        let mut open = 0;
+        let mut clause = conflict;
+        let mut index = self.trail.len();
+        let mut uip = None;
+
+        loop {
+            let lits = self.clauses[clause].clone();
+            let start = if uip.is_some() { 1 } else { 0 };
+            for &lit in &lits[start..] {
+                let var = lit.var();
+                if seen[var] || self.level[var] == 0 {
+                    continue;
+                }
+                seen[var] = true;
+                self.bump(var);
+                if self.level[var] == current {
+                    open += 1;
+                }
+                else {
+                    learnt.push(lit);
+                }
+            }
+
+            // Latest assigned literal of the current level that takes part in the conflict
+            loop {
+                index -= 1;
+                if seen[self.trail[index].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[index];
+            seen[lit.var()] = false;
+            open -= 1;
+            uip = Some(lit);
+            if open == 0 {
+                break;
+            }
+            // Its reason clause has it in position 0
+            clause = self.reason[lit.var()].unwrap();
+        }
+
+        learnt[0] = !uip.unwrap();
+
+        // Backjump to the second highest level, that literal is watched next to the asserting one
+        let mut back_level = 0;
+        if learnt.len() > 1 {
+            let max = (1..learnt.len()).max_by_key(|&i| self.level[learnt[i].var()]).unwrap();
+            learnt.swap(1, max);
+            back_level = self.level[learnt[1].var()];
+        }
+
+        (learnt, back_level)
+    }
+
+    fn bump(&mut self, var: usize) {
+        self.activity[var] += self.var_inc;
+        if self.activity[var] > ACTIVITY_RESCALE {
+            for activity in self.activity.iter_mut() {
+                *activity /= ACTIVITY_RESCALE;
+            }
+            self.var_inc /= ACTIVITY_RESCALE;
+            self.order = (0..self.assigns.len()).map(|v| (self.activity[v].to_bits(), v)).collect();
+        }
+        else {
+            self.order.push((self.activity[var].to_bits(), var));
+        }
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.trail_lim.len() <= level {
+            return;
+        }
+
+        let start = self.trail_lim[level];
+        for lit in self.trail.drain(start..) {
+            let var = lit.var();
+            self.assigns[var] = None;
+            self.reason[var] = None;
+            self.polarity[var] = !lit.is_negated();
+            self.order.push((self.activity[var].to_bits(), var));
+        }
+        self.trail_lim.truncate(level);
+        self.qhead = self.trail.len();
+    }
+
+    fn pick_branch_var(&mut self) -> Option<usize> {
+        // Activities are never negative, so their bit patterns sort like the values
+        while let Some((activity, var)) = self.order.pop() {
+            if self.assigns[var].is_none() && activity == self.activity[var].to_bits() {
+                return Some(var);
+            }
+        }
+        // Entries can go stale, fall back to any free variable
+        (0..self.assigns.len()).find(|&v| self.assigns[v].is_none())
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 1, 1, 2, 1, 1, 2, 4, 1, 1, 2, ...
+fn luby(i: u64) -> u64 {
+    let mut i = i;
+    loop {
+        let mut k = 1;
+        while (1 << k) - 1 < i {
+            k += 1;
+        }
+        if (1 << k) - 1 == i {
+            return 1 << (k - 1);
+        }
+        i -= (1 << (k - 1)) - 1;
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use uuid::Uuid;
+
+use crate::arena::GateArena;
+use crate::{Connection, GateError, GateInfo, Logic, LoopError};
+
+// A group of gates that has to be evaluated together.
+// Acyclic parts of a circuit end up as single gate components,
+// feedback loops (e.g. a NOR latch) end up in one cyclic component.
+struct Component {
+    nodes: Vec<usize>,
+    cyclic: bool,
+}
+
+// Evaluation order of a circuit, built once per topology
+pub struct Scheduler {
+    // Arena slot of every node
+    slots: Vec<usize>,
+    ids: Vec<Uuid>,
+    successors: Vec<Vec<usize>>,
+    // Per node the connections it is the destination of and the nodes driving them
+    fanin: Vec<Vec<(usize, usize)>>,
+    // Nodes with an input bit driven by more than one connection
+    multi_driven: Vec<bool>,
+    // Strongly connected components in topological order
+    components: Vec<Component>,
+    // Inputs each node saw the last time it was evaluated
+    last_inputs: Vec<Option<Vec<Logic>>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        gates: &GateArena,
+        inputs: &[Uuid],
+        gate_ids: &[Uuid],
+        outputs: &[Uuid],
+        connections: &[Connection],
+    ) -> Self {
+        let mut slots: Vec<usize> = Vec::new();
+        let mut ids: Vec<Uuid> = Vec::new();
+        let mut index_of: HashMap<Uuid, usize> = HashMap::new();
+
+        for id in inputs.iter().chain(gate_ids.iter()).chain(outputs.iter()) {
+            let Some(slot) = gates.slot_of(id) else {
+                continue;
+            };
+            index_of.entry(*id).or_insert_with(|| {
+                slots.push(slot);
+                ids.push(*id);
+                slots.len() - 1
+            });
+        }
+
+        let mut fanin = vec![Vec::new(); slots.len()];
+        let mut successors = vec![Vec::new(); slots.len()];
+
+        for (i, conn) in connections.iter().enumerate() {
+            let src = index_of.get(&conn.get_input_id());
+            let dest = index_of.get(&conn.get_output_id());
+
+            // Connections to gates that were never added to the circuit are ignored
+            if let (Some(&src), Some(&dest)) = (src, dest) {
+                fanin[dest].push((i, src));
+                successors[src].push(dest);
+            }
+        }
+
+        let multi_driven = fanin.iter().map(|conns: &Vec<(usize, usize)>| {
+            let mut driven = HashSet::new();
+            conns.iter().any(|&(c, _)| {
+                let conn = &connections[c];
+                (conn.get_dest_offset()..conn.get_dest_offset() + conn.get_width()).any(|bit| !driven.insert(bit))
+            })
+        }).collect();
+
+        let components = strongly_connected_components(&successors)
+            .into_iter()
+            .map(|nodes| {
+                let cyclic = nodes.len() > 1 || successors[nodes[0]].contains(&nodes[0]);
+                Component { nodes, cyclic }
+            })
+            .collect();
+
+        let last_inputs = vec![None; slots.len()];
+
+        Self {
+            slots,
+            ids,
+            successors,
+            fanin,
+            multi_driven,
+            components,
+            last_inputs,
+        }
+    }
+
+    // Runs one evaluation pass. Only gates whose inputs changed since their last
+    // evaluation are calculated again, stateful gates run exactly once per pass.
+    pub fn run(&mut self, gates: &mut GateArena, connections: &[Connection], max_iterations: usize) -> Result<(), Box<dyn Error>> {
+        let mut evaluated = vec![false; self.slots.len()];
+
+        for c in 0..self.components.len() {
+            let mut iterations = 0;
+
+            loop {
+                let mut changed = Vec::new();
+
+                for i in 0..self.components[c].nodes.len() {
+                    let node = self.components[c].nodes[i];
+
+                    self.pull_inputs(node, gates, connections);
+
+                    if self.needs_eval(node, gates, &evaluated) {
+                        self.eval(node, gates)?;
+                        evaluated[node] = true;
+                        changed.push(node);
+                    }
+                }
+
+                if changed.is_empty() || !self.components[c].cyclic {
+                    break;
+                }
+
+                iterations += 1;
+                if iterations >= max_iterations {
+                    let cycle = changed.iter().map(|&node| self.info(node, gates)).collect();
+                    return Err(Box::new(LoopError::Oscillation { cycle, iterations }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Cycles through stateless gates only, stateful gates break a loop
+    pub fn combinational_loops(&self, gates: &GateArena) -> Vec<Vec<GateInfo>> {
+        let stateless: Vec<bool> = self.slots.iter().map(|&slot| gates.slot(slot).compilable()).collect();
+
+        let successors: Vec<Vec<usize>> = self.successors.iter().enumerate()
+            .map(|(node, next)| {
+                if !stateless[node] {
+                    return Vec::new();
+                }
+                next.iter().copied().filter(|&n| stateless[n]).collect()
+            })
+            .collect();
+
+        strongly_connected_components(&successors)
+            .into_iter()
+            .filter(|nodes| nodes.len() > 1 || successors[nodes[0]].contains(&nodes[0]))
+            .map(|nodes| nodes.iter().map(|&node| self.info(node, gates)).collect())
+            .collect()
+    }
+
+    pub(crate) fn node_num(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Arena slot of the gate a node stands for
+    pub(crate) fn slot(&self, node: usize) -> usize {
+        self.slots[node]
+    }
+
+    pub(crate) fn successors(&self, node: usize) -> &[usize] {
+        &self.successors[node]
+    }
+
+    // Pulls the current values of all gates driving node
+    pub(crate) fn pull_inputs(&self, node: usize, gates: &mut GateArena, connections: &[Connection]) {
+        if self.multi_driven[node] {
+            self.pull_resolved(node, gates, connections);
+        }
+        else {
+            for &(c, src) in self.fanin[node].iter() {
+                let bits = connections[c].read_from(gates.slot(self.slots[src]));
+                connections[c].write_to(gates.slot_mut(self.slots[node]), bits);
+            }
+        }
+    }
+
+    pub(crate) fn info(&self, node: usize, gates: &GateArena) -> GateInfo {
+        GateInfo {
+            id: self.ids[node],
+            name: gates.slot(self.slots[node]).get_name(),
+        }
+    }
+
+    // Bits with several drivers get the resolved value, e.g. Z and 1 gives 1, 0 and 1 gives X
+    fn pull_resolved(&self, node: usize, gates: &mut GateArena, connections: &[Connection]) {
+        let mut values: Vec<Option<Logic>> = vec![None; gates.slot(self.slots[node]).get_input_num()];
+
+        for &(c, src) in self.fanin[node].iter() {
+            let conn = &connections[c];
+            for (i, value) in conn.read_from(gates.slot(self.slots[src])).into_iter().enumerate() {
+                let slot = &mut values[conn.get_dest_offset() + i];
+                *slot = Some(slot.map_or(value, |driven| driven.resolve(value)));
+            }
+        }
+
+        let gate = gates.slot_mut(self.slots[node]);
+        for (i, value) in values.into_iter().enumerate() {
+            if let Some(value) = value {
+                gate.set_input_value(i, value);
+            }
+        }
+    }
+
+    fn needs_eval(&self, node: usize, gates: &GateArena, evaluated: &[bool]) -> bool {
+        let gate = gates.slot(self.slots[node]);
+
+        if !gate.compilable() {
+            return !evaluated[node];
+        }
+
+        match &self.last_inputs[node] {
+            Some(last) => *last != gate.get_input_values(),
+            None => true,
+        }
+    }
+
+    fn eval(&mut self, node: usize, gates: &mut GateArena) -> Result<(), Box<dyn Error>> {
+        let result = {
+            let gate = gates.slot_mut(self.slots[node]);
+            self.last_inputs[node] = Some(gate.get_input_values());
+            gate.calculate()
+        };
+
+        result.map_err(|source| Box::new(GateError { gate: self.info(node, gates), source }) as Box<dyn Error>)
+    }
+}
+
+// Tarjan's algorithm, returns the components in topological order. The recursion
+// is kept on the heap, a long chain of gates would overflow the thread's stack.
+fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let node_num = successors.len();
+    let mut index: Vec<Option<usize>> = vec![None; node_num];
+    let mut lowlink = vec![0; node_num];
+    let mut on_stack = vec![false; node_num];
+    let mut stack = Vec::new();
+    let mut next_index = 0;
+    let mut components = Vec::new();
+
+    // Nodes being visited and how many of their successors were looked at
+    let mut calls: Vec<(usize, usize)> = Vec::new();
+
+    for root in 0..node_num {
+        if index[root].is_some() {
+            continue;
+        }
+        calls.push((root, 0));
+
+        while let Some((node, next)) = calls.last_mut() {
+            let node = *node;
+            if *next == 0 {
+                index[node] = Some(next_index);
+                lowlink[node] = next_index;
+                next_index += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+
+            if let Some(&succ) = successors[node].get(*next) {
+                *next += 1;
+                match index[succ] {
+                    None => calls.push((succ, 0)),
+                    Some(succ_index) if on_stack[succ] => lowlink[node] = lowlink[node].min(succ_index),
+                    _ => {}
+                }
+                continue;
+            }
+
+            calls.pop();
+            if let Some(&(parent, _)) = calls.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[node]);
+            }
+
+            if Some(lowlink[node]) == index[node] {
+                let mut component = Vec::new();
+                while let Some(top) = stack.pop() {
+                    on_stack[top] = false;
+                    component.push(top);
+                    if top == node {
+                        break;
+                    }
+                }
+                component.sort();
+                components.push(component);
+            }
+        }
+    }
+
+    // Tarjan finds sinks first
+    components.reverse();
+    components
+}
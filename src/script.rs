@@ -0,0 +1,85 @@
+use mlua::{Function, Lua, RegistryKey};
+
+use crate::sandbox::{sandboxed_lua_with, LuaLimits};
+use crate::LuaCode;
+
+// A component script loaded into its own lua state once. Calculate, OnClock and Draw
+// are kept in the registry and globals the script sets survive between calls.
+pub struct LuaScript {
+    code: LuaCode,
+    lua: Lua,
+    calculate: Option<RegistryKey>,
+    on_clock: Option<RegistryKey>,
+    draw: Option<RegistryKey>,
+    four_valued: bool,
+}
+
+impl LuaScript {
+    pub fn new(code: LuaCode) -> mlua::Result<Self> {
+        Self::with_limits(code, LuaLimits::default())
+    }
+
+    pub fn with_limits(code: LuaCode, limits: LuaLimits) -> mlua::Result<Self> {
+        let lua = sandboxed_lua_with(limits)?;
+        lua.load(&code.0).exec()?;
+
+        let (calculate, on_clock, draw, four_valued) = {
+            let globals = lua.globals();
+
+            // Check for needed variables
+            globals.get::<_, u8>("NUM_OF_INS")?;
+            globals.get::<_, u8>("NUM_OF_OUTS")?;
+
+            let function = |name: &str| -> mlua::Result<Option<RegistryKey>> {
+                globals.get::<_, Option<Function>>(name)?
+                    .map(|f| lua.create_registry_value(f))
+                    .transpose()
+            };
+
+            (
+                function("Calculate")?,
+                function("OnClock")?,
+                function("Draw")?,
+                // FOUR_VALUED components get "0", "1", "X" and "Z" instead of booleans
+                globals.get::<_, Option<bool>>("FOUR_VALUED")?.unwrap_or(false),
+            )
+        };
+
+        Ok(Self {
+            code,
+            lua,
+            calculate,
+            on_clock,
+            draw,
+            four_valued,
+        })
+    }
+
+    pub fn get_code(&self) -> &LuaCode {
+        &self.code
+    }
+
+    pub fn get_lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn is_four_valued(&self) -> bool {
+        self.four_valued
+    }
+
+    pub fn get_calculate(&self) -> mlua::Result<Option<Function<'_>>> {
+        self.function(&self.calculate)
+    }
+
+    pub fn get_on_clock(&self) -> mlua::Result<Option<Function<'_>>> {
+        self.function(&self.on_clock)
+    }
+
+    pub fn get_draw(&self) -> mlua::Result<Option<Function<'_>>> {
+        self.function(&self.draw)
+    }
+
+    fn function(&self, key: &Option<RegistryKey>) -> mlua::Result<Option<Function<'_>>> {
+        key.as_ref().map(|key| self.lua.registry_value(key)).transpose()
+    }
+}
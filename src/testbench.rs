@@ -0,0 +1,313 @@
+// Test benches for circuits and components written in lua. A bench drives the
+// pins by name and checks outputs, e.g.
+//
+//     alias("sum", "out_0")
+//     set("in_0", 1)
+//     set("in_1", "0110")
+//     eval()
+//     assert_eq("sum", 7, "1 + 6")
+//     tick(4)
+//
+// Pins are called in_N and out_N like in VCD and Verilog exports. Values are
+// booleans, unsigned numbers or strings of 0, 1, X and Z with the most significant bit first.
+use core::fmt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use mlua::{Lua, Value};
+
+use crate::sandbox::{sandboxed_lua_with, start_call, LuaLimits};
+use crate::{pin_offset, Logic, LogicGate};
+
+// A bench runs as one long call, it gets more room than a component script
+const BENCH_MAX_INSTRUCTIONS: u64 = 1_000_000_000;
+const BENCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFailure {
+    // Evaluations done before the failing check
+    pub step: u64,
+    pub line: Option<usize>,
+    pub message: String,
+    // Every pin and its value when the bench stopped
+    pub values: Vec<(String, String)>,
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}", self.step)?;
+        if let Some(line) = self.line {
+            write!(f, ", line {}", line)?;
+        }
+        write!(f, ": {}", self.message)?;
+        for (pin, value) in self.values.iter() {
+            write!(f, "\n    {} = {}", pin, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestReport {
+    pub steps: u64,
+    pub checks: usize,
+    pub failure: Option<TestFailure>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PinRef {
+    Input(usize),
+    Output(usize),
+}
+
+struct BenchState<'a> {
+    gate: &'a mut dyn LogicGate,
+    time: u64,
+    step: u64,
+    checks: usize,
+    aliases: HashMap<String, String>,
+    // Set by the bench functions, lua errors carry their position in the message instead
+    line: Option<usize>,
+    message: Option<String>,
+}
+
+impl BenchState<'_> {
+    fn find_pin(&self, name: &str) -> Result<PinRef, String> {
+        let pin_name = self.aliases.get(name).map_or(name, |pin| pin.as_str());
+        let unknown = || format!("Unknown pin {}", name);
+
+        let (pin, count) = if let Some(index) = pin_name.strip_prefix("in_") {
+            (PinRef::Input(index.parse().map_err(|_| unknown())?), self.gate.get_input_widths().len())
+        }
+        else if let Some(index) = pin_name.strip_prefix("out_") {
+            (PinRef::Output(index.parse().map_err(|_| unknown())?), self.gate.get_output_widths().len())
+        }
+        else {
+            return Err(unknown());
+        };
+
+        match pin {
+            PinRef::Input(i) | PinRef::Output(i) if i < count => Ok(pin),
+            _ => Err(unknown()),
+        }
+    }
+
+    fn find_input(&self, name: &str) -> Result<PinRef, String> {
+        match self.find_pin(name)? {
+            PinRef::Output(_) => Err(format!("{} is an output", name)),
+            pin => Ok(pin),
+        }
+    }
+
+    // First bit and width of a pin
+    fn bits(&self, pin: PinRef) -> (usize, usize) {
+        let (widths, index) = match pin {
+            PinRef::Input(i) => (self.gate.get_input_widths(), i),
+            PinRef::Output(i) => (self.gate.get_output_widths(), i),
+        };
+        (pin_offset(&widths, index), widths[index])
+    }
+
+    fn read(&self, pin: PinRef) -> Vec<Logic> {
+        let (offset, width) = self.bits(pin);
+        let values = match pin {
+            PinRef::Input(_) => self.gate.get_input_values(),
+            PinRef::Output(_) => self.gate.get_output_values(),
+        };
+        values[offset..offset + width].to_vec()
+    }
+
+    // A clocked component on its own has nobody to fire its edges, the bench does it like a circuit would
+    fn eval(&mut self) -> Result<(), String> {
+        self.step += 1;
+        self.gate.calculate().map_err(|err| err.to_string())?;
+        if let Some(edge) = self.gate.take_clock_edge() {
+            self.gate.on_clock(edge).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn pin_values(&self) -> Vec<(String, String)> {
+        let named = |pin: String| match self.aliases.iter().find(|(_, p)| **p == pin) {
+            Some((alias, _)) => format!("{} ({})", alias, pin),
+            None => pin,
+        };
+
+        let inputs = (0..self.gate.get_input_widths().len()).map(|i| (format!("in_{}", i), PinRef::Input(i)));
+        let outputs = (0..self.gate.get_output_widths().len()).map(|i| (format!("out_{}", i), PinRef::Output(i)));
+        inputs.chain(outputs)
+            .map(|(name, pin)| (named(name), bits_to_string(&self.read(pin))))
+            .collect()
+    }
+}
+
+// Most significant bit first
+fn bits_to_string(values: &[Logic]) -> String {
+    values.iter().rev().map(|v| v.to_char()).collect()
+}
+
+fn parse_value(value: &Value, width: usize) -> Result<Vec<Logic>, String> {
+    let from_number = |n: i64| -> Result<Vec<Logic>, String> {
+        if n < 0 || (width < 64 && n >> width != 0) {
+            return Err(format!("{} doesn't fit into {} bit(s)", n, width));
+        }
+        Ok((0..width).map(|i| Logic::from(i < 64 && n >> i & 1 == 1)).collect())
+    };
+
+    match value {
+        Value::Boolean(b) if width == 1 => Ok(vec![Logic::from(*b)]),
+        Value::Integer(n) => from_number(*n),
+        Value::Number(n) if n.fract() == 0.0 => from_number(*n as i64),
+        Value::String(s) => {
+            let s = s.to_str().map_err(|err| err.to_string())?;
+            let bits: Option<Vec<Logic>> = s.chars().rev().filter(|c| *c != '_').map(Logic::from_char).collect();
+            match bits {
+                Some(bits) if bits.len() == width => Ok(bits),
+                _ => Err(format!("\"{}\" isn't a {} bit value", s, width)),
+            }
+        },
+        _ => Err(format!("Can't use a {} as a {} bit value", value.type_name(), width)),
+    }
+}
+
+// Remembers where the bench failed and turns the message into a lua error
+fn fail(lua: &Lua, state: &RefCell<BenchState>, message: String) -> mlua::Error {
+    let mut state = state.borrow_mut();
+    state.line = lua.inspect_stack(1).map(|debug| debug.curr_line()).filter(|line| *line > 0).map(|line| line as usize);
+    state.message = Some(message.clone());
+    mlua::Error::RuntimeError(message)
+}
+
+// Runs a bench against a gate, failed checks and errors in the bench both end up in the report
+pub fn run_test_bench(gate: &mut dyn LogicGate, code: &str, name: &str) -> mlua::Result<TestReport> {
+    let limits = LuaLimits { max_instructions: BENCH_MAX_INSTRUCTIONS, timeout: BENCH_TIMEOUT, ..LuaLimits::default() };
+    let lua = sandboxed_lua_with(limits)?;
+
+    let state = RefCell::new(BenchState {
+        gate,
+        time: 0,
+        step: 0,
+        checks: 0,
+        aliases: HashMap::new(),
+        line: None,
+        message: None,
+    });
+
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+        let state = &state;
+
+        globals.set("alias", scope.create_function(move |lua, (alias, pin): (String, String)| {
+            let found = state.borrow().find_pin(&pin);
+            found.map_err(|err| fail(lua, state, err))?;
+            state.borrow_mut().aliases.insert(alias, pin);
+            Ok(())
+        })?)?;
+
+        globals.set("set", scope.create_function(move |lua, (pin, value): (String, Value)| {
+            let result = {
+                let mut s = state.borrow_mut();
+                s.find_input(&pin).and_then(|pin| {
+                    let (offset, width) = s.bits(pin);
+                    let values = parse_value(&value, width)?;
+                    for (i, v) in values.into_iter().enumerate() {
+                        s.gate.set_input_value(offset + i, v);
+                    }
+                    Ok(())
+                })
+            };
+            result.map_err(|err| fail(lua, state, err))
+        })?)?;
+
+        globals.set("get", scope.create_function(move |lua, pin: String| {
+            let found = state.borrow().find_pin(&pin);
+            let pin = found.map_err(|err| fail(lua, state, err))?;
+            Ok(bits_to_string(&state.borrow().read(pin)))
+        })?)?;
+
+        globals.set("eval", scope.create_function(move |lua, ()| {
+            let result = state.borrow_mut().eval();
+            result.map_err(|err| fail(lua, state, err))
+        })?)?;
+
+        // Advances the simulation time, clocks in a circuit follow it
+        globals.set("tick", scope.create_function(move |lua, ticks: Option<u64>| {
+            for _ in 0..ticks.unwrap_or(1) {
+                let result = {
+                    let mut s = state.borrow_mut();
+                    s.time += 1;
+                    let time = s.time;
+                    s.gate.set_time(time);
+                    s.eval()
+                };
+                result.map_err(|err| fail(lua, state, err))?;
+            }
+            Ok(())
+        })?)?;
+
+        // Pulses an input 0 -> 1 -> 0, for components that get their clock from outside
+        globals.set("clock", scope.create_function(move |lua, (pin, cycles): (String, Option<u64>)| {
+            let found = state.borrow().find_input(&pin);
+            let pin = found.map_err(|err| fail(lua, state, err))?;
+            for _ in 0..cycles.unwrap_or(1) {
+                for value in [true, false] {
+                    let result = {
+                        let mut s = state.borrow_mut();
+                        let (offset, _) = s.bits(pin);
+                        s.gate.set_input(offset, value);
+                        s.eval()
+                    };
+                    result.map_err(|err| fail(lua, state, err))?;
+                }
+            }
+            Ok(())
+        })?)?;
+
+        globals.set("time", scope.create_function(move |_, ()| Ok(state.borrow().time))?)?;
+
+        globals.set("assert_eq", scope.create_function(move |lua, (pin, expected, message): (String, Value, Option<String>)| {
+            let check = {
+                let mut s = state.borrow_mut();
+                s.checks += 1;
+                s.find_pin(&pin).and_then(|p| {
+                    let actual = s.read(p);
+                    let expected = parse_value(&expected, actual.len())?;
+                    if actual == expected {
+                        return Ok(());
+                    }
+                    let mut err = format!("{} is {}, expected {}", pin, bits_to_string(&actual), bits_to_string(&expected));
+                    if let Some(message) = &message {
+                        err = format!("{} ({})", err, message);
+                    }
+                    Err(err)
+                })
+            };
+            check.map_err(|err| fail(lua, state, err))
+        })?)?;
+
+        start_call(&lua)?;
+        lua.load(code).set_name(name).exec()
+    });
+
+    let state = state.into_inner();
+    let failure = result.err().map(|err| TestFailure {
+        step: state.step,
+        line: state.line,
+        // Plain lua errors already say where they happened
+        message: state.message.clone().unwrap_or_else(|| err.to_string().lines().next().unwrap_or_default().to_string()),
+        values: state.pin_values(),
+    });
+
+    Ok(TestReport {
+        steps: state.step,
+        checks: state.checks,
+        failure,
+    })
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::arena::GateArena;
+use crate::scheduler::Scheduler;
+use crate::{Connection, GateError, Logic, LoopError};
+
+// Slots of the timing wheel, events further in the future wait in the overflow map
+const WHEEL_SIZE: usize = 256;
+
+// New output values of one node
+struct Event {
+    node: usize,
+    outputs: Vec<Logic>,
+}
+
+// Discrete event simulation, a gate's new outputs take effect get_delay() ticks after
+// its inputs changed. Delays are transport delays, so even pulses shorter than the
+// delay make it through, which is what shows glitches and hazards.
+pub struct EventScheduler {
+    graph: Scheduler,
+    now: u64,
+    started: bool,
+    wheel: Vec<Vec<Event>>,
+    overflow: BTreeMap<u64, Vec<Event>>,
+    pending: usize,
+    // Outputs a node will have once all of its pending events happened
+    projected: Vec<Vec<Logic>>,
+    // Outputs the fanout of a node last reacted to
+    last_outputs: Vec<Vec<Logic>>,
+}
+
+impl EventScheduler {
+    pub fn new(graph: Scheduler, gates: &GateArena) -> Self {
+        let node_num = graph.node_num();
+        let projected = (0..node_num).map(|node| gates.slot(graph.slot(node)).get_output_values()).collect();
+
+        Self {
+            graph,
+            now: 0,
+            started: false,
+            wheel: (0..WHEEL_SIZE).map(|_| Vec::new()).collect(),
+            overflow: BTreeMap::new(),
+            pending: 0,
+            projected,
+            last_outputs: vec![Vec::new(); node_num],
+        }
+    }
+
+    pub fn get_time(&self) -> u64 {
+        self.now
+    }
+
+    pub fn pending_events(&self) -> usize {
+        self.pending
+    }
+
+    // Processes every time step with events up to and including time. Changes made
+    // from outside, like a new input value or a clock edge, are picked up at time itself.
+    pub fn run_until(&mut self, time: u64, gates: &mut GateArena, connections: &[Connection], max_iterations: usize) -> Result<(), Box<dyn Error>> {
+        let all: Vec<usize> = (0..self.graph.node_num()).collect();
+
+        // Every gate runs once at the start, even the ones nothing drives
+        if !self.started {
+            self.started = true;
+            self.now = time;
+            for &node in all.iter() {
+                self.graph.pull_inputs(node, gates, connections);
+                self.eval(node, gates)?;
+            }
+            return self.step(all, gates, connections, max_iterations);
+        }
+
+        while self.now < time {
+            self.now = if self.pending == 0 { time } else { self.now + 1 };
+
+            let changed = self.apply_events(gates);
+            if self.now < time {
+                self.step(changed, gates, connections, max_iterations)?;
+            }
+        }
+
+        // Like in the instant scheduler stateful gates run once per step, and so do
+        // nested circuits that still have events of their own coming up
+        for &node in all.iter() {
+            let gate = gates.slot(self.graph.slot(node));
+            if !gate.compilable() || gate.pending_events() > 0 {
+                self.graph.pull_inputs(node, gates, connections);
+                self.eval(node, gates)?;
+            }
+        }
+
+        // Every node is checked for changes made from outside, e.g. a new input value
+        self.step(all, gates, connections, max_iterations)
+    }
+
+    fn schedule(&mut self, time: u64, event: Event) {
+        if ((time - self.now) as usize) < WHEEL_SIZE {
+            self.wheel[time as usize % WHEEL_SIZE].push(event);
+        }
+        else {
+            self.overflow.entry(time).or_default().push(event);
+        }
+        self.pending += 1;
+    }
+
+    // Sets the outputs of every event due now and returns the nodes they belong to
+    fn apply_events(&mut self, gates: &mut GateArena) -> Vec<usize> {
+        let mut events = self.overflow.remove(&self.now).unwrap_or_default();
+        events.append(&mut self.wheel[self.now as usize % WHEEL_SIZE]);
+        self.pending -= events.len();
+
+        events.into_iter().map(|event| {
+            let gate = gates.slot_mut(self.graph.slot(event.node));
+            for (i, value) in event.outputs.into_iter().enumerate() {
+                gate.set_output_value(i, value);
+            }
+            event.node
+        }).collect()
+    }
+
+    // Evaluates everything downstream of the changed nodes within the current time step.
+    // Zero delay gates change right away and may need several rounds to settle.
+    fn step(&mut self, mut changed: Vec<usize>, gates: &mut GateArena, connections: &[Connection], max_iterations: usize) -> Result<(), Box<dyn Error>> {
+        let mut iterations = 0;
+
+        loop {
+            let mut dirty = vec![false; self.graph.node_num()];
+            for node in changed.drain(..) {
+                let outputs = gates.slot(self.graph.slot(node)).get_output_values();
+                if outputs != self.last_outputs[node] {
+                    self.last_outputs[node] = outputs;
+                    for &next in self.graph.successors(node) {
+                        dirty[next] = true;
+                    }
+                }
+            }
+
+            let dirty: Vec<usize> = (0..dirty.len()).filter(|&node| dirty[node]).collect();
+            if dirty.is_empty() {
+                return Ok(());
+            }
+
+            iterations += 1;
+            if iterations > max_iterations {
+                let cycle = dirty.iter().map(|&node| self.graph.info(node, gates)).collect();
+                return Err(Box::new(LoopError::Oscillation { cycle, iterations }));
+            }
+
+            for node in dirty {
+                self.graph.pull_inputs(node, gates, connections);
+                if self.eval(node, gates)? {
+                    changed.push(node);
+                }
+            }
+        }
+    }
+
+    // Calculates a node, returns true if its outputs changed right away
+    fn eval(&mut self, node: usize, gates: &mut GateArena) -> Result<bool, Box<dyn Error>> {
+        let result = {
+            let gate = gates.slot_mut(self.graph.slot(node));
+            let before = gate.get_output_values();
+
+            let result = gate.calculate().and_then(|_| match gate.take_clock_edge() {
+                Some(edge) => gate.on_clock(edge),
+                None => Ok(()),
+            });
+
+            let delay = gate.get_delay();
+            let outputs = gate.get_output_values();
+            if delay > 0 {
+                // The new values only show up once the event happens
+                for (i, value) in before.into_iter().enumerate() {
+                    gate.set_output_value(i, value);
+                }
+            }
+            result.map(|_| (delay, outputs))
+        };
+
+        let (delay, outputs) = result.map_err(|source| Box::new(GateError { gate: self.graph.info(node, gates), source }) as Box<dyn Error>)?;
+
+        if delay == 0 {
+            self.projected[node] = outputs;
+            return Ok(true);
+        }
+
+        if outputs != self.projected[node] {
+            self.projected[node] = outputs.clone();
+            self.schedule(self.now + delay, Event { node, outputs });
+        }
+
+        Ok(false)
+    }
+}
@@ -1,9 +1,16 @@
-use std::{cell::{Ref, RefCell}, rc::Rc};
+use std::error::Error;
+use std::path::Path;
 use egui_sdl2_gl::egui::{self as egui, Color32, InputState, Response, Stroke};
 use uuid::Uuid;
-use crate::{ui::drawable_gate::DrawableGate, Circuit, LogicGate};
+use crate::{ui::drawable_gate::DrawableGate, Circuit, Logic, LogicGate};
+use crate::circuit_file::{restore_memory, CircuitDocument, CircuitFileError, GateLayout, GateRole, GateSource};
+use crate::component::{derive_symbol, ComponentInfo, Symbol};
+use crate::reload::reload_lua_gates;
 
-use super::{drawable_connection::DrawableConnection, drawable_gate::InOutPosition, event_queue::{CanvasEvent, EventQueue, GateEvent}, gate_list::GhostGate};
+use crate::vcd::Pin;
+
+use super::{drawable_connection::DrawableConnection, drawable_gate::{GateFiles, InOutPosition}, event_queue::{CanvasEvent, EventQueue, GateEvent}, gate_list::GhostGate};
+use super::waveform::{Probe, WaveformPanel};
 
 const MAX_ZOOM: f32 = 20.0;
 const MIN_ZOOM: f32 = 0.3;
@@ -13,13 +20,23 @@ pub const GRID_SPACING: f32 = 20.0;
 pub struct Canvas {
     pan_offset: egui::Vec2, // Current pan offset
     zoom: f32, // Current zoom level
-    gates: Vec<Rc<RefCell<Box<DrawableGate>>>>, // List of gates on the canvas
+    gates: Vec<DrawableGate>, // List of gates on the canvas
     to_spawn: Option<GhostGate>,
     connections: Vec<DrawableConnection>,
     underlying_circuit: Circuit,
     selected_input: Option<(InOutPosition, Uuid)>,
     selected_output: Option<(InOutPosition, Uuid)>,
     events: EventQueue,
+    sim_error: Option<String>,
+    // Why the last connection attempt failed, e.g. mismatched bus widths
+    connect_error: Option<String>,
+    // Why the last gate couldn't be placed, e.g. its script broke after the button was made
+    spawn_error: Option<String>,
+    waveform: WaveformPanel,
+    // Shows a circuit nested in another canvas, it can be looked at but not changed
+    instance_view: bool,
+    // Circuit gate that was double clicked and should be opened in its own tab
+    open_subcircuit: Option<Uuid>,
 }
 
 impl Canvas {
@@ -34,6 +51,12 @@ impl Canvas {
             selected_input: None,
             selected_output: None,
             events: EventQueue::new(),
+            sim_error: None,
+            connect_error: None,
+            spawn_error: None,
+            waveform: WaveformPanel::new(),
+            instance_view: false,
+            open_subcircuit: None,
         }
     }
 
@@ -49,74 +72,153 @@ impl Canvas {
         self.zoom
     }
 
-    pub fn get_gate_by_id(&self, id: &Uuid) -> Option<Rc<RefCell<Box<DrawableGate>>>> {
-        self.gates.iter().find(|gate| gate.borrow().id == *id).cloned()
+    pub fn get_name(&self) -> String {
+        self.underlying_circuit.get_name()
     }
-    
-    pub fn add_gate(&mut self, gate: DrawableGate) {
-        let gate_rc = Rc::new(RefCell::new(Box::new(gate)));
-        let id = gate_rc.borrow().id.clone();
 
-        let name = gate_rc.borrow().gate.borrow().get_name();
-        if name == "BUTTON" {
-            self.underlying_circuit.add_input(gate_rc.borrow().gate.clone());
-        }
-        else if name == "LAMP" {
-            self.underlying_circuit.add_output(gate_rc.borrow().gate.clone());
-        }
-        else {
-            self.underlying_circuit.add_gate(gate_rc.borrow().gate.clone(), id);
-        }
+    pub fn get_circuit(&self) -> &Circuit {
+        &self.underlying_circuit
+    }
+
+    pub fn get_circuit_mut(&mut self) -> &mut Circuit {
+        &mut self.underlying_circuit
+    }
 
-        self.gates.push(gate_rc.clone());
+    pub fn set_instance_view(&mut self, instance_view: bool) {
+        self.instance_view = instance_view;
     }
 
-    pub fn add_connection(&mut self, connection: DrawableConnection) {
+    // Swaps in the live instance for a frame, the gates keep their ids so the
+    // drawables still fit. Has to be swapped back before the owner simulates.
+    pub fn swap_circuit(&mut self, circuit: &mut Circuit) {
+        std::mem::swap(&mut self.underlying_circuit, circuit);
+    }
+
+    pub fn take_open_subcircuit(&mut self) -> Option<Uuid> {
+        self.open_subcircuit.take()
+    }
+
+    pub fn get_gate_by_id(&self, id: &Uuid) -> Option<&DrawableGate> {
+        self.gates.iter().find(|gate| gate.id == *id)
+    }
+
+    // The drawable and the logic gate share the id, the gate goes into the underlying circuit
+    pub fn add_gate(&mut self, drawable: DrawableGate, gate: Box<dyn LogicGate>) {
+        let role = match gate.get_name().as_str() {
+            "BUTTON" => GateRole::Input,
+            "LAMP" => GateRole::Output,
+            _ => GateRole::Gate,
+        };
+        self.add_gate_as(drawable, gate, role);
+    }
+
+    pub fn add_gate_as(&mut self, drawable: DrawableGate, gate: Box<dyn LogicGate>, role: GateRole) {
+        let id = drawable.id;
+
+        match role {
+            GateRole::Input => self.underlying_circuit.add_input(gate, id),
+            GateRole::Output => self.underlying_circuit.add_output(gate, id),
+            GateRole::Gate => self.underlying_circuit.add_gate(gate, id),
+        };
+
+        self.gates.push(drawable);
+        self.restart_recording();
+    }
+
+    pub fn add_connection(&mut self, mut connection: DrawableConnection) -> Result<(), Box<dyn Error>> {
         // Now, connect the corresponding gates in the underlying circuit
-        if let (Some(input_gate), Some(output_gate)) = (&connection.input_gate, &connection.output_gate) {
+        if let (Some(input_id), Some(output_id)) = (connection.input_gate, connection.output_gate) {
+            let input_gate = self.get_gate_by_id(&input_id).ok_or("Unknown gate")?;
+            let output_gate = self.get_gate_by_id(&output_id).ok_or("Unknown gate")?;
+
             // Assuming `DrawableConnection` holds the indexes for input/output
             // If not, you'll need to determine these based on your logic
             let input_index = connection.in_num.clone(); // Default to 0 or determine based on your logic
             let output_index = connection.out_num.clone(); // Default to 0 or determine based on your logic
 
             // Get indexes of the input/output gates
-            let in_index = input_gate.borrow().outputs_pos.iter().position(|pos| pos.get() == output_index.get()).unwrap();
-            let out_index = output_gate.borrow().inputs_pos.iter().position(|pos| pos.get() == input_index.get()).unwrap();
+            let in_index = input_gate.outputs_pos.iter().position(|pos| pos.get() == output_index.get())
+                .ok_or("No output at this position")?;
+            let out_index = output_gate.inputs_pos.iter().position(|pos| pos.get() == input_index.get())
+                .ok_or("No input at this position")?;
 
             // Call the connect method on the underlying circuit with the gates and their indexes
-            self.underlying_circuit.connect(input_gate.borrow().gate.clone(), in_index, output_gate.borrow().gate.clone(), out_index);
+            // Fails if the pins have different bit widths
+            self.underlying_circuit.connect(input_id, in_index, output_id, out_index)?;
+
+            connection.width = self.underlying_circuit.get_gate(&input_id).map_or(1, |gate| gate.get_output_widths()[in_index]);
         }
 
         // Add the DrawableConnection to the list of connections
         self.connections.push(connection);
+        self.connect_error = None;
+        self.restart_recording();
+
+        Ok(())
     }
 
     pub fn remove_selected(&mut self) {
-        // First, collect Rc pointers to the selected gates.
-        let removed_gates: Vec<Rc<RefCell<Box<DrawableGate>>>> = self.gates.iter()
-            .filter(|gate| gate.borrow().selected)
-            .cloned()  // Clone the Rc pointers, not the gates themselves.
+        // First, collect the ids of the selected gates.
+        let removed_gates: Vec<Uuid> = self.gates.iter()
+            .filter(|gate| gate.selected)
+            .map(|gate| gate.id)
             .collect(); 
     
         // Remove the selected gates.
-        self.gates.retain(|gate| !gate.borrow().selected);
+        self.gates.retain(|gate| !gate.selected);
+
+        for id in removed_gates.iter() {
+            self.underlying_circuit.remove_gate(id);
+            self.waveform.remove_gate(id);
+        }
     
         // Remove connections associated with the removed gates.
         self.connections.retain(|connection| {
             // Check if the input_gate or output_gate of the connection is among the removed gates.
             let input_gate_linked = connection.input_gate.as_ref()
-                .map_or(false, |input_gate| removed_gates.iter().any(|rg| Rc::ptr_eq(rg, input_gate)));
+                .map_or(false, |input_gate| removed_gates.contains(input_gate));
             let output_gate_linked = connection.output_gate.as_ref()
-                .map_or(false, |output_gate| removed_gates.iter().any(|rg| Rc::ptr_eq(rg, output_gate)));
+                .map_or(false, |output_gate| removed_gates.contains(output_gate));
     
             // Retain the connection only if neither its input_gate nor output_gate was removed.
             !input_gate_linked && !output_gate_linked
         });
+
+        self.restart_recording();
+    }
+
+    // A recording keeps the signals it started with, so a changed circuit starts a new one
+    fn restart_recording(&mut self) {
+        if self.underlying_circuit.get_recording().is_some() {
+            self.underlying_circuit.start_recording();
+        }
+    }
+
+    pub fn toggle_waveform(&mut self) {
+        self.waveform.set_open(!self.waveform.get_open());
+    }
+
+    // Shows a pin of a gate on the canvas in the waveform panel
+    fn add_probe(&mut self, id: &Uuid, pin_pos: &InOutPosition, output: bool) {
+        let (Some(gate), Some(logic)) = (self.get_gate_by_id(id), self.underlying_circuit.get_gate(id)) else {
+            return;
+        };
+
+        let (positions, prefix) = if output { (&gate.outputs_pos, "out") } else { (&gate.inputs_pos, "in") };
+        let Some(index) = positions.iter().position(|pos| pos.get() == pin_pos.get()) else {
+            return;
+        };
+
+        self.waveform.add_probe(Probe {
+            label: format!("{}.{}_{}", logic.get_name(), prefix, index),
+            gate_id: gate.id,
+            pin: if output { Pin::Output(index) } else { Pin::Input(index) },
+        });
     }
     
     pub fn unselect_all(&mut self) {
-        for gate_rc in &self.gates {
-            gate_rc.borrow_mut().selected = false;
+        for gate in self.gates.iter_mut() {
+            gate.selected = false;
         }
     }
 
@@ -127,10 +229,169 @@ impl Canvas {
     pub fn jump_to(&mut self, x: f32, y: f32) {
         self.pan_offset = egui::Vec2::new(x, y);
     }
+
+    // The underlying circuit plus where every gate sits on the canvas
+    pub fn to_document(&self) -> Result<CircuitDocument, CircuitFileError> {
+        let mut doc = self.underlying_circuit.to_document()?;
+
+        for entry in doc.gates.iter_mut() {
+            if let Some(gate) = self.get_gate_by_id(&entry.id) {
+                entry.layout = Some(GateLayout { pos: gate.pos, size: gate.size });
+
+                // Placed components keep their pins where they were
+                if let GateSource::Circuit { circuit } = &mut entry.source {
+                    circuit.symbol = Some(Symbol {
+                        width: (gate.size.0 / GRID_SPACING).round() as u16,
+                        height: (gate.size.1 / GRID_SPACING).round() as u16,
+                        inputs_pos: gate.inputs_pos.iter().map(|pos| pos.get()).collect(),
+                        outputs_pos: gate.outputs_pos.iter().map(|pos| pos.get()).collect(),
+                    });
+                }
+            }
+        }
+
+        Ok(doc)
+    }
+
+    pub fn from_document(ctx: &egui::Context, doc: &CircuitDocument) -> Result<Self, Box<dyn Error>> {
+        let mut canvas = Canvas::new(&doc.name);
+
+        for entry in doc.gates.iter() {
+            let Some(layout) = entry.layout else {
+                return Err(format!("Gate {} ({}) can't be placed on a canvas", entry.name, entry.id).into());
+            };
+
+            let mut gate = entry.source.build(entry.name.clone())?;
+            restore_memory(&mut gate, &entry.memory);
+
+            let ghost = match &entry.source {
+                GateSource::Lua { path } => {
+                    let files = GateFiles::new(path.clone().into_boxed_path(), None);
+                    let props = files.read_props()?;
+
+                    GhostGate {
+                        gate,
+                        files: Some(files),
+                        inputs_pos: props.inputs_pos,
+                        outputs_pos: props.outputs_pos,
+                        size: layout.size,
+                        info: props.info,
+                    }
+                },
+                GateSource::Circuit { circuit } => {
+                    let symbol = circuit.symbol.clone().unwrap_or_else(|| derive_symbol(circuit));
+                    GhostGate::from_component(gate, &symbol)
+                },
+                // The ports of a component
+                GateSource::Bus { .. } => {
+                    let (inputs_pos, outputs_pos) = bus_pins(entry.role, layout.size);
+                    GhostGate { gate, files: None, inputs_pos, outputs_pos, size: layout.size, info: ComponentInfo::default() }
+                },
+                _ => return Err(format!("Gate {} ({}) can't be placed on a canvas", entry.name, entry.id).into()),
+            };
+
+            let mut drawable = DrawableGate::from_ghost(ctx, &ghost, layout.pos, layout.size);
+            drawable.id = entry.id;
+            canvas.add_gate_as(drawable, ghost.gate, entry.role);
+        }
+
+        for conn in doc.connections.iter() {
+            let (Some(src), Some(dest)) = (canvas.get_gate_by_id(&conn.src), canvas.get_gate_by_id(&conn.dest)) else {
+                return Err(format!("Connection between unknown gates {} and {}", conn.src, conn.dest).into());
+            };
+
+            let out_num = src.outputs_pos.get(conn.src_index).cloned()
+                .ok_or_else(|| format!("Gate {} has no output {}", conn.src, conn.src_index))?;
+            let in_num = dest.inputs_pos.get(conn.dest_index).cloned()
+                .ok_or_else(|| format!("Gate {} has no input {}", conn.dest, conn.dest_index))?;
+
+            let connection = DrawableConnection::with_gates(
+                src.get_pos_of_in_out(out_num.clone(), canvas.zoom, canvas.pan_offset),
+                dest.get_pos_of_in_out(in_num.clone(), canvas.zoom, canvas.pan_offset),
+                in_num,
+                out_num,
+                Color32::WHITE,
+                conn.src,
+                conn.dest,
+                Uuid::new_v4()
+            );
+
+            canvas.add_connection(connection)?;
+        }
+
+        Ok(canvas)
+    }
+
+    // Loads an edited lua component again into every gate placed from it. A gate
+    // whose script doesn't load keeps the old one and shows why.
+    pub fn reload_script(&mut self, script: &Path) {
+        let mut reloaded = Vec::new();
+
+        for gate in reload_lua_gates(&mut self.underlying_circuit, script) {
+            // Gates inside placed components are drawn by the tab that opens them
+            let [id] = gate.path[..] else {
+                continue;
+            };
+            let Some(drawable) = self.gates.iter_mut().find(|drawable| drawable.id == id) else {
+                continue;
+            };
+
+            let props = gate.result.and_then(|_| {
+                let files = drawable.files.as_ref().ok_or("Gate has no script")?;
+                files.read_props().map_err(|e| e.to_string())
+            });
+            match props {
+                Ok(props) => {
+                    drawable.apply_props(&props);
+                    reloaded.push(id);
+                },
+                Err(e) => drawable.reload_error = Some(e),
+            }
+        }
+
+        if reloaded.is_empty() {
+            return;
+        }
+
+        // The pins may have moved, so the wires of the reloaded gates are drawn again
+        // from the connections the circuit kept
+        let touches = |src: Option<Uuid>, dest: Option<Uuid>| reloaded.iter().any(|id| Some(*id) == src || Some(*id) == dest);
+        self.connections.retain(|conn| !touches(conn.input_gate, conn.output_gate));
+
+        for conn in self.underlying_circuit.get_connections() {
+            let (src_id, dest_id) = (conn.get_input_id(), conn.get_output_id());
+            if !touches(Some(src_id), Some(dest_id)) {
+                continue;
+            }
+            let (Some(src), Some(dest)) = (self.get_gate_by_id(&src_id), self.get_gate_by_id(&dest_id)) else {
+                continue;
+            };
+            let (Some(out_num), Some(in_num)) = (src.outputs_pos.get(conn.get_input_index()), dest.inputs_pos.get(conn.get_output_index())) else {
+                continue;
+            };
+
+            let mut connection = DrawableConnection::with_gates(
+                src.get_pos_of_in_out(out_num.clone(), self.zoom, self.pan_offset),
+                dest.get_pos_of_in_out(in_num.clone(), self.zoom, self.pan_offset),
+                in_num.clone(),
+                out_num.clone(),
+                Color32::WHITE,
+                src_id,
+                dest_id,
+                Uuid::new_v4()
+            );
+            connection.width = conn.get_width();
+            self.connections.push(connection);
+        }
+
+        self.restart_recording();
+    }
 }
 
 impl Canvas {
     pub fn update(&mut self, ctx: &egui::Context) {
+        self.waveform.show(ctx, &self.underlying_circuit);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             let (response, painter) = ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
             ctx.input(|i| {
@@ -143,14 +404,36 @@ impl Canvas {
             let pan_offset = self.get_pan_offset();
 
             for conn in self.connections.iter_mut() {
-                conn.update(zoom, pan_offset);
+                conn.update(&self.gates, zoom, pan_offset);
+                conn.color = signal_color(&self.underlying_circuit, &self.gates, conn);
             }
 
             self.draw(ctx, ui,&painter, response.rect);
         
         });
 
-        self.underlying_circuit.calculate().unwrap();
+        self.simulate();
+    }
+
+    // Runs this frame's step of the circuit, needs no ui so canvases in the
+    // background can do it on their own thread
+    pub fn simulate(&mut self) {
+        // The canvas that owns the circuit simulates it
+        if self.instance_view {
+            return;
+        }
+
+        if self.waveform.wants_recording() && self.underlying_circuit.get_recording().is_none() {
+            self.underlying_circuit.start_recording();
+        }
+
+        // A circuit that doesn't settle shouldn't take the whole ui down
+        let result = if self.waveform.take_step() {
+            self.underlying_circuit.tick()
+        } else {
+            self.underlying_circuit.calculate()
+        };
+        self.sim_error = result.err().map(|e| e.to_string());
     }
 
     fn get_events(&mut self, res: &Response, input: &InputState) {
@@ -171,11 +454,12 @@ impl Canvas {
 
             let mut dragged_gate = false;
             let mut clicked_in_out = false;
+            let mut right_clicked_pin = false;
 
             // Assuming `is_gate_dragging` is a boolean field in your struct initialized to `false`
             if let Some(ptr) = res.interact_pointer_pos() {
                 for g in self.gates.iter() {
-                    if let Some(event) = g.borrow_mut().get_events(res, ptr, self.pan_offset, self.zoom) {
+                    if let Some(event) = g.get_events(res, ptr, self.pan_offset, self.zoom) {
                         if let GateEvent::MovedGate { id, from, to, start } = &event {
                             dragged_gate = true; // Indicate that a gate is being dragged
                         }
@@ -185,12 +469,23 @@ impl Canvas {
                         if let GateEvent::ClickedOut { num, id } = &event {
                             clicked_in_out = true;
                         }
+                        if let GateEvent::RightClickedIn { .. } | GateEvent::RightClickedOut { .. } = &event {
+                            right_clicked_pin = true;
+                        }
 
                         self.events.add_event(CanvasEvent::GateEvent(event));
                     }
                 }
             }
 
+            if res.secondary_clicked() && !right_clicked_pin {
+                if let Some(ptr) = res.interact_pointer_pos() {
+                    if let Some(conn) = self.connections.iter().find(|c| c.contains_point(ptr, self.pan_offset)) {
+                        self.events.add_event(CanvasEvent::RightClickedConnection { id: conn.id });
+                    }
+                }
+            }
+
             let mut doubley = false;
             if res.double_clicked() {
                 doubley = true;
@@ -216,36 +511,12 @@ impl Canvas {
                         let x_pan = (adjusted_pan_x / GRID_SPACING).round() * GRID_SPACING;
                         let y_pan = (adjusted_pan_y / GRID_SPACING).round() * GRID_SPACING;
                     
-                        // TODO
-                        // Implement error handling when a property is read wronlgy
-                        // that includes not reading at all because not present 
-                        // and wrong len of inputs_pos in comparison to num_ins
-                        // and wrong len of outputs_pos in comparison to num_outs
-                        // auch checken ob die inputs und outputs pos in den richtigen grenzen liegen
-                        // auch checken ob inputs und outputs gleihce einträge haben
-                        let width_o = gate.files.read_props().unwrap().width;
-                        let height_o = gate.files.read_props().unwrap().height;
-                        let width;
-                        let height;
-                        if let Some(width_s) = width_o {
-                            width = width_s as f32 * GRID_SPACING;
-                        }
-                        else {
-                            width = 3.0 * GRID_SPACING;
-                        }
-
-                        if let Some(height_s) = height_o {
-                            height = height_s as f32 * GRID_SPACING;
-                        }
-                        else {
-                            height = 2.0 * GRID_SPACING;
-                        }
-                    
+                        let size = gate.size;
                         self.events.add_event(
                             CanvasEvent::SpawnGate {
-                                gate: gate.to_owned(),
+                                gate,
                                 pos: (x - x_pan, y - y_pan),
-                                size: (width, height),
+                                size,
                             }
                         );
                     }
@@ -274,56 +545,81 @@ impl Canvas {
 
     fn process_events(&mut self, ctx: &egui::Context) {
         let mut event_to_add: Option<CanvasEvent> = None;
+        let mut probe_to_add: Option<(Uuid, InOutPosition, bool)> = None;
 
         let current_event = self.events.get_current();
 
         if let Some(event) = current_event {
             match event {
+                // Changes to a nested circuit would only reach this one instance, not its component
+                CanvasEvent::SpawnGate { .. } | CanvasEvent::RemoveSelected | CanvasEvent::AddConnection { .. } | CanvasEvent::SplitterClicked { .. } if self.instance_view => {}
                 CanvasEvent::SpawnGate { gate, pos, size } => {
-                    self.add_gate(DrawableGate::from_ghost(ctx, gate.clone(), *pos, *size));
+                    // The event stays in the history, so redoing it places a copy
+                    match gate.try_clone() {
+                        Ok(ghost) => {
+                            self.add_gate(DrawableGate::from_ghost(ctx, &ghost, *pos, *size), ghost.gate);
+                            self.spawn_error = None;
+                        },
+                        Err(e) => self.spawn_error = Some(format!("Can't place {}: {}", gate.gate.get_name(), e)),
+                    }
                 }
                 CanvasEvent::AddConnection { from_gate, to_gate, InputPos, OutputPos } => {
+                    let (Some(from), Some(to)) = (self.get_gate_by_id(from_gate), self.get_gate_by_id(to_gate)) else {
+                        self.events.advance();
+                        return;
+                    };
                     let connection = DrawableConnection::with_gates(
-                        from_gate.borrow().get_pos_of_in_out(OutputPos.clone(), self.zoom, self.pan_offset),
-                        to_gate.borrow().get_pos_of_in_out(InputPos.clone(), self.zoom, self.pan_offset),
+                        from.get_pos_of_in_out(OutputPos.clone(), self.zoom, self.pan_offset),
+                        to.get_pos_of_in_out(InputPos.clone(), self.zoom, self.pan_offset),
                         InputPos.clone(),
                         OutputPos.clone(),
                         Color32::WHITE,
-                        from_gate.clone(),
-                        to_gate.clone(),
+                        *from_gate,
+                        *to_gate,
                         Uuid::new_v4()
                     );
 
-                    self.add_connection(connection);
+                    if let Err(e) = self.add_connection(connection) {
+                        self.connect_error = Some(e.to_string());
+                    }
                 }
                 CanvasEvent::SplitterClicked { pos, gate } => {
+                    let gate = *gate;
+                    let Some(splitter) = self.get_gate_by_id(&gate) else {
+                        self.events.advance();
+                        return;
+                    };
                     if let Some(sel_inp) = &self.selected_input {
                         let connection = DrawableConnection::with_gates(
-                            gate.borrow().get_pos_of_in_out(sel_inp.0.clone(), self.zoom, self.pan_offset),
+                            splitter.get_pos_of_in_out(sel_inp.0.clone(), self.zoom, self.pan_offset),
                             (pos.0, pos.1),
                             sel_inp.0.clone(),
                             InOutPosition::new(0),
                             Color32::WHITE,
-                            gate.clone(),
-                            gate.clone(),
+                            gate,
+                            gate,
                             Uuid::new_v4()
                         );
 
-                        self.add_connection(connection);
+                        if let Err(e) = self.add_connection(connection) {
+                            self.connect_error = Some(e.to_string());
+                        }
                     }
                     else if let Some(sel_out) = &self.selected_output {
                         let connection = DrawableConnection::with_gates(
                             (pos.0, pos.1),
-                            gate.borrow().get_pos_of_in_out(sel_out.0.clone(), self.zoom, self.pan_offset),
+                            splitter.get_pos_of_in_out(sel_out.0.clone(), self.zoom, self.pan_offset),
                             InOutPosition::new(0),
                             sel_out.0.clone(),
                             Color32::WHITE,
-                            gate.clone(),
-                            gate.clone(),
+                            gate,
+                            gate,
                             Uuid::new_v4()
                         );
 
-                        self.add_connection(connection);
+                        if let Err(e) = self.add_connection(connection) {
+                            self.connect_error = Some(e.to_string());
+                        }
                     }
                 }
                 CanvasEvent::RemoveSelected => {
@@ -341,80 +637,80 @@ impl Canvas {
                     self.selected_output = None;
                 }
                 CanvasEvent::DoubleClickedCanvas { pos } => {
-                    
+                    let pos = egui::pos2(pos.0, pos.1);
+                    self.open_subcircuit = self.gates.iter()
+                        .find(|gate| gate.get_rect(self.zoom, self.pan_offset).contains(pos))
+                        .map(|gate| gate.id)
+                        .filter(|id| self.underlying_circuit.get_gate(id).is_some_and(|gate| gate.as_circuit().is_some()));
                 }
                 CanvasEvent::RightClickedCanvas { pos } => {
                     // TODO
                     // Implement context menu
                 }
+                CanvasEvent::RightClickedConnection { id } => {
+                    // A wire carries the value of the output driving it
+                    let source = self.connections.iter()
+                        .find(|c| c.id == *id)
+                        .and_then(|c| Some((c.input_gate?, c.out_num.clone())));
+                    probe_to_add = source.map(|(gate_id, out_num)| (gate_id, out_num, true));
+                }
+                CanvasEvent::GateEvent(GateEvent::RightClickedIn { num, id }) => {
+                    probe_to_add = Some((*id, num.clone(), false));
+                }
+                CanvasEvent::GateEvent(GateEvent::RightClickedOut { num, id }) => {
+                    probe_to_add = Some((*id, num.clone(), true));
+                }
                 CanvasEvent::GateEvent(event) => {
-                    for g in self.gates.iter() {
+                    for g_ref in self.gates.iter_mut() {
                         match event {
-                            GateEvent::ClickedOn { id } => {
-                                let mut g_ref = g.borrow_mut();
-                                if g_ref.id == *id {
-                                    g_ref.selected = true;
-                        
-                                    let is_button = g_ref.gate.borrow().get_name() == "BUTTON";
-                                    
-                                    drop(g_ref); 
-                        
-                                    if is_button {
-                                        let gate_ref = g.borrow_mut(); 
-                                        let mut gate_logic_ref = gate_ref.gate.borrow_mut(); 
-                        
-                                        if let Some(memory) = gate_logic_ref.get_memory() {
-                                            gate_logic_ref.set_memory(0, !memory[0]);
+                            GateEvent::ClickedOn { id } if g_ref.id == *id => {
+                                g_ref.selected = true;
+
+                                if let Some(gate) = self.underlying_circuit.get_gate_mut(id) {
+                                    if gate.get_name() == "BUTTON" {
+                                        if let Some(memory) = gate.get_memory() {
+                                            gate.set_memory(0, !memory[0]);
                                         }
                                     }
-                                }  
+                                }
                             },
-                            GateEvent::ClickedIn { num, id } => {
-                                if g.borrow().id == *id {
-                                    if let Some((num_out, id_out)) = &self.selected_output.take() {
-                                        event_to_add = Some(CanvasEvent::AddConnection { from_gate: self.get_gate_by_id(id_out).unwrap(), to_gate: g.clone(), InputPos: num.clone(), OutputPos: num_out.clone() });
-                                    }
-                                    else {
-                                        self.selected_input = Some((num.clone(), id.clone()));
-                                    }
-                                    
-                                    if g.borrow().gate.borrow().get_name() == "SPLITTER" {
-                                        event_to_add = Some(CanvasEvent::SplitterClicked { pos: g.borrow().pos, gate: g.clone() });
-                                    }
+                            GateEvent::ClickedIn { num, id } if g_ref.id == *id => {
+                                if let Some((num_out, id_out)) = &self.selected_output.take() {
+                                    event_to_add = Some(CanvasEvent::AddConnection { from_gate: *id_out, to_gate: *id, InputPos: num.clone(), OutputPos: num_out.clone() });
+                                }
+                                else {
+                                    self.selected_input = Some((num.clone(), *id));
+                                }
+
+                                if self.underlying_circuit.get_gate(id).is_some_and(|gate| gate.get_name() == "SPLITTER") {
+                                    event_to_add = Some(CanvasEvent::SplitterClicked { pos: g_ref.pos, gate: *id });
                                 }
                             },
-                            GateEvent::ClickedOut { num, id } => {
-                                if g.borrow().id == *id {
-                                    if let Some((num_in, id_in)) = &self.selected_input.take() {
-                                        event_to_add = Some(CanvasEvent::AddConnection { from_gate: g.clone(), to_gate: self.get_gate_by_id(id_in).unwrap(), InputPos: num_in.clone(), OutputPos: num.clone() });
-                                    }
-                                    else {
-                                        self.selected_output = Some((num.clone(), id.clone()));
-                                    }
+                            GateEvent::ClickedOut { num, id } if g_ref.id == *id => {
+                                if let Some((num_in, id_in)) = &self.selected_input.take() {
+                                    event_to_add = Some(CanvasEvent::AddConnection { from_gate: *id, to_gate: *id_in, InputPos: num_in.clone(), OutputPos: num.clone() });
+                                }
+                                else {
+                                    self.selected_output = Some((num.clone(), *id));
                                 }
                             },
-                            GateEvent::MovedGate { id, from, to, start } => {
-                                if g.borrow().id == *id {
-                                    // Create a single mutable borrow at the start
-                                    let mut g_ref = g.borrow_mut();
-                                    
-                                    g_ref.drag.0 += to.0 - from.0;
-                                    g_ref.drag.1 += to.1 - from.1;
-                                
-                                    // Directly use the mutable borrow without additional calls to `borrow_mut`
-                                    // Check if the drag distance exceeds the grid spacing in either direction
-                                    if g_ref.drag.0.abs() >= GRID_SPACING || g_ref.drag.1.abs() >= GRID_SPACING {
-                                        // Snap the position to the nearest grid line by adding the accumulated drag distance
-                                        // and then rounding to the nearest GRID_SPACING multiple
-                                        g_ref.pos.0 = ((g_ref.pos.0 + g_ref.drag.0) / GRID_SPACING).round() * GRID_SPACING;
-                                        g_ref.pos.1 = ((g_ref.pos.1 + g_ref.drag.1) / GRID_SPACING).round() * GRID_SPACING;
-                                
-                                        // Reset the drag accumulator since we've just snapped the gate to the grid
-                                        g_ref.drag.0 = 0.0;
-                                        g_ref.drag.1 = 0.0;
-                                    }
+                            GateEvent::MovedGate { id, from, to, start } if g_ref.id == *id => {
+                                g_ref.drag.0 += to.0 - from.0;
+                                g_ref.drag.1 += to.1 - from.1;
+                            
+                                // Check if the drag distance exceeds the grid spacing in either direction
+                                if g_ref.drag.0.abs() >= GRID_SPACING || g_ref.drag.1.abs() >= GRID_SPACING {
+                                    // Snap the position to the nearest grid line by adding the accumulated drag distance
+                                    // and then rounding to the nearest GRID_SPACING multiple
+                                    g_ref.pos.0 = ((g_ref.pos.0 + g_ref.drag.0) / GRID_SPACING).round() * GRID_SPACING;
+                                    g_ref.pos.1 = ((g_ref.pos.1 + g_ref.drag.1) / GRID_SPACING).round() * GRID_SPACING;
+                            
+                                    // Reset the drag accumulator since we've just snapped the gate to the grid
+                                    g_ref.drag.0 = 0.0;
+                                    g_ref.drag.1 = 0.0;
                                 }
                             },
+                            _ => {}
                         }
                     }
                 }
@@ -424,6 +720,10 @@ impl Canvas {
             self.events.advance();
         }
 
+        if let Some((id, num, output)) = probe_to_add {
+            self.add_probe(&id, &num, output);
+        }
+
         // After processing the current event, check if there's a new event to add
         if let Some(new_event) = event_to_add {
             // Add the new event to the queue and check if a new event was actually added
@@ -433,17 +733,15 @@ impl Canvas {
 
     fn draw(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, painter: &egui::Painter, rect: egui::Rect) {
         self.draw_grid(painter, rect);
-        for gate in &self.gates {
-            if let Some(lua) = gate.borrow().gate.borrow_mut().get_lua_env() {
-                lua.0.load(&lua.1.0).exec().unwrap();
+        for gate in self.gates.iter_mut() {
+            if let Some(logic) = self.underlying_circuit.get_gate(&gate.id) {
+                gate.draw(logic, ctx, ui, painter, self.pan_offset, self.zoom);
             }
-            
-            gate.borrow_mut().draw(ctx, ui, painter, self.pan_offset, self.zoom);
 
             if let Some(sel_in) = &self.selected_input {
-                if sel_in.1 == gate.borrow().id {
+                if sel_in.1 == gate.id {
                     // Draw a white circle around the selected input to signal that its selected
-                    let (x, y) = gate.borrow().get_pos_of_in_out(sel_in.0.clone(), self.zoom, self.pan_offset);
+                    let (x, y) = gate.get_pos_of_in_out(sel_in.0.clone(), self.zoom, self.pan_offset);
                     let pos = egui::pos2(x, y);
                     let radius = 10.0;
                     let color = Color32::WHITE;
@@ -452,9 +750,9 @@ impl Canvas {
             }
 
             if let Some(sel_out) = &self.selected_output {
-                if sel_out.1 == gate.borrow().id {
+                if sel_out.1 == gate.id {
                     // Draw a white circle around the selected output to signal that its selected
-                    let (x, y) = gate.borrow().get_pos_of_in_out(sel_out.0.clone(), self.zoom, self.pan_offset);
+                    let (x, y) = gate.get_pos_of_in_out(sel_out.0.clone(), self.zoom, self.pan_offset);
                     let pos = egui::pos2(x, y);
                     let radius = 10.0;
                     let color = Color32::WHITE;
@@ -465,6 +763,11 @@ impl Canvas {
         for connection in &self.connections {
             connection.draw(painter, self.pan_offset, self.zoom);
         }
+
+        let errors = self.sim_error.iter().chain(self.connect_error.iter()).chain(self.spawn_error.iter());
+        for (i, err) in errors.enumerate() {
+            painter.text(rect.left_bottom() + egui::vec2(5.0, -5.0 - 20.0 * i as f32), egui::Align2::LEFT_BOTTOM, err, egui::FontId::default(), Color32::RED);
+        }
     }
 
     // // Method to render the canvas and its contents
@@ -586,3 +889,38 @@ impl Canvas {
         }
     }
 }
+
+// Inputs of a component drive the circuit from the left, outputs are read on the right
+fn bus_pins(role: GateRole, size: (f32, f32)) -> (Vec<InOutPosition>, Vec<InOutPosition>) {
+    let width = (size.0 / GRID_SPACING).round() as u16;
+    let height = (size.1 / GRID_SPACING).round() as u16;
+    let right = InOutPosition::new(width + height / 2);
+    let left = InOutPosition::new(2 * width + height + height.div_ceil(2));
+
+    match role {
+        GateRole::Input => (vec![], vec![right]),
+        GateRole::Output => (vec![left], vec![]),
+        GateRole::Gate => (vec![left], vec![right]),
+    }
+}
+
+// Single bit wires light up while they carry a 1, X and Z stand out
+fn signal_color(circuit: &Circuit, gates: &[DrawableGate], conn: &DrawableConnection) -> Color32 {
+    let value = conn.input_gate.and_then(|id| {
+        let index = gates.iter().find(|gate| gate.id == id)?
+            .outputs_pos.iter().position(|pos| pos.get() == conn.out_num.get())?;
+        let gate = circuit.get_gate(&id)?;
+        let widths = gate.get_output_widths();
+        if widths.get(index) != Some(&1) {
+            return None;
+        }
+        gate.get_output_values().get(widths[..index].iter().sum::<usize>()).copied()
+    });
+
+    match value {
+        Some(Logic::One) => Color32::LIGHT_GREEN,
+        Some(Logic::X) => Color32::RED,
+        Some(Logic::Z) => Color32::LIGHT_BLUE,
+        _ => Color32::WHITE,
+    }
+}
@@ -1,29 +1,115 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
 use egui_sdl2_gl::egui as egui;
+use uuid::Uuid;
 
+use crate::instance::{InstanceError, InstancePath};
 use crate::ui::canvas::Canvas;
+use crate::LogicGate;
+
+// About one frame, background canvases step as often as the shown one
+const BACKGROUND_STEP: Duration = Duration::from_millis(16);
+
+// Simulates a canvas whose tab isn't shown on its own thread, until the tab needs it back
+struct BackgroundWorker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Canvas>>,
+}
+
+impl BackgroundWorker {
+    fn start(mut canvas: Canvas) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                canvas.simulate();
+                thread::park_timeout(BACKGROUND_STEP);
+            }
+            canvas
+        });
+
+        Self { stop, handle: Some(handle) }
+    }
+
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+    }
+
+    // Waits for at most the step that is running
+    fn join(mut self) -> Canvas {
+        self.stop();
+        let handle = self.handle.take().expect("worker joined once");
+        handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+// A closed tab lets its worker run out on its own
+impl Drop for BackgroundWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
 
 pub struct SelectableCanvas {
+    id: Uuid,
     name: String,
     selected: bool,
-    pub canvas: Canvas,
+    // An empty stand-in while the worker has the canvas
+    canvas: Canvas,
+    worker: Option<BackgroundWorker>,
+    // Only set for tabs that look into a circuit nested in another tab
+    instance: Option<InstancePath>,
 }
 
 impl SelectableCanvas {
     fn new(name: &str) -> Self {
+        Self::with_canvas(name, Canvas::new(name))
+    }
+
+    pub fn with_canvas(name: &str, canvas: Canvas) -> Self {
         Self {
+            id: Uuid::new_v4(),
             name: name.to_owned(),
             selected: false,
-            canvas: Canvas::new(name),
+            canvas,
+            worker: None,
+            instance: None,
         }
     }
 
     fn is_selected(&self) -> bool {
         self.selected
     }
+
+    // Takes the canvas back from its worker first
+    fn canvas(&mut self) -> &mut Canvas {
+        if let Some(worker) = self.worker.take() {
+            self.canvas = worker.join();
+        }
+        &mut self.canvas
+    }
+
+    fn send_to_background(&mut self) {
+        if self.worker.is_none() {
+            let canvas = std::mem::replace(&mut self.canvas, Canvas::new(&self.name));
+            self.worker = Some(BackgroundWorker::start(canvas));
+        }
+    }
 }
 
 pub struct CanvasList {
     pub elements: Vec<SelectableCanvas>,
+    // Why the last subcircuit couldn't be opened
+    error: Option<String>,
 }
 
 impl CanvasList {
@@ -33,6 +119,7 @@ impl CanvasList {
                 SelectableCanvas::new("Canvas 1"),
                 SelectableCanvas::new("Canvas 2"),
             ],
+            error: None,
         }
     }
 
@@ -40,6 +127,13 @@ impl CanvasList {
         self.elements.push(element);
     }
 
+    // Adds the canvas and switches to it
+    pub fn add_selected(&mut self, mut element: SelectableCanvas) {
+        self.unselect_all();
+        element.selected = true;
+        self.elements.push(element);
+    }
+
     pub fn unselect_all(&mut self) {
         for element in &mut self.elements {
             element.selected = false;
@@ -47,15 +141,166 @@ impl CanvasList {
     }
 
     pub fn get_selected(&mut self) -> Option<&mut Canvas> {
-        for element in &mut self.elements {
-            if element.is_selected() {
-                return Some(&mut element.canvas);
+        self.elements.iter_mut().find(|element| element.is_selected()).map(|element| element.canvas())
+    }
+
+    // The selected canvas is drawn and simulated on the ui thread. The others keep
+    // simulating on a worker each, which keeps the canvas until its tab is needed again.
+    pub fn update(&mut self, ctx: &egui::Context) {
+        let selected = self.elements.iter().position(|element| element.selected);
+        // A tab showing a nested circuit borrows it from the tab that owns it
+        let owner = selected
+            .and_then(|i| self.elements[i].instance.as_ref())
+            .and_then(|path| self.elements.iter().position(|element| element.id == path.root));
+
+        let mut shown = None;
+        let mut owner_canvas = None;
+        for (i, element) in self.elements.iter_mut().enumerate() {
+            if Some(i) == selected {
+                shown = Some(element);
+            } else if Some(i) == owner {
+                owner_canvas = Some(element.canvas());
+            } else if element.instance.is_none() {
+                // Tabs showing an instance don't simulate, the owner does
+                element.send_to_background();
             }
         }
-        None
+
+        let Some(shown) = shown else {
+            return;
+        };
+        let path = shown.instance.clone().map(|path| path.gates).unwrap_or_default();
+
+        match owner_canvas {
+            Some(owner) => {
+                // The live instance is shown, its values come from the owner's simulation
+                match owner.get_circuit_mut().get_nested_mut(&path) {
+                    Some(instance) => {
+                        shown.canvas().swap_circuit(instance);
+                        shown.canvas().update(ctx);
+                        shown.canvas().swap_circuit(instance);
+                    },
+                    None => shown.canvas().update(ctx),
+                }
+                owner.simulate();
+            },
+            None => shown.canvas().update(ctx),
+        }
+
+        let Some(selected) = selected else {
+            return;
+        };
+        if let Some(gate) = self.elements[selected].canvas().take_open_subcircuit() {
+            let element = &self.elements[selected];
+            let mut path = element.instance.clone().unwrap_or(InstancePath { root: element.id, gates: vec![] });
+            path.gates.push(gate);
+
+            let result = self.open_instance(ctx, path);
+            self.show_result(result);
+        }
+    }
+
+    fn show_result(&mut self, result: Result<(), Box<dyn Error>>) {
+        self.error = result.err().map(|e| format!("Can't open subcircuit: {}", e));
+    }
+
+    // Every tab reloads its gates, tabs showing an instance reload their own copy
+    // so their pins match the live instance the owner reloads
+    pub fn reload_script(&mut self, script: &Path) {
+        for element in self.elements.iter_mut() {
+            element.canvas().reload_script(script);
+        }
+    }
+
+    // Selects the tab showing the instance, a new one is opened if there is none
+    pub fn open_instance(&mut self, ctx: &egui::Context, path: InstancePath) -> Result<(), Box<dyn Error>> {
+        let existing = self.elements.iter().position(|element| match &element.instance {
+            Some(instance) => *instance == path,
+            None => element.id == path.root && path.gates.is_empty(),
+        });
+        if let Some(index) = existing {
+            self.select(index);
+            return Ok(());
+        }
+
+        let root = self.root_canvas(path.root).map(|canvas| canvas.get_circuit());
+        let instance = path.resolve(root)?;
+        let (name, doc) = (instance.get_name(), instance.to_document()?);
+
+        let mut canvas = Canvas::from_document(ctx, &doc)?;
+        canvas.set_instance_view(true);
+
+        let mut element = SelectableCanvas::with_canvas(&name, canvas);
+        element.instance = Some(path);
+        self.add_selected(element);
+
+        Ok(())
+    }
+
+    fn select(&mut self, index: usize) {
+        for (i, element) in self.elements.iter_mut().enumerate() {
+            element.selected = i == index;
+        }
+    }
+
+    fn root_canvas(&mut self, root: Uuid) -> Option<&mut Canvas> {
+        self.elements.iter_mut().find(|element| element.id == root).map(|element| element.canvas())
+    }
+
+    // Names from the outermost tab down to the selected instance, each with the path to open it
+    fn breadcrumbs(&mut self) -> Option<Result<Vec<(String, InstancePath)>, InstanceError>> {
+        let path = self.elements.iter().find(|element| element.selected)?.instance.clone()?;
+        let root_name = self.elements.iter().find(|element| element.id == path.root).map(|element| element.name.clone());
+        let root = self.root_canvas(path.root).map(|canvas| canvas.get_circuit());
+
+        Some(path.breadcrumbs(&root_name.unwrap_or_default(), root))
     }
 
     pub fn show(&mut self, ui: &mut egui::Ui) {
+        self.show_tabs(ui);
+
+        if let Some(err) = &self.error {
+            let mut dismissed = false;
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::RED, err);
+                dismissed = ui.small_button("✖").clicked();
+            });
+            if dismissed {
+                self.error = None;
+            }
+        }
+
+        // Breadcrumbs back to the circuits the shown one is nested in
+        let Some(crumbs) = self.breadcrumbs() else {
+            return;
+        };
+        let mut clicked = None;
+        ui.horizontal(|ui| match crumbs {
+            Ok(crumbs) => {
+                let last = crumbs.len() - 1;
+                for (i, (name, path)) in crumbs.into_iter().enumerate() {
+                    if i == last {
+                        ui.strong(name);
+                    } else {
+                        if ui.link(name).clicked() {
+                            clicked = Some(path);
+                        }
+                        ui.label(">");
+                    }
+                }
+            },
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+            },
+        });
+
+        if let Some(path) = clicked {
+            let result = self.open_instance(&ui.ctx().clone(), path);
+            self.show_result(result);
+        }
+    }
+
+    fn show_tabs(&mut self, ui: &mut egui::Ui) {
         egui::ScrollArea::horizontal()
             .auto_shrink([false; 2])
             .show(ui, |ui| {
@@ -84,9 +329,7 @@ impl CanvasList {
     
                     // Only keep the last selected element selected
                     if let Some(index) = newly_selected {
-                        for (i, element) in self.elements.iter_mut().enumerate() {
-                            element.selected = i == index;
-                        }
+                        self.select(index);
                     }
     
                     // Remove the element if the remove button was clicked
@@ -1,6 +1,5 @@
-use std::{cell::RefCell};
-use std::rc::Rc;
 use egui_sdl2_gl::egui::{self as egui, Color32};
+use uuid::Uuid;
 
 use super::drawable_gate::{DrawableGate, InOutPosition};
 
@@ -10,9 +9,11 @@ pub struct DrawableConnection {
     pub in_num: InOutPosition,
     pub out_num: InOutPosition,
     pub color: Color32,
-    pub input_gate: Option<Rc<RefCell<Box<DrawableGate>>>>,
-    pub output_gate: Option<Rc<RefCell<Box<DrawableGate>>>>,
+    // Ids of the gates on the canvas the wire runs between
+    pub input_gate: Option<Uuid>,
+    pub output_gate: Option<Uuid>,
     pub id: uuid::Uuid,
+    pub width: usize, // Number of bits on this wire
 }
 
 impl DrawableConnection {
@@ -26,6 +27,7 @@ impl DrawableConnection {
             input_gate: None,
             output_gate: None,
             id,
+            width: 1,
         }
     }
 
@@ -35,8 +37,8 @@ impl DrawableConnection {
         in_num: InOutPosition,
         out_num: InOutPosition,
         color: Color32,
-        input_gate: Rc<RefCell<Box<DrawableGate>>>,
-        output_gate: Rc<RefCell<Box<DrawableGate>>>,
+        input_gate: Uuid,
+        output_gate: Uuid,
         id: uuid::Uuid,
     ) -> Self {
         DrawableConnection {
@@ -48,13 +50,16 @@ impl DrawableConnection {
             input_gate: Some(input_gate),
             output_gate: Some(output_gate),
             id,
+            width: 1,
         }
     }
 
-    pub fn update(&mut self, zoom_level: f32, pan_offset: egui::Vec2) {
+    pub fn update(&mut self, gates: &[DrawableGate], zoom_level: f32, pan_offset: egui::Vec2) {
+        let find = |id: &Option<Uuid>| id.and_then(|id| gates.iter().find(|gate| gate.id == id));
+
         // Update the start point if there's an input gate
-        if let Some(input_gate) = &self.input_gate {
-            let (in_x, in_y) = input_gate.borrow().get_pos_of_in_out(self.out_num.clone(), zoom_level, pan_offset);
+        if let Some(input_gate) = find(&self.input_gate) {
+            let (in_x, in_y) = input_gate.get_pos_of_in_out(self.out_num.clone(), zoom_level, pan_offset);
     
             // Adjust the position from world space to canvas space
             self.start.0 = in_x - pan_offset.x;
@@ -62,8 +67,8 @@ impl DrawableConnection {
         }
     
         // Update the end point if there's an output gate
-        if let Some(output_gate) = &self.output_gate {
-            let (out_x, out_y) = output_gate.borrow().get_pos_of_in_out(self.in_num.clone(), zoom_level, pan_offset);
+        if let Some(output_gate) = find(&self.output_gate) {
+            let (out_x, out_y) = output_gate.get_pos_of_in_out(self.in_num.clone(), zoom_level, pan_offset);
     
             // Adjust the position from world space to canvas space
             self.end.0 = out_x - pan_offset.x;
@@ -82,7 +87,20 @@ impl DrawableConnection {
             self.end.1 + pan_offset.y
         );
     
+        // Buses are drawn thicker than single bit wires
+        let thickness = if self.width > 1 { 3.0 } else { 1.0 };
+
         // Draw the line with adjusted coordinates
-        painter.line_segment([start_adjusted, end_adjusted], (1.0 * zoom_level, self.color));
+        painter.line_segment([start_adjusted, end_adjusted], (thickness * zoom_level, self.color));
+    }
+
+    // Whether a point in screen space lies on the drawn line, give or take a few pixels
+    pub fn contains_point(&self, pos: egui::Pos2, pan_offset: egui::Vec2) -> bool {
+        let start = egui::pos2(self.start.0, self.start.1) + pan_offset;
+        let end = egui::pos2(self.end.0, self.end.1) + pan_offset;
+
+        let line = end - start;
+        let t = if line.length_sq() > 0.0 { ((pos - start).dot(line) / line.length_sq()).clamp(0.0, 1.0) } else { 0.0 };
+        (start + line * t).distance(pos) <= 5.0
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,13 @@
 
-use std::{cell::RefCell, error::Error, hash::Hash, path::Path, rc::Rc};
+use std::{error::Error, hash::Hash, path::Path};
 use egui_sdl2_gl::{egui::{self as egui, pos2, Color32, Rect, TextureHandle, TextureOptions}};
 use mlua::{Debug, Function, Lua, UserData, UserDataMethods};
 use sdl2::libc::sock_extended_err;
 use serde::de::value::UsizeDeserializer;
 use crate::LogicGate;
+use crate::component::ComponentInfo;
+use crate::logic::logic_vec_to_string;
+use crate::sandbox::{sandboxed_lua, start_call};
 use super::{canvas::GRID_SPACING, drawable_connection::DrawableConnection, event_queue::GateEvent, gate_list::GhostGate};
 use uuid::Uuid;
 
@@ -76,6 +79,14 @@ pub struct GateProps {
     pub memory: Option<u8>,
     pub height: Option<u8>,
     pub width: Option<u8>,   
+    pub info: ComponentInfo,
+}
+
+impl GateProps {
+    // On the canvas, a script without WIDTH or HEIGHT gets 3x2 grid steps
+    pub fn size(&self) -> (f32, f32) {
+        (self.width.unwrap_or(3) as f32 * GRID_SPACING, self.height.unwrap_or(2) as f32 * GRID_SPACING)
+    }
 }
 
 impl GateFiles {
@@ -88,10 +99,10 @@ impl GateFiles {
 
     pub fn read_props(&self) -> Result<GateProps, Box<dyn Error>> {
         // Read the lua file and get the defined properties
-        let lua = Lua::new();
+        let lua = sandboxed_lua()?;
         let globals = lua.globals();
-        let code = std::fs::read_to_string(&self.lua).unwrap();
-        lua.load(&code).exec().unwrap();
+        let code = std::fs::read_to_string(&self.lua)?;
+        lua.load(&code).exec()?;
 
         let inputs_pos: Vec<InOutPosition> = globals.get::<_, Vec<u16>>("INPUT_POSITIONS")?
         .iter()
@@ -111,6 +122,7 @@ impl GateFiles {
             memory: globals.get::<_, Option<u8>>("MEMORY_SIZE")?,
             height: globals.get::<_, Option<u8>>("HEIGHT")?,
             width: globals.get::<_, Option<u8>>("WIDTH")?,
+            info: ComponentInfo::from_lua(&lua)?,
         })
     }
 }
@@ -192,18 +204,22 @@ pub enum Orientation {
     Right,
 }
 
+// The look of a gate on the canvas, the gate itself lives in the canvas circuit under the same id
 pub struct DrawableGate {
-    pub gate: Rc<RefCell<Box<dyn LogicGate>>>,
     pub pos: (f32, f32),
     pub size: (f32, f32),
     visual: VisualBuffer,
     pub inputs_pos: Vec<InOutPosition>,
     pub outputs_pos: Vec<InOutPosition>,
-    pub files: GateFiles,
+    pub files: Option<GateFiles>,
     pub selected: bool,
     pub orientation: Orientation,
     pub drag: (f32, f32),
     pub id: uuid::Uuid,
+    // Why the last Draw call failed, e.g. the script ran out of time
+    pub draw_error: Option<String>,
+    // Why the edited script couldn't be loaded, the gate keeps running the old one
+    pub reload_error: Option<String>,
 }
 
 impl core::fmt::Debug for DrawableGate {
@@ -227,9 +243,9 @@ impl PartialEq for DrawableGate {
 }
 
 impl DrawableGate {
-    pub fn new(ctx: &egui::Context,gate: Rc<RefCell<Box<dyn LogicGate>>>, pos: (f32, f32), size: (f32, f32), inputs_pos: Vec<InOutPosition>, outputs_pos: Vec<InOutPosition>) -> Self {
-        let lua = Path::new("comps").join(gate.borrow().get_name().to_ascii_lowercase() + ".lua");
-        let json = Path::new("comps").join(gate.borrow().get_name().to_ascii_lowercase() + ".json");
+    pub fn new(ctx: &egui::Context,gate: &dyn LogicGate, pos: (f32, f32), size: (f32, f32), inputs_pos: Vec<InOutPosition>, outputs_pos: Vec<InOutPosition>) -> Self {
+        let lua = Path::new("comps").join(gate.get_name().to_ascii_lowercase() + ".lua");
+        let json = Path::new("comps").join(gate.get_name().to_ascii_lowercase() + ".json");
 
         let id = Uuid::new_v4();
 
@@ -257,16 +273,17 @@ impl DrawableGate {
         };
 
         Self {
-            gate,
             pos,
             size,
             visual,
             inputs_pos,
             outputs_pos,
-            files,
+            files: Some(files),
             selected: false,
             orientation: Orientation::Right,
             drag: (0.0, 0.0),
+            draw_error: None,
+            reload_error: None,
             id,
         }
     }
@@ -288,7 +305,7 @@ impl DrawableGate {
         in_out.calc_coord_of_center(gate_rect, zoom_level)
     }
 
-    pub fn from_ghost(ctx: &egui::Context, gate: GhostGate, pos: (f32, f32), size: (f32, f32)) -> Self {
+    pub fn from_ghost(ctx: &egui::Context, gate: &GhostGate, pos: (f32, f32), size: (f32, f32)) -> Self {
         let id = Uuid::new_v4();
 
         let pixels = vec![[0, 0, 0, 0]; (size.0 * size.1) as usize];
@@ -309,20 +326,34 @@ impl DrawableGate {
         };
 
         Self {
-            gate: gate.gate,
             pos,
             size,
             visual,
-            files: gate.files,
-            inputs_pos: gate.inputs_pos,
-            outputs_pos: gate.outputs_pos,
+            files: gate.files.clone(),
+            inputs_pos: gate.inputs_pos.clone(),
+            outputs_pos: gate.outputs_pos.clone(),
             selected: false,
             orientation: Orientation::Right,
             drag: (0.0, 0.0),
+            draw_error: None,
+            reload_error: None,
             id: Uuid::new_v4(),
         }
     }
 
+    // Takes on the pins and size of the reloaded script, the next Draw call paints it from scratch
+    pub fn apply_props(&mut self, props: &GateProps) {
+        self.inputs_pos = props.inputs_pos.clone();
+        self.outputs_pos = props.outputs_pos.clone();
+        self.size = props.size();
+
+        self.visual.size = (self.size.0 as u32, self.size.1 as u32);
+        self.visual.buffer = vec![[0, 0, 0, 0]; (self.visual.size.0 * self.visual.size.1) as usize];
+        self.visual.changed = true;
+        self.draw_error = None;
+        self.reload_error = None;
+    }
+
     pub fn set_selected(&mut self, selected: bool) {
         self.selected = selected;
     }
@@ -348,22 +379,22 @@ impl DrawableGate {
         self.visual.changed = false;
     }
 
-    pub fn call_lua_update_buffer(&mut self) -> mlua::Result<()> {
-        let mut gate_ref = self.gate.borrow_mut();
-    
-        let lua = gate_ref.get_lua_env().ok_or_else(|| mlua::Error::RuntimeError("Failed to get Lua environment".to_string()))?;
+    pub fn call_lua_update_buffer(&mut self, gate: &dyn LogicGate) -> mlua::Result<()> {
+        let script = gate.get_lua_script().ok_or_else(|| mlua::Error::RuntimeError("Failed to get Lua environment".to_string()))?;
 
-        lua.0.scope(|scope| {
+        // The script was loaded with the gate, only Draw runs every frame
+        let Some(draw_func) = script.get_draw()? else {
+            return Ok(());
+        };
+        start_call(script.get_lua())?;
+
+        script.get_lua().scope(|scope| {
             let visual_buff_ref = scope.create_nonstatic_userdata(&mut self.visual)?;
-            let globals = lua.0.globals();
-            let draw_func: mlua::Function = globals.get("Draw")?;
             draw_func.call(visual_buff_ref)?;
 
             Ok(())
         })?;
 
-        lua.0.gc_collect()?;
-
         Ok(())
     }
     
@@ -396,6 +427,12 @@ impl DrawableGate {
                     num: input_pos.clone(),
                 })
             }         
+            else if interact_rect.contains(ptr_pos) && res.secondary_clicked() {
+                event = Some(GateEvent::RightClickedIn {
+                    id: self.id,
+                    num: input_pos.clone(),
+                })
+            }
         }
 
         // Draw and make outputs interactive
@@ -411,17 +448,45 @@ impl DrawableGate {
                     num: output_pos.clone(),
                 })
             }         
+            else if interact_rect.contains(ptr_pos) && res.secondary_clicked() {
+                event = Some(GateEvent::RightClickedOut {
+                    id: self.id,
+                    num: output_pos.clone(),
+                })
+            }
         }
 
         event
     }
 
-    pub fn draw(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, painter: &egui::Painter, pan_offset: egui::Vec2, zoom_level: f32) {
+    pub fn draw(&mut self, gate: &dyn LogicGate, ctx: &egui::Context, ui: &mut egui::Ui, painter: &egui::Painter, pan_offset: egui::Vec2, zoom_level: f32) {
         let gate_rect = self.get_rect(zoom_level, pan_offset);
-        
-        self.call_lua_update_buffer().unwrap();
+
+        // Components made from a circuit and their ports have no Draw script, they get a box with their name
+        let component = gate.get_lua_script().is_none();
+        if component {
+            if self.visual.changed {
+                self.visual.buffer.fill([200, 200, 200, 255]);
+            }
+        } else {
+            // A broken script shows its error below the gate instead of taking the ui down
+            self.draw_error = self.call_lua_update_buffer(gate).err().map(|e| e.to_string());
+        }
         
         self.draw_texture(painter, gate_rect, ctx, zoom_level);
+
+        if component {
+            // Ports show the value passing through them
+            let label = match gate.as_circuit() {
+                Some(_) => gate.get_name(),
+                None => format!("{} {}", gate.get_name(), logic_vec_to_string(&gate.get_output_values())),
+            };
+            painter.text(gate_rect.center(), egui::Align2::CENTER_CENTER, label, egui::FontId::proportional(12.0 * zoom_level), Color32::BLACK);
+        }
+
+        if let Some(err) = self.reload_error.as_ref().or(self.draw_error.as_ref()) {
+            painter.text(gate_rect.left_bottom() + egui::vec2(0.0, 4.0), egui::Align2::LEFT_TOP, err, egui::FontId::proportional(12.0), Color32::RED);
+        }
     
         // Draw inputs
         for input_pos in self.inputs_pos.iter() {
@@ -1,9 +1,8 @@
 use core::fmt;
-use std::{cell::RefCell, fmt::Formatter};
-use std::rc::Rc;
+use std::fmt::Formatter;
 use uuid::Uuid;
 
-use super::{drawable_gate::{DrawableGate, InOutPosition}, gate_list::GhostGate};
+use super::{drawable_gate::InOutPosition, gate_list::GhostGate};
 
 #[derive(Clone, Debug)]
 pub enum GateEvent {
@@ -18,6 +17,15 @@ pub enum GateEvent {
         num: InOutPosition,
         id: Uuid,
     },
+    // Right clicks on a pin add it to the waveform panel
+    RightClickedIn {
+        num: InOutPosition,
+        id: Uuid,
+    },
+    RightClickedOut {
+        num: InOutPosition,
+        id: Uuid,
+    },
     MovedGate {
         id: Uuid,
         from: (f32, f32),
@@ -36,8 +44,8 @@ pub enum CanvasEvent {
     // Für undo muss hier gate info rein
     RemoveSelected,
     AddConnection {
-        from_gate: Rc<RefCell<Box<DrawableGate>>>,
-        to_gate: Rc<RefCell<Box<DrawableGate>>>,
+        from_gate: Uuid,
+        to_gate: Uuid,
         InputPos: InOutPosition,
         OutputPos: InOutPosition,
     },
@@ -60,7 +68,10 @@ pub enum CanvasEvent {
     },
     SplitterClicked {
         pos: (f32, f32),
-        gate: Rc<RefCell<Box<DrawableGate>>>,
+        gate: Uuid,
+    },
+    RightClickedConnection {
+        id: Uuid,
     },
     GateEvent(GateEvent),
 }
@@ -95,6 +106,9 @@ impl std::fmt::Debug for CanvasEvent {
             CanvasEvent::SplitterClicked { pos, gate } => {
                 write!(f, "SplitterClicked")
             },
+            CanvasEvent::RightClickedConnection { id } => {
+                write!(f, "RightClickedConnection on {}", id)
+            },
             CanvasEvent::GateEvent(event) => {
                 write!(f, "GateEvent: {:?}", event)
             },
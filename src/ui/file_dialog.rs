@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use egui_sdl2_gl::egui as egui;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileAction {
+    Open,
+    Save,
+    // Saves the canvas into the component folder, the file name becomes the gate name
+    SaveComponent,
+}
+
+// Small window asking for a path, there is no native file dialog
+pub struct FileDialog {
+    action: FileAction,
+    path: String,
+    open: bool,
+    error: Option<String>,
+}
+
+impl FileDialog {
+    pub fn new(action: FileAction, path: &str) -> Self {
+        Self {
+            action,
+            path: path.to_owned(),
+            open: true,
+            error: None,
+        }
+    }
+
+    pub fn get_action(&self) -> FileAction {
+        self.action
+    }
+
+    pub fn get_open(&self) -> bool {
+        self.open
+    }
+
+    // Keeps the dialog open and shows what went wrong with the last path
+    pub fn set_error(&mut self, error: String) {
+        self.open = true;
+        self.error = Some(error);
+    }
+
+    // Returns the chosen path once the user confirms
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<PathBuf> {
+        let mut chosen = None;
+        let mut open = self.open;
+
+        let title = match self.action {
+            FileAction::Open => "Open circuit",
+            FileAction::Save => "Save circuit",
+            FileAction::SaveComponent => "Save as component",
+        };
+
+        egui::Window::new(title)
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Path:");
+                    ui.text_edit_singleline(&mut self.path);
+                });
+
+                if let Some(err) = &self.error {
+                    ui.colored_label(egui::Color32::RED, err);
+                }
+
+                let label = match self.action {
+                    FileAction::Open => "Open",
+                    FileAction::Save | FileAction::SaveComponent => "Save",
+                };
+
+                if ui.button(label).clicked() && !self.path.is_empty() {
+                    chosen = Some(PathBuf::from(&self.path));
+                }
+            });
+
+        self.open = open && chosen.is_none();
+        if chosen.is_some() {
+            self.error = None;
+        }
+
+        chosen
+    }
+}
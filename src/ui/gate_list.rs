@@ -1,41 +1,89 @@
 
 use egui_sdl2_gl::egui::{self as egui, pos2};
 
+use crate::component::{load_component, ComponentInfo, Symbol, COMPONENT_DIR, LUA_DIR};
+use crate::reload::reload_lua_gates;
 use crate::BasicGate;
 use crate::LogicGate;
 
 
+use super::canvas::GRID_SPACING;
 use super::drawable_gate::GateFiles;
 use super::drawable_gate::InOutPosition;
-use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
 use std::fs;
 use std::path::Path;
-use std::rc::Rc;
+
+// One gate name per line
+const FAVOURITES_FILE: &str = "./comps/favourites.txt";
 
 pub struct GhostGate {
-    pub gate: Rc<RefCell<Box<dyn LogicGate>>>,
-    pub files: GateFiles,
+    pub gate: Box<dyn LogicGate>,
+    // Components made from a circuit have no lua file
+    pub files: Option<GateFiles>,
     pub inputs_pos: Vec<InOutPosition>,
     pub outputs_pos: Vec<InOutPosition>,
+    pub size: (f32, f32),
+    pub info: ComponentInfo,
 }
 
-impl PartialEq for GhostGate {
-    fn eq(&self, other: &Self) -> bool {
-        self.gate.borrow().get_name() == other.gate.borrow().get_name()
+impl GhostGate {
+    pub fn from_component(gate: Box<dyn LogicGate>, symbol: &Symbol) -> Self {
+        Self {
+            gate,
+            files: None,
+            inputs_pos: symbol.inputs_pos.iter().map(|&pos| InOutPosition::new(pos)).collect(),
+            outputs_pos: symbol.outputs_pos.iter().map(|&pos| InOutPosition::new(pos)).collect(),
+            size: (symbol.width as f32 * GRID_SPACING, symbol.height as f32 * GRID_SPACING),
+            info: ComponentInfo::default(),
+        }
     }
-}
 
-impl Clone for GhostGate {
-    fn clone(&self) -> Self {
-        Self {
-            gate: Rc::new(RefCell::new(Box::new(BasicGate::from_lua(self.gate.borrow().get_name(), self.files.lua.clone()).unwrap()))),
+    // Pin counts and whatever the component says about itself
+    fn tooltip(&self) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let (ins, outs) = (self.inputs_pos.len(), self.outputs_pos.len());
+
+        let mut text = format!("{} input{}, {} output{}", ins, plural(ins), outs, plural(outs));
+        if let Some(description) = &self.info.description {
+            text += &format!("\n{}", description);
+        }
+        match (&self.info.author, &self.info.version) {
+            (Some(author), Some(version)) => text += &format!("\n{}, version {}", author, version),
+            (Some(author), None) => text += &format!("\n{}", author),
+            (None, Some(version)) => text += &format!("\nVersion {}", version),
+            (None, None) => {},
+        }
+        text
+    }
+
+    // A fresh copy of the gate to place, the script may have broken since the button was made
+    pub fn try_clone(&self) -> Result<Self, Box<dyn Error>> {
+        let source = self.gate.get_source().ok_or("The gate can't be copied")?;
+        Ok(Self {
+            gate: source.build(self.gate.get_name())?,
             files: self.files.clone(),
             inputs_pos: self.inputs_pos.clone(),
             outputs_pos: self.outputs_pos.clone(),
+            size: self.size,
+            info: self.info.clone(),
+        })
+    }
+
+    fn label(&self) -> String {
+        match &self.info.icon {
+            Some(icon) => format!("{} {}", icon, self.gate.get_name()),
+            None => self.gate.get_name(),
         }
     }
 }
 
+impl PartialEq for GhostGate {
+    fn eq(&self, other: &Self) -> bool {
+        self.gate.get_name() == other.gate.get_name()
+    }
+}
 
 pub struct GateList {
     buttons: Vec<GhostGate>,
@@ -43,18 +91,84 @@ pub struct GateList {
     open: bool,
     anchor: [f32; 2],
     pub gate_to_spawn: Option<GhostGate>,
+    search: String,
+    // Names of the gates listed on top
+    favourites: HashSet<String>,
+    // Gates whose script doesn't load, by name. Their button can't be used until it is fixed.
+    errors: HashMap<String, String>,
+    // Why the favourites couldn't be written to their file
+    favourites_error: Option<String>,
 }
 
 impl GateList {
     pub fn new() -> Self {
+        let favourites = fs::read_to_string(FAVOURITES_FILE)
+            .map(|text| text.lines().map(str::to_string).filter(|name| !name.is_empty()).collect())
+            .unwrap_or_default();
 
-        Self { buttons: vec![], pinned: false, open: true, anchor: [0.0, 0.0], gate_to_spawn: None}
+        Self { buttons: vec![], pinned: false, open: true, anchor: [0.0, 0.0], gate_to_spawn: None, search: String::new(), favourites, errors: HashMap::new(), favourites_error: None }
+    }
+
+    fn toggle_favourite(&mut self, name: String) {
+        if !self.favourites.remove(&name) {
+            self.favourites.insert(name);
+        }
+
+        let mut names: Vec<&str> = self.favourites.iter().map(String::as_str).collect();
+        names.sort();
+        self.favourites_error = fs::write(FAVOURITES_FILE, names.join("\n"))
+            .err()
+            .map(|e| format!("Can't save favourites: {}", e));
     }
 
     fn add_gate(&mut self, gate: GhostGate) {
         self.buttons.push(gate);
     }
 
+    // A component saved again replaces the old one
+    pub fn add_component(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let (circuit, symbol, info) = load_component(path)?;
+        let mut gate = GhostGate::from_component(Box::new(circuit), &symbol);
+        gate.info = info;
+
+        self.buttons.retain(|button| *button != gate);
+        self.errors.remove(&gate.gate.get_name());
+        self.add_gate(gate);
+
+        Ok(())
+    }
+
+    // An edited script replaces its button. A broken one leaves the old button
+    // and shows why, the components made from circuits reload their copies too.
+    pub fn reload(&mut self, script: &Path) {
+        let name = lua_gate_name(script);
+        match load_lua_gate(script) {
+            Ok(gate) => {
+                self.buttons.retain(|button| *button != gate);
+                self.add_gate(gate);
+                self.errors.remove(&name);
+            },
+            Err(e) => {
+                self.errors.insert(name, e.to_string());
+            },
+        }
+
+        for button in self.buttons.iter_mut() {
+            let Some(circuit) = button.gate.as_circuit_mut() else {
+                continue;
+            };
+            let reloaded = reload_lua_gates(circuit, script);
+            if reloaded.is_empty() {
+                continue;
+            }
+
+            match reloaded.into_iter().find_map(|gate| gate.result.err()) {
+                Some(e) => self.errors.insert(button.gate.get_name(), e),
+                None => self.errors.remove(&button.gate.get_name()),
+            };
+        }
+    }
+
     pub fn set_open(&mut self, open: bool) {
         self.open = open;
     }
@@ -65,48 +179,43 @@ impl GateList {
 
     pub fn update(&mut self, _ctx: &egui::Context) {
         // Read the gates from ./comps
-        let comps_dir = "./comps";
-        if let Ok(entries) = fs::read_dir(comps_dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    if let Some(file_name) = entry.file_name().to_str() {
-                        if file_name.ends_with(".lua") {
-                            let json_file_name = file_name.replace(".lua", ".json");
-                            let json_file = Path::new(comps_dir).join(json_file_name);
-                            
-                            let gate_file;
-                            if json_file.exists() {
-                                gate_file = GateFiles {
-                                    lua: entry.path().into_boxed_path(),
-                                    json: Some(json_file.into_boxed_path()),
-                                };
-                            } else {
-                                gate_file = GateFiles {
-                                    lua: entry.path().into_boxed_path(),
-                                    json: None,
-                                };
-                            }
+        if let Ok(entries) = fs::read_dir(LUA_DIR) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.extension().is_none_or(|ext| ext != "lua") {
+                    continue;
+                }
 
-                            // Hier auch prop reading abchecken
-                            // props müssen zentral gespeichert werden.
-                            let props = gate_file.read_props().unwrap();
-                            let ins = props.inputs_pos;
-                            let outs = props.outputs_pos;
+                // Broken scripts are tried again when they are saved
+                let name = lua_gate_name(&path);
+                let known = self.buttons.iter().any(|button| button.gate.get_name() == name);
+                if known || self.errors.contains_key(&name) {
+                    continue;
+                }
 
-                            let gate_name = file_name.split(".").next().unwrap().to_ascii_uppercase();
+                match load_lua_gate(&path) {
+                    Ok(gate) => self.add_gate(gate),
+                    Err(e) => {
+                        self.errors.insert(name, e.to_string());
+                    },
+                }
+            }
+        }
 
-                            let gate = GhostGate {
-                                gate: Rc::new(RefCell::new(Box::new(BasicGate::from_lua(gate_name, gate_file.lua.clone()).unwrap()))),
-                                files: gate_file,
-                                inputs_pos: ins,
-                                outputs_pos: outs,
-                            };
+        // Saved canvases, named after their file like the lua gates
+        if let Ok(entries) = fs::read_dir(COMPONENT_DIR) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                let Some(name) = path.file_stem().map(|n| n.to_string_lossy().to_ascii_uppercase()) else {
+                    continue;
+                };
+                // Broken components are tried again when they are saved from a canvas
+                let known = self.buttons.iter().any(|button| button.gate.get_name() == name);
+                if known || self.errors.contains_key(&name) {
+                    continue;
+                }
 
-                            // Add if not already in
-                            if !self.buttons.contains(&gate) {
-                                self.add_gate(gate);
-                            }
-                        }
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    if let Err(e) = self.add_component(&path) {
+                        self.errors.insert(name, e.to_string());
                     }
                 }
             }
@@ -155,22 +264,92 @@ impl GateList {
 
                 ctx.set_style(style.clone());
     
+                ui.horizontal(|ui| {
+                    ui.label("Search:");
+                    ui.text_edit_singleline(&mut self.search);
+                });
+
+                let mut spawn = None;
+                let mut toggled = None;
+                let mut failed = None;
+
                 // The ScrollArea takes up the rest of the space
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    for gate in &self.buttons {
-                        ui.with_layout(egui::Layout::top_down_justified(egui::Align::Center), |ui| {
-                            // Add a button for each label
-                            let bt_res = ui.button(gate.gate.borrow().get_name());
-                            if bt_res.clicked() {
-                                self.gate_to_spawn = Some(gate.clone());
-                            }
-                            if bt_res.double_clicked() {
-                                println!("Double clicked: {}", gate.gate.borrow().get_name());
+                    let found: Vec<&GhostGate> = self.buttons.iter()
+                        .filter(|gate| gate.info.matches(&gate.gate.get_name(), &self.search))
+                        .collect();
+
+                    let mut categories: BTreeMap<&str, Vec<&GhostGate>> = BTreeMap::new();
+                    for gate in found.iter() {
+                        categories.entry(gate.info.get_category()).or_default().push(gate);
+                    }
+
+                    let favourites: Vec<&GhostGate> = found.iter()
+                        .filter(|gate| self.favourites.contains(&gate.gate.get_name()))
+                        .copied()
+                        .collect();
+                    let sections = Some(("Favourites", favourites)).filter(|(_, gates)| !gates.is_empty()).into_iter().chain(categories);
+
+                    for (category, mut gates) in sections {
+                        gates.sort_by_key(|gate| gate.gate.get_name());
+
+                        // Everything that was found is shown while searching
+                        let open = if self.search.trim().is_empty() { None } else { Some(true) };
+                        egui::CollapsingHeader::new(category).default_open(true).open(open).show(ui, |ui| {
+                            for gate in gates {
+                                let name = gate.gate.get_name();
+                                ui.horizontal(|ui| {
+                                    let star = if self.favourites.contains(&name) { "★" } else { "☆" };
+                                    if ui.small_button(star).clicked() {
+                                        toggled = Some(name.clone());
+                                    }
+
+                                    ui.with_layout(egui::Layout::top_down_justified(egui::Align::Center), |ui| {
+                                        // Placing it would build the gate from the broken script
+                                        if let Some(err) = self.errors.get(&name) {
+                                            let label = egui::RichText::new(gate.label()).color(egui::Color32::RED);
+                                            ui.add_enabled(false, egui::Button::new(label)).on_disabled_hover_text(err);
+                                            return;
+                                        }
+
+                                        let bt_res = ui.button(gate.label()).on_hover_text(gate.tooltip());
+                                        if bt_res.clicked() {
+                                            match gate.try_clone() {
+                                                Ok(gate) => spawn = Some(gate),
+                                                Err(e) => failed = Some((name.clone(), e.to_string())),
+                                            }
+                                        }
+                                        if bt_res.double_clicked() {
+                                            println!("Double clicked: {}", name);
+                                        }
+                                    });
+                                });
                             }
                         });
                     }
+
+                    // New scripts and components that don't load have no button yet
+                    for (name, err) in self.errors.iter() {
+                        if !self.buttons.iter().any(|button| button.gate.get_name() == *name) {
+                            ui.colored_label(egui::Color32::RED, format!("{}: {}", name, err));
+                        }
+                    }
+                    if let Some(err) = &self.favourites_error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
                 });
 
+                if spawn.is_some() {
+                    self.gate_to_spawn = spawn;
+                }
+                // The button stays disabled until the script is saved again
+                if let Some((name, err)) = failed {
+                    self.errors.insert(name, err);
+                }
+                if let Some(name) = toggled {
+                    self.toggle_favourite(name);
+                }
+
                 ui.allocate_space(ui.available_size());
             });
 
@@ -180,4 +359,30 @@ impl GateList {
     }
     
     
-}
\ No newline at end of file
+}
+
+fn lua_gate_name(path: &Path) -> String {
+    path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_ascii_uppercase())
+}
+
+// The json file next to the script, e.g. and.json for and.lua, goes with it
+fn load_lua_gate(path: &Path) -> Result<GhostGate, Box<dyn Error>> {
+    let json = path.with_extension("json");
+    let files = GateFiles {
+        lua: path.to_path_buf().into_boxed_path(),
+        json: json.exists().then(|| json.into_boxed_path()),
+    };
+
+    let props = files.read_props()?;
+    let size = props.size();
+    let gate = BasicGate::from_lua(lua_gate_name(path), files.lua.clone())?;
+
+    Ok(GhostGate {
+        gate: Box::new(gate),
+        files: Some(files),
+        inputs_pos: props.inputs_pos,
+        outputs_pos: props.outputs_pos,
+        size,
+        info: props.info,
+    })
+}
@@ -5,4 +5,6 @@ pub mod canvas;
 pub mod canvas_list;
 pub mod drawable_gate;
 pub mod drawable_connection;
-pub mod event_queue;
\ No newline at end of file
+pub mod event_queue;
+pub mod file_dialog;
+pub mod waveform;
\ No newline at end of file
@@ -1,10 +1,17 @@
 
 
+use std::path::Path;
+
 use egui_sdl2_gl::egui as egui;
+use crate::circuit_file::{load_document, save_document};
+use crate::component::{component_path, make_component, save_component, COMPONENT_DIR, LUA_DIR};
+use crate::reload::ComponentWatcher;
 use crate::ui::gate_list;
 use crate::ui::top_menu;
 
-use super::canvas_list::CanvasList;
+use super::canvas::Canvas;
+use super::canvas_list::{CanvasList, SelectableCanvas};
+use super::file_dialog::{FileAction, FileDialog};
 
 use super::gate_list::GateList;
 
@@ -12,6 +19,10 @@ pub struct State {
     pub canvas_list: CanvasList,
     pub top_menu: top_menu::TopMenu,
     pub gate_selector: Option<gate_list::GateList>,
+    pub file_dialog: Option<FileDialog>,
+    new_canvas_count: usize,
+    // Edited lua components are loaded again while the app runs
+    watcher: ComponentWatcher,
 }
 
 impl State {
@@ -20,12 +31,22 @@ impl State {
             canvas_list: CanvasList::new(),
             top_menu: top_menu::TopMenu::new(),
             gate_selector: Some(GateList::new()),
+            file_dialog: None,
+            new_canvas_count: 0,
+            watcher: ComponentWatcher::new(Path::new(LUA_DIR)),
         };
         state.top_menu.open_gate_selector = true;
         state
     }
 
     fn update(&mut self, ctx: &egui::Context) {
+        for script in self.watcher.poll() {
+            if let Some(sel) = &mut self.gate_selector {
+                sel.reload(&script);
+            }
+            self.canvas_list.reload_script(&script);
+        }
+
         //Fucking ui programming stateful shitt ffuck ass aids 
         // FIX unfassbar schlecht
         if self.top_menu.open_gate_selector {
@@ -60,14 +81,77 @@ impl State {
                 canvas.jump_to(0.0, 0.0);
             }
         }
+
+        if self.top_menu.toggle_waveform {
+            if let Some(canvas) = self.canvas_list.get_selected() {
+                canvas.toggle_waveform();
+            }
+            self.top_menu.toggle_waveform = false;
+        }
+
+        if self.top_menu.new_file {
+            self.new_canvas_count += 1;
+            let name = format!("Untitled {}", self.new_canvas_count);
+            self.canvas_list.add_selected(SelectableCanvas::with_canvas(&name, Canvas::new(&name)));
+
+            self.top_menu.new_file = false;
+        }
+
+        if self.top_menu.open_file {
+            self.file_dialog = Some(FileDialog::new(FileAction::Open, "circuit.json"));
+            self.top_menu.open_file = false;
+        }
+
+        if self.top_menu.save_file {
+            self.file_dialog = Some(FileDialog::new(FileAction::Save, "circuit.json"));
+            self.top_menu.save_file = false;
+        }
+
+        if self.top_menu.save_component {
+            if let Some(canvas) = self.canvas_list.get_selected() {
+                let path = component_path(Path::new(COMPONENT_DIR), &canvas.get_name());
+                self.file_dialog = Some(FileDialog::new(FileAction::SaveComponent, &path.to_string_lossy()));
+            }
+            self.top_menu.save_component = false;
+        }
+    }
+
+    fn handle_file_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.file_dialog else {
+            return;
+        };
+
+        if let Some(path) = dialog.show(ctx) {
+            let result = match dialog.get_action() {
+                FileAction::Open => load_document(&path)
+                    .and_then(|doc| Canvas::from_document(ctx, &doc))
+                    .map(|canvas| {
+                        let name = path.file_stem().map_or("Circuit".to_string(), |n| n.to_string_lossy().to_string());
+                        self.canvas_list.add_selected(SelectableCanvas::with_canvas(&name, canvas));
+                    }),
+                FileAction::Save => match self.canvas_list.get_selected() {
+                    Some(canvas) => canvas.to_document()
+                        .map_err(|e| e.into())
+                        .and_then(|doc| save_document(&doc, &path)),
+                    None => Err("No canvas selected".into()),
+                },
+                FileAction::SaveComponent => save_as_component(&mut self.canvas_list, &mut self.gate_selector, &path),
+            };
+
+            if let Err(e) = result {
+                dialog.set_error(e.to_string());
+            }
+        }
+
+        if !dialog.get_open() {
+            self.file_dialog = None;
+        }
     }
 
     pub fn show(&mut self, ctx: &egui::Context) {
         self.update(ctx);
 
-        if let Some(canvas) = self.canvas_list.get_selected() {
-            canvas.update(ctx);
-        }
+        self.canvas_list.update(ctx);
 
         self.top_menu.show(ctx);
 
@@ -103,5 +187,17 @@ impl State {
         if let Some(gate_selector) = &mut self.gate_selector {
             gate_selector.show(ctx);
         }
+
+        self.handle_file_dialog(ctx);
     }
-}
\ No newline at end of file
+}
+
+// Writes the selected canvas as a component and puts it into the gate selector
+fn save_as_component(canvas_list: &mut CanvasList, gate_selector: &mut Option<GateList>, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let canvas = canvas_list.get_selected().ok_or("No canvas selected")?;
+    let name = path.file_stem().ok_or("The component needs a file name")?.to_string_lossy().to_ascii_uppercase();
+
+    save_component(&make_component(&canvas.to_document()?, &name), path)?;
+
+    gate_selector.get_or_insert_with(GateList::new).add_component(path)
+}
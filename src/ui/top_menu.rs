@@ -3,6 +3,11 @@ use egui_sdl2_gl::egui as egui;
 pub struct TopMenu {
     pub open_gate_selector: bool,
     pub jump_to_0_0: bool,
+    pub new_file: bool,
+    pub open_file: bool,
+    pub save_file: bool,
+    pub save_component: bool,
+    pub toggle_waveform: bool,
 }
 
 impl TopMenu {
@@ -10,6 +15,11 @@ impl TopMenu {
         Self {
             open_gate_selector: false,
             jump_to_0_0: false,
+            new_file: false,
+            open_file: false,
+            save_file: false,
+            save_component: false,
+            toggle_waveform: false,
         }
     }
 
@@ -19,21 +29,23 @@ impl TopMenu {
                 ui.menu_button("File", |ui| {
                     if ui.button("New").clicked() {
                         // Handle the New action
-                        println!("New file");
+                        self.new_file = true;
                         ui.close_menu();
-                        todo!("Handle the New action")
                     }
                     if ui.button("Open").clicked() {
                         // Handle the Open action
-                        println!("Open file");
+                        self.open_file = true;
                         ui.close_menu();
-                        todo!("Handle the Open action")
                     }
                     if ui.button("Save").clicked() {
                         // Handle the Save action
-                        println!("Save file");
+                        self.save_file = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Save as component").clicked() {
+                        // Makes the canvas a gate in the gate selector
+                        self.save_component = true;
                         ui.close_menu();
-                        todo!("Handle the Save action")
                     }
                     if ui.button("Quit").clicked() {
                         // Handle the Quit action
@@ -76,6 +88,11 @@ impl TopMenu {
                         self.open_gate_selector = true;
                         ui.close_menu();
                     }
+                    if ui.button("Waveforms").clicked() {
+                        // Show or hide the waveform panel of the selected canvas
+                        self.toggle_waveform = true;
+                        ui.close_menu();
+                    }
                 })
     
                 // Add more top-level menus as needed
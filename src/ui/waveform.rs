@@ -0,0 +1,390 @@
+use egui_sdl2_gl::egui::{self as egui, Align2, Color32, FontId, Stroke};
+use uuid::Uuid;
+
+use crate::logic::logic_vec_to_hex;
+use crate::vcd::Pin;
+use crate::{Circuit, Logic};
+
+const ROW_HEIGHT: f32 = 26.0;
+const RULER_HEIGHT: f32 = 18.0;
+const NAME_WIDTH: f32 = 130.0;
+// Pixels per tick
+const MIN_TICK_WIDTH: f32 = 0.5;
+const MAX_TICK_WIDTH: f32 = 200.0;
+const ZOOM_SPEED: f32 = 0.005;
+// How far away from an edge a click still puts the cursor on the edge
+const SNAP_DISTANCE: f32 = 6.0;
+
+const HIGH_COLOR: Color32 = Color32::from_rgb(80, 220, 80);
+const UNKNOWN_COLOR: Color32 = Color32::from_rgb(230, 60, 60);
+const FLOATING_COLOR: Color32 = Color32::from_rgb(230, 200, 60);
+const CURSOR_COLORS: [Color32; 2] = [Color32::from_rgb(90, 160, 255), Color32::from_rgb(255, 150, 50)];
+
+// A pin of a gate on the canvas whose recorded values are shown
+pub struct Probe {
+    pub label: String,
+    pub gate_id: Uuid,
+    pub pin: Pin,
+}
+
+pub struct WaveformPanel {
+    open: bool,
+    probes: Vec<Probe>,
+    // While running the circuit moves on by one tick every frame
+    running: bool,
+    step: bool,
+    tick_width: f32,
+    // Tick at the left edge of the diagram
+    scroll: f32,
+    // Keeps the current tick in view while running
+    follow: bool,
+    cursors: [Option<u64>; 2],
+}
+
+impl WaveformPanel {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            probes: Vec::new(),
+            running: false,
+            step: false,
+            tick_width: 12.0,
+            scroll: 0.0,
+            follow: true,
+            cursors: [None, None],
+        }
+    }
+
+    pub fn get_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    // Adds the probe and opens the panel, a pin that's already shown isn't added twice
+    pub fn add_probe(&mut self, probe: Probe) {
+        if !self.probes.iter().any(|p| p.gate_id == probe.gate_id && p.pin == probe.pin) {
+            self.probes.push(probe);
+        }
+        self.open = true;
+    }
+
+    pub fn remove_gate(&mut self, gate_id: &Uuid) {
+        self.probes.retain(|p| p.gate_id != *gate_id);
+    }
+
+    // The circuit only has to be recorded while something is shown
+    pub fn wants_recording(&self) -> bool {
+        !self.probes.is_empty()
+    }
+
+    // Whether the circuit should move on to the next tick this frame
+    pub fn take_step(&mut self) -> bool {
+        let step = self.running || self.step;
+        self.step = false;
+        step
+    }
+
+    // Has to be shown before the canvas, egui lays out side panels first
+    pub fn show(&mut self, ctx: &egui::Context, circuit: &Circuit) {
+        if !self.open {
+            return;
+        }
+
+        let mut open = self.open;
+        egui::SidePanel::right("waveform_panel")
+            .resizable(true)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Waveforms");
+                    if ui.button("✖").clicked() {
+                        open = false;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button(if self.running { "Pause" } else { "Run" }).clicked() {
+                        self.running = !self.running;
+                    }
+                    if ui.button("Step").clicked() {
+                        self.step = true;
+                    }
+                    if ui.button("Fit").clicked() {
+                        self.fit(circuit.get_time(), ui.available_width() - NAME_WIDTH);
+                    }
+                    ui.checkbox(&mut self.follow, "Follow");
+                    ui.label(format!("t = {}", circuit.get_time()));
+                });
+
+                ui.label(self.cursor_text());
+                ui.separator();
+
+                if self.probes.is_empty() {
+                    ui.label("Right click a pin or a wire to show it here");
+                    return;
+                }
+
+                egui::ScrollArea::vertical().auto_shrink([false; 2]).show(ui, |ui| {
+                    ui.horizontal_top(|ui| {
+                        ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
+                        self.show_names(ui, circuit);
+                        self.show_diagram(ui, circuit);
+                    });
+                });
+            });
+
+        self.open = open;
+    }
+
+    fn cursor_text(&self) -> String {
+        match self.cursors {
+            [Some(a), Some(b)] => format!("A: {}  B: {}  Δ: {} ticks", a, b, a.abs_diff(b)),
+            [Some(a), None] => format!("A: {}  (right click for cursor B)", a),
+            [None, Some(b)] => format!("B: {}  (click for cursor A)", b),
+            [None, None] => "Click the diagram to place cursor A, right click for B".to_string(),
+        }
+    }
+
+    fn fit(&mut self, end: u64, width: f32) {
+        self.scroll = 0.0;
+        self.tick_width = (width / (end + 1) as f32).clamp(MIN_TICK_WIDTH, MAX_TICK_WIDTH);
+    }
+
+    fn history<'a>(&self, circuit: &'a Circuit, probe: &Probe) -> Option<&'a [(u64, Vec<Logic>)]> {
+        let signal = circuit.find_pin_signal(&probe.gate_id, probe.pin)?;
+        Some(circuit.get_recording()?.get_history(signal))
+    }
+
+    // Names plus the value under cursor A, or the current value
+    fn show_names(&mut self, ui: &mut egui::Ui, circuit: &Circuit) {
+        let mut to_remove = None;
+
+        ui.vertical(|ui| {
+            ui.allocate_exact_size(egui::vec2(NAME_WIDTH, RULER_HEIGHT), egui::Sense::hover());
+
+            for (i, probe) in self.probes.iter().enumerate() {
+                let value = self.history(circuit, probe).and_then(|history| {
+                    let time = self.cursors[0].unwrap_or(circuit.get_time());
+                    let index = history.partition_point(|(t, _)| *t <= time);
+                    history[..index].last().map(|(_, value)| format_value(value))
+                });
+
+                ui.allocate_ui_with_layout(
+                    egui::vec2(NAME_WIDTH, ROW_HEIGHT),
+                    egui::Layout::left_to_right(egui::Align::Center),
+                    |ui| {
+                        ui.set_min_size(egui::vec2(NAME_WIDTH, ROW_HEIGHT));
+                        if ui.small_button("✖").clicked() {
+                            to_remove = Some(i);
+                        }
+                        ui.label(format!(" {} = {}", probe.label, value.unwrap_or("-".to_string())));
+                    },
+                );
+            }
+        });
+
+        if let Some(i) = to_remove {
+            self.probes.remove(i);
+        }
+    }
+
+    fn show_diagram(&mut self, ui: &mut egui::Ui, circuit: &Circuit) {
+        let size = egui::vec2(ui.available_width().max(50.0), RULER_HEIGHT + ROW_HEIGHT * self.probes.len() as f32);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::click_and_drag());
+        let rect = response.rect;
+        let now = circuit.get_time();
+
+        self.handle_input(ui, &response, circuit);
+
+        if self.running && self.follow {
+            let visible = rect.width() / self.tick_width;
+            self.scroll = ((now + 1) as f32 - visible).max(0.0);
+        }
+
+        painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+        self.draw_ruler(&painter, rect);
+
+        for (i, probe) in self.probes.iter().enumerate() {
+            let row = egui::Rect::from_min_size(
+                rect.min + egui::vec2(0.0, RULER_HEIGHT + ROW_HEIGHT * i as f32),
+                egui::vec2(rect.width(), ROW_HEIGHT),
+            );
+            painter.line_segment([row.left_bottom(), row.right_bottom()], (0.5, Color32::from_gray(60)));
+
+            match self.history(circuit, probe) {
+                Some(history) => self.draw_trace(&painter, row, history, now),
+                None => {
+                    painter.text(row.left_center() + egui::vec2(4.0, 0.0), Align2::LEFT_CENTER, "not recorded, connect the pin", FontId::proportional(11.0), Color32::GRAY);
+                },
+            }
+        }
+
+        // Current time
+        let x = self.x_of(now as f32 + 1.0, rect);
+        if x <= rect.right() {
+            painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.0, Color32::from_gray(120)));
+        }
+
+        for (cursor, color) in self.cursors.iter().zip(CURSOR_COLORS) {
+            if let Some(t) = cursor {
+                let x = self.x_of(*t as f32, rect);
+                if rect.x_range().contains(x) {
+                    painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.5, color));
+                }
+            }
+        }
+    }
+
+    fn handle_input(&mut self, ui: &egui::Ui, response: &egui::Response, circuit: &Circuit) {
+        let rect = response.rect;
+
+        if response.hovered() {
+            let (scroll, hover) = ui.input(|i| (i.scroll_delta, i.pointer.hover_pos()));
+
+            // Zoom around the tick under the pointer
+            if scroll.y != 0.0 {
+                let anchor = hover.map_or(rect.left(), |p| p.x);
+                let time = self.time_of(anchor, rect);
+                self.tick_width = (self.tick_width * (1.0 + scroll.y * ZOOM_SPEED)).clamp(MIN_TICK_WIDTH, MAX_TICK_WIDTH);
+                self.scroll = (time - (anchor - rect.left()) / self.tick_width).max(0.0);
+            }
+            if scroll.x != 0.0 {
+                self.scroll = (self.scroll - scroll.x / self.tick_width).max(0.0);
+                self.follow = false;
+            }
+        }
+
+        if response.dragged() && response.drag_delta().x != 0.0 {
+            self.scroll = (self.scroll - response.drag_delta().x / self.tick_width).max(0.0);
+            self.follow = false;
+        }
+
+        let cursor = if response.clicked() { Some(0) } else if response.secondary_clicked() { Some(1) } else { None };
+        if let (Some(cursor), Some(pos)) = (cursor, response.interact_pointer_pos()) {
+            self.cursors[cursor] = Some(self.snap_to_edge(pos, rect, circuit));
+        }
+    }
+
+    // Tick under the pointer, or the closest edge of the signal under it
+    fn snap_to_edge(&self, pos: egui::Pos2, rect: egui::Rect, circuit: &Circuit) -> u64 {
+        let time = self.time_of(pos.x, rect).round().max(0.0) as u64;
+
+        let row = ((pos.y - rect.top() - RULER_HEIGHT) / ROW_HEIGHT).floor();
+        let Some(history) = self.probes.get(row as usize).filter(|_| row >= 0.0).and_then(|p| self.history(circuit, p)) else {
+            return time;
+        };
+
+        history.iter().skip(1)
+            .map(|(t, _)| *t)
+            .filter(|t| (self.x_of(*t as f32, rect) - pos.x).abs() <= SNAP_DISTANCE)
+            .min_by_key(|t| t.abs_diff(time))
+            .unwrap_or(time)
+    }
+
+    fn x_of(&self, time: f32, rect: egui::Rect) -> f32 {
+        rect.left() + (time - self.scroll) * self.tick_width
+    }
+
+    fn time_of(&self, x: f32, rect: egui::Rect) -> f32 {
+        self.scroll + (x - rect.left()) / self.tick_width
+    }
+
+    fn draw_ruler(&self, painter: &egui::Painter, rect: egui::Rect) {
+        // Labels at least 50 pixels apart, on multiples of 1, 2 or 5
+        let mut spacing = 1u64;
+        let mut steps = [2, 5, 10].iter().cycle();
+        let mut decade = 1u64;
+        while (spacing as f32) * self.tick_width < 50.0 {
+            let step = *steps.next().unwrap();
+            spacing = decade * step;
+            if step == 10 {
+                decade *= 10;
+            }
+        }
+
+        let first = (self.scroll / spacing as f32).ceil() as u64 * spacing;
+        let mut t = first;
+        while self.x_of(t as f32, rect) <= rect.right() {
+            let x = self.x_of(t as f32, rect);
+            painter.line_segment([egui::pos2(x, rect.top() + RULER_HEIGHT - 4.0), egui::pos2(x, rect.bottom())], (0.5, Color32::from_gray(50)));
+            painter.text(egui::pos2(x + 2.0, rect.top()), Align2::LEFT_TOP, t.to_string(), FontId::monospace(10.0), Color32::GRAY);
+            t += spacing;
+        }
+    }
+
+    fn draw_trace(&self, painter: &egui::Painter, row: egui::Rect, history: &[(u64, Vec<Logic>)], now: u64) {
+        let high = row.top() + 5.0;
+        let low = row.bottom() - 5.0;
+        let mid = row.center().y;
+        let painter = painter.with_clip_rect(row);
+
+        let mut last_y = None;
+        for (k, (start, value)) in history.iter().enumerate() {
+            // The last value holds until the current tick is over
+            let stop = history.get(k + 1).map_or(now + 1, |(t, _)| *t);
+            let x0 = self.x_of(*start as f32, row);
+            let x1 = self.x_of(stop as f32, row);
+            if x1 < row.left() || x0 > row.right() {
+                last_y = None;
+                continue;
+            }
+
+            if value.len() == 1 {
+                let (y, color) = match value[0] {
+                    Logic::One => (high, HIGH_COLOR),
+                    Logic::Zero => (low, HIGH_COLOR),
+                    Logic::Z => (mid, FLOATING_COLOR),
+                    Logic::X => (mid, UNKNOWN_COLOR),
+                };
+
+                if value[0] == Logic::X {
+                    painter.rect_filled(egui::Rect::from_x_y_ranges(x0..=x1, high..=low), 0.0, UNKNOWN_COLOR.linear_multiply(0.3));
+                }
+                if let Some(last_y) = last_y {
+                    painter.line_segment([egui::pos2(x0, last_y), egui::pos2(x0, y)], Stroke::new(1.5, color));
+                }
+                painter.line_segment([egui::pos2(x0, y), egui::pos2(x1, y)], Stroke::new(1.5, color));
+                last_y = Some(y);
+            }
+            else {
+                let color = if value.iter().all(|v| *v == Logic::Z) {
+                    FLOATING_COLOR
+                } else if value.iter().all(|v| v.is_known()) {
+                    HIGH_COLOR
+                } else {
+                    UNKNOWN_COLOR
+                };
+
+                // Buses are drawn as a band that crosses over at every change
+                let slant = (2.0f32).min((x1 - x0) / 2.0);
+                let stroke = Stroke::new(1.5, color);
+                painter.line_segment([egui::pos2(x0, mid), egui::pos2(x0 + slant, high)], stroke);
+                painter.line_segment([egui::pos2(x0, mid), egui::pos2(x0 + slant, low)], stroke);
+                painter.line_segment([egui::pos2(x0 + slant, high), egui::pos2(x1 - slant, high)], stroke);
+                painter.line_segment([egui::pos2(x0 + slant, low), egui::pos2(x1 - slant, low)], stroke);
+                painter.line_segment([egui::pos2(x1 - slant, high), egui::pos2(x1, mid)], stroke);
+                painter.line_segment([egui::pos2(x1 - slant, low), egui::pos2(x1, mid)], stroke);
+
+                // Only label segments wide enough for the text
+                let text = format_value(value);
+                let visible = (x0.max(row.left()), x1.min(row.right()));
+                if visible.1 - visible.0 > text.len() as f32 * 7.0 + 6.0 {
+                    painter.text(egui::pos2((visible.0 + visible.1) / 2.0, mid), Align2::CENTER_CENTER, text, FontId::monospace(11.0), Color32::WHITE);
+                }
+                last_y = None;
+            }
+        }
+    }
+}
+
+fn format_value(value: &[Logic]) -> String {
+    if value.len() == 1 {
+        value[0].to_string()
+    }
+    else {
+        format!("0x{}", logic_vec_to_hex(value))
+    }
+}
@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::verilog::sanitize_identifier;
+use crate::{Circuit, Logic};
+
+// One recorded wire or bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    // Names of the nested circuits, starting with the top circuit
+    pub scope: Vec<String>,
+    pub name: String,
+    pub width: usize,
+}
+
+impl Signal {
+    // e.g. "Adder.g0_HALF_ADDER.in_1"
+    pub fn full_name(&self) -> String {
+        let mut parts = self.scope.clone();
+        parts.push(self.name.clone());
+        parts.join(".")
+    }
+}
+
+// A pin of a gate in the top circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pin {
+    Input(usize),
+    Output(usize),
+}
+
+// Value changes of every circuit input and output and every connection, nested
+// circuits included. Times are simulation ticks of the top circuit.
+pub struct Recording {
+    signals: Vec<Signal>,
+    // Per signal the time and value of every change, in time order
+    history: Vec<Vec<(u64, Vec<Logic>)>>,
+}
+
+impl Recording {
+    // The signals are fixed when recording starts, don't add or remove gates while recording
+    pub fn new(circuit: &Circuit) -> Self {
+        let mut signals = Vec::new();
+        collect_signals(circuit, &mut vec![sanitize_identifier(&circuit.name)], &mut signals);
+
+        Self {
+            history: vec![Vec::new(); signals.len()],
+            signals,
+        }
+    }
+
+    pub fn get_signals(&self) -> &[Signal] {
+        &self.signals
+    }
+
+    pub fn find_signal(&self, full_name: &str) -> Option<usize> {
+        self.signals.iter().position(|s| s.full_name() == full_name)
+    }
+
+    pub fn get_history(&self, signal: usize) -> &[(u64, Vec<Logic>)] {
+        &self.history[signal]
+    }
+
+    // Value of a signal at the given time, None before it was first recorded
+    pub fn value_at(&self, signal: usize, time: u64) -> Option<&Vec<Logic>> {
+        let history = &self.history[signal];
+        let index = history.partition_point(|(t, _)| *t <= time);
+        history[..index].last().map(|(_, value)| value)
+    }
+
+    // Last time anything changed
+    pub fn get_end_time(&self) -> u64 {
+        self.history.iter().filter_map(|h| h.last()).map(|(t, _)| *t).max().unwrap_or(0)
+    }
+
+    // Records every signal that changed, several samples at the same time keep the last value
+    pub fn sample(&mut self, circuit: &Circuit, time: u64) {
+        let mut values = Vec::new();
+        collect_values(circuit, &mut values);
+
+        for (history, value) in self.history.iter_mut().zip(values) {
+            match history.last() {
+                Some((t, _)) if *t == time => {
+                    history.pop();
+                    if history.last().is_none_or(|(_, last)| *last != value) {
+                        history.push((time, value));
+                    }
+                },
+                Some((_, last)) if *last == value => {},
+                _ => history.push((time, value)),
+            }
+        }
+    }
+
+    pub fn write_vcd(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "$version {} {} $end", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+        // A tick has no real duration, every tick is shown as 1 ns
+        writeln!(w, "$timescale 1 ns $end")?;
+
+        let mut scope: Vec<String> = Vec::new();
+        for (i, signal) in self.signals.iter().enumerate() {
+            let common = scope.iter().zip(signal.scope.iter()).take_while(|(a, b)| a == b).count();
+            for _ in common..scope.len() {
+                writeln!(w, "$upscope $end")?;
+            }
+            for name in signal.scope[common..].iter() {
+                writeln!(w, "$scope module {} $end", name)?;
+            }
+            scope = signal.scope.clone();
+
+            let range = if signal.width > 1 { format!(" [{}:0]", signal.width - 1) } else { String::new() };
+            writeln!(w, "$var wire {} {} {}{} $end", signal.width, identifier(i), signal.name, range)?;
+        }
+        for _ in 0..scope.len() {
+            writeln!(w, "$upscope $end")?;
+        }
+        writeln!(w, "$enddefinitions $end")?;
+
+        let mut changes: Vec<(u64, usize, &Vec<Logic>)> = self.history.iter().enumerate()
+            .flat_map(|(i, history)| history.iter().map(move |(t, value)| (*t, i, value)))
+            .collect();
+        changes.sort_by_key(|(t, i, _)| (*t, *i));
+
+        // The values at the first time are the initial dump
+        let start = changes.first().map(|(t, _, _)| *t);
+        let mut current = None;
+        for (t, i, value) in changes {
+            if current != Some(t) {
+                match current {
+                    None => writeln!(w, "#{}\n$dumpvars", t)?,
+                    Some(_) if current == start => writeln!(w, "$end\n#{}", t)?,
+                    Some(_) => writeln!(w, "#{}", t)?,
+                }
+                current = Some(t);
+            }
+            writeln!(w, "{}", value_change(value, &identifier(i)))?;
+        }
+        if current.is_some() && current == start {
+            writeln!(w, "$end")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save_vcd(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_vcd(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+// Short identifier codes made of the printable characters '!' to '~'
+fn identifier(mut index: usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    code
+}
+
+fn value_change(value: &[Logic], id: &str) -> String {
+    let bit = |v: &Logic| v.to_char().to_ascii_lowercase();
+
+    if value.len() == 1 {
+        format!("{}{}", bit(&value[0]), id)
+    }
+    else {
+        // Most significant bit first
+        let bits: String = value.iter().rev().map(bit).collect();
+        format!("b{} {}", bits, id)
+    }
+}
+
+fn node_label(circuit: &Circuit, id: &Uuid) -> String {
+    if let Some(i) = circuit.circuit_inputs.iter().position(|g| g == id) {
+        return format!("in_{}", i);
+    }
+    let name = circuit.arena.get(id).map_or(id.to_string(), |gate| gate.get_name());
+    if let Some(k) = circuit.gates.iter().position(|g| g == id) {
+        return gate_label(k, &name);
+    }
+    if let Some(i) = circuit.circuit_outputs.iter().position(|g| g == id) {
+        return format!("out_{}", i);
+    }
+    sanitize_identifier(&name)
+}
+
+// Same numbering as the Verilog export
+fn gate_label(k: usize, name: &str) -> String {
+    sanitize_identifier(&format!("g{}_{}", k, name))
+}
+
+// collect_signals and collect_values have to visit everything in the same order
+fn collect_signals(circuit: &Circuit, scope: &mut Vec<String>, signals: &mut Vec<Signal>) {
+    let mut add = |name: String, width: usize| signals.push(Signal { scope: scope.clone(), name, width });
+
+    for (i, input) in circuit.io_gates(&circuit.circuit_inputs).enumerate() {
+        add(format!("in_{}", i), input.get_output_num());
+    }
+    for (i, output) in circuit.io_gates(&circuit.circuit_outputs).enumerate() {
+        add(format!("out_{}", i), output.get_input_num());
+    }
+    for conn in circuit.connections.iter() {
+        let name = format!(
+            "{}_{}_to_{}_{}",
+            node_label(circuit, &conn.get_input_id()), conn.get_input_index(),
+            node_label(circuit, &conn.get_output_id()), conn.get_output_index(),
+        );
+        add(name, conn.get_width());
+    }
+
+    for (k, gate) in circuit.io_gates(&circuit.gates).enumerate() {
+        if let Some(sub) = gate.as_circuit() {
+            scope.push(gate_label(k, &gate.get_name()));
+            collect_signals(sub, scope, signals);
+            scope.pop();
+        }
+    }
+}
+
+fn collect_values(circuit: &Circuit, values: &mut Vec<Vec<Logic>>) {
+    for input in circuit.io_gates(&circuit.circuit_inputs) {
+        values.push(input.get_output_values());
+    }
+    for output in circuit.io_gates(&circuit.circuit_outputs) {
+        values.push(output.get_input_values());
+    }
+    for conn in circuit.connections.iter() {
+        values.push(conn.read(&circuit.arena));
+    }
+
+    for gate in circuit.io_gates(&circuit.gates) {
+        if let Some(sub) = gate.as_circuit() {
+            collect_values(sub, values);
+        }
+    }
+}
+
+impl Circuit {
+    // Records every value change from now on, until stop_recording
+    pub fn start_recording(&mut self) {
+        let mut recording = Recording::new(self);
+        recording.sample(self, self.time);
+        self.recording = Some(recording);
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn get_recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    // Recorded signal carrying the value of a pin, either the circuit input or output
+    // the gate stands for or a connection attached to the pin
+    pub fn find_pin_signal(&self, id: &Uuid, pin: Pin) -> Option<usize> {
+        self.recording.as_ref()?;
+        let gate = self.arena.get(id)?;
+
+        let inputs = self.circuit_inputs.len();
+        let outputs = self.circuit_outputs.len();
+        let connection = |found: Option<usize>| found.map(|c| inputs + outputs + c);
+
+        match pin {
+            // A circuit input or output signal holds all pins of its gate at once
+            Pin::Output(index) => {
+                if index == 0 && gate.get_output_widths().len() == 1 {
+                    if let Some(i) = self.circuit_inputs.iter().position(|g| g == id) {
+                        return Some(i);
+                    }
+                }
+                connection(self.connections.iter().position(|c| c.get_input_id() == *id && c.get_input_index() == index))
+            },
+            Pin::Input(index) => {
+                if index == 0 && gate.get_input_widths().len() == 1 {
+                    if let Some(i) = self.circuit_outputs.iter().position(|g| g == id) {
+                        return Some(inputs + i);
+                    }
+                }
+                connection(self.connections.iter().position(|c| c.get_output_id() == *id && c.get_output_index() == index))
+            },
+        }
+    }
+
+    pub(crate) fn sample_recording(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            recording.sample(self, self.time);
+            self.recording = Some(recording);
+        }
+    }
+}
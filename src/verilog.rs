@@ -0,0 +1,267 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::error::Error;
+
+use uuid::Uuid;
+
+use crate::{Circuit, GateInfo, LogicGate, TruthTable};
+
+#[derive(Debug)]
+pub enum VerilogError {
+    // Stateful gates have no truth table an assign could be derived from
+    NotCompilable { gates: Vec<GateInfo> },
+    // Verilog has no resolution for several gates driving the same wire
+    MultipleDrivers { gate: GateInfo, input: usize },
+}
+
+impl Error for VerilogError {}
+
+impl fmt::Display for VerilogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerilogError::NotCompilable { gates } => {
+                let names: Vec<String> = gates.iter().map(|g| g.to_string()).collect();
+                write!(f, "Can't export to Verilog, these gates can't be compiled to a truth table: {}", names.join(", "))
+            },
+            VerilogError::MultipleDrivers { gate, input } => {
+                write!(f, "Can't export to Verilog, input bit {} of {} has more than one driver", input, gate)
+            },
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "always", "and", "assign", "begin", "buf", "case", "default", "else", "end", "endmodule",
+    "if", "initial", "inout", "input", "module", "nand", "nor", "not", "or", "output", "reg",
+    "wire", "xnor", "xor",
+];
+
+// Turns a gate or circuit name into a valid Verilog identifier
+pub fn sanitize_identifier(name: &str) -> String {
+    let mut ident: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        ident.push_str("_m");
+    }
+
+    ident
+}
+
+impl Circuit {
+    // Structural Verilog, one module per circuit with submodules written first.
+    // Gates are compiled to get their truth tables, which is why this needs the circuit mutably.
+    pub fn to_verilog(&mut self) -> Result<String, VerilogError> {
+        let mut modules: Vec<(String, String)> = Vec::new();
+        write_module(self, &mut modules)?;
+
+        let text: Vec<String> = modules.into_iter().map(|(name, body)| format!("module {}{}", name, body)).collect();
+        Ok(text.join("\n"))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Input(usize),
+    Gate(usize),
+    Output(usize),
+}
+
+// Adds the module for circuit (and everything it instantiates) and returns its name.
+// Circuits that end up with the same name but different contents get a numbered name.
+fn write_module(circuit: &mut Circuit, modules: &mut Vec<(String, String)>) -> Result<String, VerilogError> {
+    let body = module_body(circuit, modules)?;
+    let base = sanitize_identifier(&circuit.name);
+
+    let mut name = base.clone();
+    let mut n = 1;
+    loop {
+        match modules.iter().find(|m| m.0 == name) {
+            Some(existing) if existing.1 == body => return Ok(name),
+            Some(_) => {
+                n += 1;
+                name = format!("{}_{}", base, n);
+            },
+            None => break,
+        }
+    }
+
+    modules.push((name.clone(), body));
+    Ok(name)
+}
+
+fn bus_name(prefix: &str, index: usize, width: usize, bit: usize) -> String {
+    if width == 1 {
+        format!("{}_{}", prefix, index)
+    }
+    else {
+        format!("{}_{}[{}]", prefix, index, bit)
+    }
+}
+
+// Verilog concatenations list the most significant bit first
+fn concat(bits: &[String]) -> String {
+    if bits.len() == 1 {
+        return bits[0].clone();
+    }
+    let reversed: Vec<&str> = bits.iter().rev().map(|b| b.as_str()).collect();
+    format!("{{{}}}", reversed.join(", "))
+}
+
+fn port_range(width: usize) -> String {
+    if width == 1 {
+        String::new()
+    }
+    else {
+        format!("[{}:0] ", width - 1)
+    }
+}
+
+// Sum of products for one output column of the table
+fn sum_of_products(table: &TruthTable, output: usize, inputs: &[String]) -> String {
+    let mut rows: Vec<(&Vec<bool>, &Vec<bool>)> = table.map.iter().collect();
+    rows.sort();
+
+    let ones: Vec<&Vec<bool>> = rows.iter().filter(|row| row.1[output]).map(|row| row.0).collect();
+    if ones.is_empty() {
+        return "1'b0".to_string();
+    }
+    if ones.len() == 1 << inputs.len() {
+        return "1'b1".to_string();
+    }
+
+    let terms: Vec<String> = ones.iter().map(|row| {
+        let literals: Vec<String> = row.iter().zip(inputs.iter())
+            .map(|(value, input)| if *value { input.clone() } else { format!("~{}", input) })
+            .collect();
+
+        if literals.len() == 1 {
+            literals[0].clone()
+        }
+        else {
+            format!("({})", literals.join(" & "))
+        }
+    }).collect();
+
+    terms.join(" | ")
+}
+
+fn module_body(circuit: &mut Circuit, modules: &mut Vec<(String, String)>) -> Result<String, VerilogError> {
+    let mut nodes: HashMap<Uuid, Node> = HashMap::new();
+    for (i, input) in circuit.circuit_inputs.iter().enumerate() {
+        nodes.insert(*input, Node::Input(i));
+    }
+    for (k, gate) in circuit.gates.iter().enumerate() {
+        nodes.entry(*gate).or_insert(Node::Gate(k));
+    }
+    for (i, output) in circuit.circuit_outputs.iter().enumerate() {
+        nodes.entry(*output).or_insert(Node::Output(i));
+    }
+
+    let input_widths: Vec<usize> = circuit.io_gates(&circuit.circuit_inputs).map(|g| g.get_output_num()).collect();
+    let output_widths: Vec<usize> = circuit.io_gates(&circuit.circuit_outputs).map(|g| g.get_input_num()).collect();
+
+    let source_name = |node: Node, bit: usize| match node {
+        Node::Input(i) => bus_name("in", i, input_widths[i], bit),
+        Node::Gate(k) => format!("g{}_{}", k, bit),
+        Node::Output(i) => bus_name("out", i, output_widths[i], bit),
+    };
+
+    // Which signal drives every input bit
+    let mut drivers: HashMap<(Uuid, usize), String> = HashMap::new();
+    for conn in circuit.connections.iter() {
+        let src = conn.get_input_id();
+        let dest = conn.get_output_id();
+        let (Some(&src_node), true) = (nodes.get(&src), nodes.contains_key(&dest)) else {
+            continue;
+        };
+
+        for i in 0..conn.get_width() {
+            let key = (dest, conn.get_dest_offset() + i);
+            let name = source_name(src_node, conn.get_src_offset() + i);
+
+            if drivers.insert(key, name).is_some() {
+                let name = circuit.arena.get(&dest).map_or(String::new(), |g| g.get_name());
+                return Err(VerilogError::MultipleDrivers { gate: GateInfo { id: dest, name }, input: key.1 });
+            }
+        }
+    }
+
+    let input_exprs = |id: &Uuid, input_num: usize| -> Vec<String> {
+        (0..input_num)
+            .map(|bit| drivers.get(&(*id, bit)).cloned().unwrap_or_else(|| "1'bx".to_string()))
+            .collect()
+    };
+
+    let mut ports = Vec::new();
+    for (i, width) in input_widths.iter().enumerate() {
+        ports.push(format!("    input wire {}in_{}", port_range(*width), i));
+    }
+    for (i, width) in output_widths.iter().enumerate() {
+        ports.push(format!("    output wire {}out_{}", port_range(*width), i));
+    }
+
+    let mut body = format!(" (\n{}\n);\n", ports.join(",\n"));
+    let mut not_compilable = Vec::new();
+
+    for (k, id) in circuit.gates.clone().iter().enumerate() {
+        let Some(gate) = circuit.arena.get_mut(id) else {
+            continue;
+        };
+        let inputs = input_exprs(id, gate.get_input_num());
+        let output_num = gate.get_output_num();
+        let outputs: Vec<String> = (0..output_num).map(|bit| format!("g{}_{}", k, bit)).collect();
+
+        body.push_str(&format!("\n    // g{}: {}\n", k, gate.get_name()));
+        for output in outputs.iter() {
+            body.push_str(&format!("    wire {};\n", output));
+        }
+
+        // Nested circuits keep their hierarchy as submodule instances
+        if let Some(sub) = gate.as_circuit_mut() {
+            let sub_name = write_module(sub, modules)?;
+
+            let mut port_conns = Vec::new();
+            let mut offset = 0;
+            for (i, width) in sub.get_input_widths().into_iter().enumerate() {
+                port_conns.push(format!("        .in_{}({})", i, concat(&inputs[offset..offset + width])));
+                offset += width;
+            }
+            offset = 0;
+            for (i, width) in sub.get_output_widths().into_iter().enumerate() {
+                port_conns.push(format!("        .out_{}({})", i, concat(&outputs[offset..offset + width])));
+                offset += width;
+            }
+
+            body.push_str(&format!("    {} g{} (\n{}\n    );\n", sub_name, k, port_conns.join(",\n")));
+            continue;
+        }
+
+        let table = if gate.compilable() { gate.compile().ok() } else { None };
+        let Some(table) = table else {
+            not_compilable.push(GateInfo { id: *id, name: gate.get_name() });
+            continue;
+        };
+
+        for (bit, output) in outputs.iter().enumerate() {
+            body.push_str(&format!("    assign {} = {};\n", output, sum_of_products(&table, bit, &inputs)));
+        }
+    }
+
+    if !not_compilable.is_empty() {
+        return Err(VerilogError::NotCompilable { gates: not_compilable });
+    }
+
+    if !circuit.circuit_outputs.is_empty() {
+        body.push('\n');
+    }
+    for (i, output) in circuit.circuit_outputs.iter().enumerate() {
+        let input_num = circuit.arena.get(output).map_or(0, |g| g.get_input_num());
+        body.push_str(&format!("    assign out_{} = {};\n", i, concat(&input_exprs(output, input_num))));
+    }
+
+    body.push_str("endmodule\n");
+    Ok(body)
+}
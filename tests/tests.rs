@@ -3,6 +3,7 @@ mod tests {
     use new_logic_gates::Gate;
     use new_logic_gates::CalcMode;
     use new_logic_gates::LuaCode;
+    use new_logic_gates::LuaScript;
 
     #[test]
     fn test_calculate_and() -> mlua::Result<()> {
@@ -29,7 +30,7 @@ mod tests {
         let lua_code = LuaCode(lua_code.to_string());
 
         // Führe die calculate-Funktion aus
-        gate.calculate(&CalcMode::Lua(lua_code))?;
+        gate.calculate(&CalcMode::Lua(LuaScript::new(lua_code)?))?;
 
         // Überprüfe das Ergebnis
         assert_eq!(gate.get_outputs(), vec![true]);
@@ -62,7 +63,7 @@ mod tests {
         let lua_code = &LuaCode(lua_code.to_string());
 
         // Führe die calculate-Funktion aus
-        gate.calculate(&CalcMode::Lua(lua_code.clone()))?;
+        gate.calculate(&CalcMode::Lua(LuaScript::new(lua_code.clone())?))?;
 
         // Überprüfe das Ergebnis
         assert_eq!(gate.get_outputs(), vec![true]);
@@ -92,7 +93,7 @@ mod tests {
         let lua_code = LuaCode(lua_code.to_string());
         
         // Führe die calculate-Funktion aus
-        gate.calculate(&CalcMode::Lua(lua_code))?;
+        gate.calculate(&CalcMode::Lua(LuaScript::new(lua_code)?))?;
         
         // Überprüfe das Ergebnis
         assert_eq!(gate.get_outputs(), vec![false]);
@@ -102,9 +103,28 @@ mod tests {
 
     #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, error::Error, rc::Rc};
-
-    use new_logic_gates::{BasicGate, Circuit, CircuitBus, Connection, LogicGate, TruthTable};
+    use std::error::Error;
+
+    use new_logic_gates::{BasicGate, Circuit, CircuitBus, Connection, GateInfo, Logic, LogicGate, LoopError, TruthTable};
+    use new_logic_gates::circuit_file::{load_circuit, load_document, save_circuit, GateSource, FORMAT_VERSION};
+    use new_logic_gates::netlist::{import_blif, import_verilog};
+    use new_logic_gates::clock::ClockGate;
+    use new_logic_gates::vcd::{Pin, Recording};
+    use new_logic_gates::logic::logic_vec_to_hex;
+    use new_logic_gates::sandbox::{LuaLimitError, LuaLimits};
+    use new_logic_gates::{GateError, LuaCode};
+    use new_logic_gates::minimize::{espresso, minimize_pos, minimize_sop, quine_mccluskey, Cube};
+    use new_logic_gates::verilog::VerilogError;
+    use new_logic_gates::arena::GateArena;
+    use new_logic_gates::batch::{BatchError, BatchNetlist, Step};
+    use new_logic_gates::equiv::{check_against_table, check_equivalence, EquivError};
+    use new_logic_gates::testbench::run_test_bench;
+    use new_logic_gates::parallel::{self, JobControl, ParallelError};
+    use new_logic_gates::component::{load_component, make_component, save_component, ComponentInfo, Symbol};
+    use new_logic_gates::circuit_file::{GateLayout, GateRole};
+    use new_logic_gates::instance::{InstanceError, InstancePath};
+    use new_logic_gates::reload::{reload_lua_gates, ComponentWatcher};
+    use uuid::Uuid;
 
     use super::*;
 
@@ -156,44 +176,49 @@ mod tests {
         end
         "#;
 
-        let gate1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        let mut gates = GateArena::new();
+        let (id1, id2, id3) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        gates.insert(id1, Box::new(BasicGate::from_gate(
             gate1,
-            CalcMode::Lua(LuaCode(lua_code1.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code1.to_string()))?),
+        )));
         
-        let gate2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        gates.insert(id2, Box::new(BasicGate::from_gate(
             gate2,
-            CalcMode::Lua(LuaCode(lua_code2.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code2.to_string()))?),
+        )));
 
-        let gate3: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        gates.insert(id3, Box::new(BasicGate::from_gate(
             gate3,
-            CalcMode::Lua(LuaCode(lua_code3.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code3.to_string()))?),
+        )));
 
         // Erstelle Verbindungen zwischen den Gates
-        let mut connection1 = Connection::new(
-            Rc::clone(&gate1),
+        let connection1 = Connection::new(
+            &gates,
+            id1,
             0,
-            Rc::clone(&gate2),
+            id2,
             0,
-        );
-        let mut connection2 = Connection::new(
-            Rc::clone(&gate2),
+        )?;
+        let connection2 = Connection::new(
+            &gates,
+            id2,
             0,
-            Rc::clone(&gate3),
+            id3,
             0,
-        );
+        )?;
 
         // Aktualisiere die Verbindungen
-        gate1.borrow_mut().calculate()?;
-        connection1.update();
-        gate2.borrow_mut().calculate()?;
-        connection2.update();
-        gate3.borrow_mut().calculate()?;
+        gates.get_mut(&id1).unwrap().calculate()?;
+        connection1.update(&mut gates);
+        gates.get_mut(&id2).unwrap().calculate()?;
+        connection2.update(&mut gates);
+        gates.get_mut(&id3).unwrap().calculate()?;
 
         // Überprüfe das Ergebnis
-        assert_eq!(gate3.borrow().get_inputs()[0], true);
+        assert!(gates.get(&id3).unwrap().get_inputs()[0]);
 
         Ok(())
     }
@@ -236,50 +261,52 @@ mod tests {
         end
         "#;
     
-        // Wrap the gates in Rc<RefCell<Box<dyn LogicGate>>> with their calculation modes
-        let gate1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        // Put the gates into an arena with their calculation modes
+        let mut gates = GateArena::new();
+        let (id1, id2, id3) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        gates.insert(id1, Box::new(BasicGate::from_gate(
             gate1,
-            CalcMode::Lua(LuaCode(lua_code1.to_string())),
-        ))));
-        let gate2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code1.to_string()))?),
+        )));
+        gates.insert(id2, Box::new(BasicGate::from_gate(
             gate2,
-            CalcMode::Lua(LuaCode(lua_code2.to_string())),
-        ))));
-        let gate3: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code2.to_string()))?),
+        )));
+        gates.insert(id3, Box::new(BasicGate::from_gate(
             gate3,
-            CalcMode::Lua(LuaCode(lua_code3.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code3.to_string()))?),
+        )));
     
         // Create connections between the gates
-        let mut connection1 = Connection::new(Rc::clone(&gate1), 0, Rc::clone(&gate2), 0);
-        let mut connection2 = Connection::new(Rc::clone(&gate2), 0, Rc::clone(&gate3), 0);
+        let connection1 = Connection::new(&gates, id1, 0, id2, 0)?;
+        let connection2 = Connection::new(&gates, id2, 0, id3, 0)?;
     
         // Perform the calculation functions
-        gate1.borrow_mut().calculate()?;
-        connection1.update();
-        gate2.borrow_mut().calculate()?;
-        connection2.update();
-        gate3.borrow_mut().calculate()?;
+        gates.get_mut(&id1).unwrap().calculate()?;
+        connection1.update(&mut gates);
+        gates.get_mut(&id2).unwrap().calculate()?;
+        connection2.update(&mut gates);
+        gates.get_mut(&id3).unwrap().calculate()?;
     
         // Check the result
-        assert_eq!(gate3.borrow().get_outputs()[0], true);
+        assert!(gates.get(&id3).unwrap().get_outputs()[0]);
     
         // Change the inputs
-        gate1.borrow_mut().set_input(0, true);
-        gate1.borrow_mut().set_input(1, true);
+        gates.get_mut(&id1).unwrap().set_input(0, true);
+        gates.get_mut(&id1).unwrap().set_input(1, true);
     
         // Perform the calculation functions
-        gate1.borrow_mut().calculate()?;
-        connection1.update();
-        gate2.borrow_mut().calculate()?;
-        connection2.update();
-        gate3.borrow_mut().calculate()?;
+        gates.get_mut(&id1).unwrap().calculate()?;
+        connection1.update(&mut gates);
+        gates.get_mut(&id2).unwrap().calculate()?;
+        connection2.update(&mut gates);
+        gates.get_mut(&id3).unwrap().calculate()?;
         
         // Re-check the result
-        assert_eq!(gate2.borrow().get_outputs()[0], false);
-        assert_eq!(gate3.borrow().get_inputs()[0], false);
-        assert_eq!(gate3.borrow().get_inputs()[1], false);
-        assert_eq!(gate3.borrow().get_outputs()[0], false);
+        assert!(!gates.get(&id2).unwrap().get_outputs()[0]);
+        assert!(!gates.get(&id3).unwrap().get_inputs()[0]);
+        assert!(!gates.get(&id3).unwrap().get_inputs()[1]);
+        assert!(!gates.get(&id3).unwrap().get_outputs()[0]);
     
         Ok(())
     }
@@ -300,15 +327,16 @@ mod tests {
         let lua_code = LuaCode(buffer_code.to_string());
 
         // Führe die calculate-Funktion aus
-        gate.calculate(&CalcMode::Lua(lua_code.clone()))?;
+        let calc = CalcMode::Lua(LuaScript::new(lua_code)?);
+        gate.calculate(&calc)?;
         assert_eq!(gate.get_outputs(), vec![false]);
-        gate.calculate(&CalcMode::Lua(lua_code.clone()))?;
+        gate.calculate(&calc)?;
         assert_eq!(gate.get_outputs(), vec![true]);
         
         gate.set_input(0, false);
-        gate.calculate(&CalcMode::Lua(lua_code.clone()))?;
+        gate.calculate(&calc)?;
         assert_eq!(gate.get_outputs(), vec![true]);
-        gate.calculate(&CalcMode::Lua(lua_code))?;
+        gate.calculate(&calc)?;
         assert_eq!(gate.get_outputs(), vec![false]);
 
         Ok(())
@@ -334,7 +362,7 @@ mod tests {
 
         let lua_code = LuaCode(lua_code.to_string());
 
-        gate.calculate(&CalcMode::Lua(lua_code.clone())).unwrap();
+        gate.calculate(&CalcMode::Lua(LuaScript::new(lua_code.clone()).unwrap())).unwrap();
 
         let tt = new_logic_gates::compile_gate_to_truth_table(&mut gate, &lua_code).unwrap();
 
@@ -362,6 +390,44 @@ mod tests {
         assert!(is_correct, "The truth table does not represent an AND gate correctly.");
     }
 
+    #[test]
+    fn test_truth_table_gate() -> Result<(), Box<dyn Error>> {
+        let mut tt = TruthTable::new();
+        tt.add(vec![false, false], vec![false]);
+        tt.add(vec![false, true], vec![true]);
+        tt.add(vec![true, false], vec![true]);
+        tt.add(vec![true, true], vec![false]);
+
+        let mut xor = BasicGate::from_truth_table("XOR".to_string(), tt.clone());
+        assert_eq!(xor.get_input_num(), 2);
+        assert_eq!(xor.get_output_num(), 1);
+
+        xor.set_input(0, true);
+        xor.set_input(1, false);
+        xor.calculate()?;
+        assert_eq!(xor.get_outputs(), vec![true]);
+
+        xor.set_input(1, true);
+        xor.calculate()?;
+        assert_eq!(xor.get_outputs(), vec![false]);
+
+        assert_eq!(xor.compile()?.map, tt.map);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truth_table_missing_entry() {
+        let mut tt = TruthTable::new();
+        tt.add(vec![false], vec![true]);
+
+        let mut gate = Gate::new("NOT".to_string(), vec![true], vec![false]);
+        let res = gate.calculate(&CalcMode::TruthTable(tt));
+
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("no entry for inputs 1"));
+    }
+
     #[test]
     fn test_circuit() -> Result<(), Box<dyn Error>> {
         let mut circuit = Circuit::new("Test Circuit".to_string());
@@ -403,37 +469,37 @@ mod tests {
         "#;
     
         // Wrap the gates in Rc<RefCell<Box<dyn LogicGate>>> with their calculation modes
-        let and_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        let and_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             gate1,
-            CalcMode::Lua(LuaCode(lua_code1.to_string())),
-        ))));
-        let not_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code1.to_string()))?),
+        ));
+        let not_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             gate2,
-            CalcMode::Lua(LuaCode(lua_code2.to_string())),
-        ))));
-        let or_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code2.to_string()))?),
+        ));
+        let or_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             gate3,
-            CalcMode::Lua(LuaCode(lua_code3.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(lua_code3.to_string()))?),
+        ));
 
-        let input1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let input2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let output1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
 
-        circuit.add_input(input1.clone());
-        circuit.add_input(input2.clone());
-        circuit.add_output(output1.clone());
+        circuit.add_input(input1, Uuid::new_v4());
+        circuit.add_input(input2, Uuid::new_v4());
+        circuit.add_output(output1, Uuid::new_v4());
 
-        circuit.add_gate(and_gate.clone());
-        circuit.add_gate(not_gate.clone());
-        circuit.add_gate(or_gate.clone());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+        let not_gate = circuit.add_gate(not_gate, Uuid::new_v4());
+        let or_gate = circuit.add_gate(or_gate, Uuid::new_v4());
 
-        circuit.conn_input_to_gate(0, and_gate.clone(), 0).unwrap();
-        circuit.conn_input_to_gate(0, or_gate.clone(), 1).unwrap();
-        circuit.conn_input_to_gate(1, and_gate.clone(), 1).unwrap();
+        circuit.conn_input_to_gate(0, and_gate, 0).unwrap();
+        circuit.conn_input_to_gate(0, or_gate, 1).unwrap();
+        circuit.conn_input_to_gate(1, and_gate, 1).unwrap();
 
-        circuit.connect(and_gate, 0, or_gate.clone(), 0);
-        circuit.connect(or_gate, 0, not_gate.clone(), 0);
+        circuit.connect(and_gate, 0, or_gate, 0)?;
+        circuit.connect(or_gate, 0, not_gate, 0)?;
 
         circuit.conn_gate_to_output(0, not_gate, 0)?;
 
@@ -478,30 +544,30 @@ mod tests {
         "#;
     
         // Wrap the gates in Rc<RefCell<Box<dyn LogicGate>>> with their calculation modes
-        let and_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        let and_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             and,
-            CalcMode::Lua(LuaCode(and_code.to_string())),
-        ))));
-        let not_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(and_code.to_string()))?),
+        ));
+        let not_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             not,
-            CalcMode::Lua(LuaCode(not_code.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(not_code.to_string()))?),
+        ));
 
-        let input1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let input2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let output1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
 
-        circuit.add_input(input1.clone());
-        circuit.add_input(input2.clone());
-        circuit.add_output(output1.clone());
+        circuit.add_input(input1, Uuid::new_v4());
+        circuit.add_input(input2, Uuid::new_v4());
+        circuit.add_output(output1, Uuid::new_v4());
 
-        circuit.add_gate(and_gate.clone());
-        circuit.add_gate(not_gate.clone());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+        let not_gate = circuit.add_gate(not_gate, Uuid::new_v4());
 
-        circuit.conn_input_to_gate(0, and_gate.clone(), 0).unwrap();
-        circuit.conn_input_to_gate(1, and_gate.clone(), 1).unwrap();
+        circuit.conn_input_to_gate(0, and_gate, 0).unwrap();
+        circuit.conn_input_to_gate(1, and_gate, 1).unwrap();
 
-        circuit.connect(and_gate, 0, not_gate.clone(), 0);
+        circuit.connect(and_gate, 0, not_gate, 0)?;
 
         circuit.conn_gate_to_output(0, not_gate, 0)?;
 
@@ -511,20 +577,20 @@ mod tests {
         assert_eq!(circuit.get_outputs()[0], true);
 
         let mut circuit2 = Circuit::new("Test".to_string());
-        let input1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let input2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let output2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
 
-        let circuit: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(circuit)));
+        let circuit: Box<dyn LogicGate> = Box::new(circuit);
 
-        circuit2.add_input(input1.clone());
-        circuit2.add_input(input2.clone());
-        circuit2.add_output(output2.clone());
-        circuit2.add_gate(circuit.clone());
+        circuit2.add_input(input1, Uuid::new_v4());
+        circuit2.add_input(input2, Uuid::new_v4());
+        circuit2.add_output(output2, Uuid::new_v4());
+        let circuit = circuit2.add_gate(circuit, Uuid::new_v4());
 
-        circuit2.conn_input_to_gate(0, circuit.clone(), 0).unwrap();
-        circuit2.conn_input_to_gate(1, circuit.clone(), 1).unwrap();
-        circuit2.conn_gate_to_output(0, circuit.clone(), 0)?;
+        circuit2.conn_input_to_gate(0, circuit, 0).unwrap();
+        circuit2.conn_input_to_gate(1, circuit, 1).unwrap();
+        circuit2.conn_gate_to_output(0, circuit, 0)?;
 
         circuit2.set_input(0, true);
         circuit2.set_input(1, true);
@@ -565,30 +631,30 @@ mod tests {
         "#;
     
         // Wrap the gates in Rc<RefCell<Box<dyn LogicGate>>> with their calculation modes
-        let and_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+        let and_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             and,
-            CalcMode::Lua(LuaCode(and_code.to_string())),
-        ))));
-        let not_gate: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(BasicGate::from_gate(
+            CalcMode::Lua(LuaScript::new(LuaCode(and_code.to_string()))?),
+        ));
+        let not_gate: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
             not,
-            CalcMode::Lua(LuaCode(not_code.to_string())),
-        ))));
+            CalcMode::Lua(LuaScript::new(LuaCode(not_code.to_string()))?),
+        ));
 
-        let input1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let input2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let output1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
 
-        circuit.add_input(input1.clone());
-        circuit.add_input(input2.clone());
-        circuit.add_output(output1.clone());
+        circuit.add_input(input1, Uuid::new_v4());
+        circuit.add_input(input2, Uuid::new_v4());
+        circuit.add_output(output1, Uuid::new_v4());
 
-        circuit.add_gate(and_gate.clone());
-        circuit.add_gate(not_gate.clone());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+        let not_gate = circuit.add_gate(not_gate, Uuid::new_v4());
 
-        circuit.conn_input_to_gate(0, and_gate.clone(), 0).unwrap();
-        circuit.conn_input_to_gate(1, and_gate.clone(), 1).unwrap();
+        circuit.conn_input_to_gate(0, and_gate, 0).unwrap();
+        circuit.conn_input_to_gate(1, and_gate, 1).unwrap();
 
-        circuit.connect(and_gate, 0, not_gate.clone(), 0);
+        circuit.connect(and_gate, 0, not_gate, 0)?;
 
         circuit.conn_gate_to_output(0, not_gate, 0)?;
 
@@ -607,20 +673,20 @@ mod tests {
         assert_eq!(tt.map, comp_tt.map);
 
         let mut circuit2 = Circuit::new("Test".to_string());
-        let input1: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let input2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
-        let output2: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(CircuitBus::new())));
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
 
-        let circuit: Rc<RefCell<Box<dyn LogicGate>>> = Rc::new(RefCell::new(Box::new(circuit)));
+        let circuit: Box<dyn LogicGate> = Box::new(circuit);
 
-        circuit2.add_input(input1.clone());
-        circuit2.add_input(input2.clone());
-        circuit2.add_output(output2.clone());
-        circuit2.add_gate(circuit.clone());
+        circuit2.add_input(input1, Uuid::new_v4());
+        circuit2.add_input(input2, Uuid::new_v4());
+        circuit2.add_output(output2, Uuid::new_v4());
+        let circuit = circuit2.add_gate(circuit, Uuid::new_v4());
 
-        circuit2.conn_input_to_gate(0, circuit.clone(), 0).unwrap();
-        circuit2.conn_input_to_gate(1, circuit.clone(), 1).unwrap();
-        circuit2.conn_gate_to_output(0, circuit.clone(), 0)?;
+        circuit2.conn_input_to_gate(0, circuit, 0).unwrap();
+        circuit2.conn_input_to_gate(1, circuit, 1).unwrap();
+        circuit2.conn_gate_to_output(0, circuit, 0)?;
 
         circuit2.set_input(0, true);
         circuit2.set_input(1, true);
@@ -632,7 +698,1912 @@ mod tests {
     }
 
 
-}
-}
+    fn nor_gate() -> Box<dyn LogicGate> {
+        let nor_code = r#"
+        NUM_OF_INS = 2
+        NUM_OF_OUTS = 1
+
+        function Calculate(inputs)
+            return {not (inputs[1] or inputs[2])}
+        end
+        "#;
+
+        let nor = Gate::new("NOR".to_string(), vec![false, false], vec![false]);
+        Box::new(BasicGate::from_gate(
+            nor,
+            CalcMode::Lua(LuaScript::new(LuaCode(nor_code.to_string())).unwrap()),
+        ))
+    }
+
+    #[test]
+    fn test_nor_latch() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("SR Latch".to_string());
+
+        let set: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let reset: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let q: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+
+        circuit.add_input(reset, Uuid::new_v4());
+        circuit.add_input(set, Uuid::new_v4());
+        circuit.add_output(q, Uuid::new_v4());
+
+        let nor1 = circuit.add_gate(nor_gate(), Uuid::new_v4());
+        let nor2 = circuit.add_gate(nor_gate(), Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, nor1, 0)?;
+        circuit.conn_input_to_gate(1, nor2, 1)?;
+        circuit.connect(nor1, 0, nor2, 0)?;
+        circuit.connect(nor2, 0, nor1, 1)?;
+        circuit.conn_gate_to_output(0, nor1, 0)?;
+
+        // Set
+        circuit.set_input(0, false);
+        circuit.set_input(1, true);
+        circuit.calculate()?;
+        assert!(circuit.get_outputs()[0]);
+
+        // Hold
+        circuit.set_input(1, false);
+        circuit.calculate()?;
+        assert!(circuit.get_outputs()[0]);
+
+        // Reset
+        circuit.set_input(0, true);
+        circuit.calculate()?;
+        assert!(!circuit.get_outputs()[0]);
+
+        // Hold
+        circuit.set_input(0, false);
+        circuit.calculate()?;
+        assert!(!circuit.get_outputs()[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreachable_gate_is_evaluated() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Constant".to_string());
+
+        let output: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        circuit.add_output(output, Uuid::new_v4());
+
+        // A gate without inputs is not reachable from any circuit input
+        let mut tt = TruthTable::new();
+        tt.add(vec![], vec![true]);
+        let high: Box<dyn LogicGate> = Box::new(BasicGate::from_truth_table("HIGH".to_string(), tt));
+
+        let high = circuit.add_gate(high, Uuid::new_v4());
+        circuit.conn_gate_to_output(0, high, 0)?;
+
+        circuit.calculate()?;
+        assert!(circuit.get_outputs()[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ring_oscillator_does_not_settle() {
+        let mut circuit = Circuit::new("Ring".to_string());
+
+        let mut tt = TruthTable::new();
+        tt.add(vec![false], vec![true]);
+        tt.add(vec![true], vec![false]);
+        let not: Box<dyn LogicGate> = Box::new(BasicGate::from_truth_table("NOT".to_string(), tt));
+
+        let id = Uuid::new_v4();
+        let not = circuit.add_gate(not, id);
+        circuit.connect(not, 0, not, 0).unwrap();
+        // Starting from X the loop would just stay X
+        circuit.get_gate_mut(&not).unwrap().set_output(0, false);
+        circuit.set_max_iterations(10);
+
+        let err = circuit.calculate().unwrap_err();
+        match err.downcast_ref::<LoopError>() {
+            Some(LoopError::Oscillation { cycle, iterations }) => {
+                assert_eq!(*iterations, 10);
+                assert_eq!(cycle, &vec![GateInfo { id, name: "NOT".to_string() }]);
+            },
+            _ => panic!("Expected an oscillation error, got: {}", err),
+        }
+
+        match circuit.check_combinational_loops() {
+            Err(LoopError::Combinational { cycles }) => assert_eq!(cycles.len(), 1),
+            _ => panic!("Expected a combinational loop"),
+        }
+    }
+
+    #[test]
+    fn test_long_chain_does_not_overflow_the_stack() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Chain".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+
+        let mut tt = TruthTable::new();
+        tt.add(vec![false], vec![true]);
+        tt.add(vec![true], vec![false]);
+
+        let mut nots = Vec::new();
+        for _ in 0..50_000 {
+            let not = Box::new(BasicGate::from_truth_table("NOT".to_string(), tt.clone()));
+            nots.push(circuit.add_gate(not, Uuid::new_v4()));
+        }
+        circuit.conn_input_to_gate(0, nots[0], 0)?;
+        for pair in nots.windows(2) {
+            circuit.connect(pair[0], 0, pair[1], 0)?;
+        }
+        circuit.conn_gate_to_output(0, *nots.last().unwrap(), 0)?;
+
+        circuit.set_input(0, true);
+        circuit.calculate()?;
+        assert_eq!(circuit.get_outputs(), vec![true]);
+        assert!(circuit.check_combinational_loops().is_ok());
+
+        // Closing the chain makes one loop out of every gate
+        circuit.connect(*nots.last().unwrap(), 0, nots[0], 0)?;
+        match circuit.check_combinational_loops() {
+            Err(LoopError::Combinational { cycles }) => assert_eq!(cycles.iter().map(|cycle| cycle.len()).collect::<Vec<_>>(), vec![50_000]),
+            _ => panic!("Expected a combinational loop"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_through_stateful_gate_is_not_combinational() -> mlua::Result<()> {
+        let mut circuit = Circuit::new("Toggle".to_string());
+
+        let buffer_code = std::fs::read_to_string("./comps/buffer.lua")?;
+        let buffer = Gate::with_buffer("BUFFER".to_string(), vec![false], vec![false], vec![false]);
+        let buffer: Box<dyn LogicGate> = Box::new(BasicGate::from_gate(
+            buffer,
+            CalcMode::Lua(LuaScript::new(LuaCode(buffer_code))?),
+        ));
+
+        let mut tt = TruthTable::new();
+        tt.add(vec![false], vec![true]);
+        tt.add(vec![true], vec![false]);
+        let not: Box<dyn LogicGate> = Box::new(BasicGate::from_truth_table("NOT".to_string(), tt));
+
+        let buffer = circuit.add_gate(buffer, Uuid::new_v4());
+        let not = circuit.add_gate(not, Uuid::new_v4());
+        circuit.connect(buffer, 0, not, 0).unwrap();
+        circuit.connect(not, 0, buffer, 0).unwrap();
+
+        assert!(circuit.find_combinational_loops().is_empty());
+        assert!(circuit.calculate().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load_circuit() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Saved".to_string());
+
+        let input1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output1: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let output2: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+
+        let and_gate: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("AND".to_string(), std::path::Path::new("./comps/and.lua").into())?
+        );
+        let mut buffer: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("BUFFER".to_string(), std::path::Path::new("./comps/buffer.lua").into())?
+        );
+        buffer.set_memory(0, true);
+
+        circuit.add_input(input1, Uuid::new_v4());
+        circuit.add_input(input2, Uuid::new_v4());
+        circuit.add_output(output1, Uuid::new_v4());
+        circuit.add_output(output2, Uuid::new_v4());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+        let buffer = circuit.add_gate(buffer, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, and_gate, 0)?;
+        circuit.conn_input_to_gate(1, and_gate, 1)?;
+        circuit.conn_input_to_gate(0, buffer, 0)?;
+        circuit.conn_gate_to_output(0, and_gate, 0)?;
+        circuit.conn_gate_to_output(1, buffer, 0)?;
+
+        let path = std::env::temp_dir().join(format!("saved_circuit_{}.json", Uuid::new_v4()));
+        save_circuit(&circuit, &path)?;
+
+        let doc = load_document(&path)?;
+        assert_eq!(doc.version, FORMAT_VERSION);
+        assert_eq!(doc.gates.len(), 6);
+        assert_eq!(doc.connections.len(), 5);
+        assert!(doc.gates.iter().any(|g| matches!(g.source, GateSource::Lua { .. })));
+
+        let mut loaded = load_circuit(&path)?;
+        std::fs::remove_file(&path)?;
+
+        for (a, b) in [(true, true), (true, false), (false, true), (false, false)] {
+            circuit.set_input(0, a);
+            circuit.set_input(1, b);
+            loaded.set_input(0, a);
+            loaded.set_input(1, b);
+
+            circuit.calculate()?;
+            loaded.calculate()?;
+
+            // The buffer memory is part of the file, so both stay in step
+            assert_eq!(circuit.get_outputs(), loaded.get_outputs());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_unsupported_version() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("future_circuit_{}.json", Uuid::new_v4()));
+        let doc = format!(r#"{{"version": {}, "name": "Future", "gates": [], "connections": []}}"#, FORMAT_VERSION + 1);
+        std::fs::write(&path, doc)?;
+
+        let result = load_circuit(&path);
+        std::fs::remove_file(&path)?;
+
+        let err = result.err().expect("Loading a newer format should fail");
+        assert!(err.to_string().contains("unsupported format version"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headless_simulator() -> Result<(), Box<dyn Error>> {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .args(["./comps/buffer.lua", "--steps", "2"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(b"1\n# comment\n\n0\n")?;
+        let output = child.wait_with_output()?;
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout)?, "0: 1 -> 0\n1: 1 -> 1\n2: 0 -> 1\n3: 0 -> 0\n");
+
+        let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .args(["./comps/and.lua", "--truth-table"])
+            .output()?;
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout)?, "00 -> 0\n01 -> 0\n10 -> 0\n11 -> 1\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headless_simulator_ticks_clocks() -> Result<(), Box<dyn Error>> {
+        use std::process::Command;
+
+        // No inputs, the clock drives everything
+        let mut circuit = Circuit::new("Counter".to_string());
+        let clock = circuit.add_gate(Box::new(ClockGate::new("CLK".to_string(), 2, 0.5)), Uuid::new_v4());
+        let counter = circuit.add_gate(lua_gate("COUNTER", "./comps/counter.lua")?, Uuid::new_v4());
+        let output = circuit.add_output(Box::new(CircuitBus::with_width(4)), Uuid::new_v4());
+        circuit.connect(clock, 0, counter, 0)?;
+        circuit.connect(counter, 0, output, 0)?;
+
+        let path = std::env::temp_dir().join(format!("clocked_{}.json", Uuid::new_v4()));
+        save_circuit(&circuit, &path)?;
+        let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .arg(&path)
+            .args(["--steps", "6", "--inputs", "/dev/null"])
+            .output()?;
+        std::fs::remove_file(&path)?;
+
+        // Ticks 2, 4 and 6 are rising edges, the count is least significant bit first
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            "0:  -> 0000\n1:  -> 1000\n2:  -> 1000\n3:  -> 0100\n4:  -> 0100\n5:  -> 1100\n"
+        );
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_bus_splitter_and_merger() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Reverse".to_string());
+
+        let input: Box<dyn LogicGate> = Box::new(CircuitBus::with_width(8));
+        let output: Box<dyn LogicGate> = Box::new(CircuitBus::with_width(8));
+        let splitter: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("BUS_SPLITTER".to_string(), std::path::Path::new("./comps/bus_splitter.lua").into())?
+        );
+        let merger: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("BUS_MERGER".to_string(), std::path::Path::new("./comps/bus_merger.lua").into())?
+        );
+
+        assert_eq!(splitter.get_input_widths(), vec![8]);
+        assert_eq!(splitter.get_output_widths(), vec![1; 8]);
+
+        circuit.add_input(input, Uuid::new_v4());
+        circuit.add_output(output, Uuid::new_v4());
+        let splitter = circuit.add_gate(splitter, Uuid::new_v4());
+        let merger = circuit.add_gate(merger, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, splitter, 0)?;
+        for i in 0..8 {
+            circuit.connect(splitter, i, merger, 7 - i)?;
+        }
+        circuit.conn_gate_to_output(0, merger, 0)?;
+
+        assert_eq!(circuit.get_input_widths(), vec![8]);
+        assert_eq!(circuit.get_output_widths(), vec![8]);
+
+        let bits = vec![true, true, false, true, false, false, false, true];
+        for (i, bit) in bits.iter().enumerate() {
+            circuit.set_input(i, *bit);
+        }
+        circuit.calculate()?;
+
+        let reversed: Vec<bool> = bits.into_iter().rev().collect();
+        assert_eq!(circuit.get_outputs(), reversed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_checks_widths() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Mismatch".to_string());
+
+        let bus: Box<dyn LogicGate> = Box::new(CircuitBus::with_width(4));
+        let and_gate: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("AND".to_string(), std::path::Path::new("./comps/and.lua").into())?
+        );
+
+        let bus = circuit.add_input(bus, Uuid::new_v4());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+
+        let err = circuit.conn_input_to_gate(0, and_gate, 0).unwrap_err();
+        assert!(err.to_string().contains("4 bit(s) wide"));
+
+        assert!(circuit.connect(bus, 0, and_gate, 2).is_err());
+        assert!(circuit.connect(and_gate, 0, and_gate, 1).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undriven_inputs_are_x() -> Result<(), Box<dyn Error>> {
+        let mut and_gate = BasicGate::from_lua("AND".to_string(), std::path::Path::new("./comps/and.lua").into())?;
+        assert_eq!(and_gate.get_input_values(), vec![Logic::X, Logic::X]);
+
+        // A 0 decides the output no matter what the other input is
+        and_gate.set_input(0, false);
+        and_gate.calculate()?;
+        assert_eq!(and_gate.get_output_values(), vec![Logic::Zero]);
+
+        and_gate.set_input(0, true);
+        and_gate.calculate()?;
+        assert_eq!(and_gate.get_output_values(), vec![Logic::X]);
+
+        and_gate.set_input_value(1, Logic::One);
+        and_gate.calculate()?;
+        assert_eq!(and_gate.get_output_values(), vec![Logic::One]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tristate_drivers() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Shared bus".to_string());
+
+        let tristate = || -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+            Ok(Box::new(
+                BasicGate::from_lua("TRISTATE".to_string(), std::path::Path::new("./comps/tristate.lua").into())?
+            ))
+        };
+        let code = r#"
+        NUM_OF_INS = 1
+        NUM_OF_OUTS = 1
+        MEMORY_SIZE = 0
+        FOUR_VALUED = true
+
+        function Calculate(inputs)
+            return {inputs[1]}
+        end
+        "#;
+        let wire: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua_code("WIRE".to_string(), LuaCode(code.to_string()))?
+        );
+
+        let a = tristate()?;
+        let b = tristate()?;
+        for _ in 0..4 {
+            circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        }
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let a = circuit.add_gate(a, Uuid::new_v4());
+        let b = circuit.add_gate(b, Uuid::new_v4());
+        let wire = circuit.add_gate(wire, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, a, 0)?;
+        circuit.conn_input_to_gate(1, a, 1)?;
+        circuit.conn_input_to_gate(2, b, 0)?;
+        circuit.conn_input_to_gate(3, b, 1)?;
+        circuit.connect(a, 0, wire, 0)?;
+        circuit.connect(b, 0, wire, 0)?;
+        circuit.conn_gate_to_output(0, wire, 0)?;
+
+        // (a, enable a, b, enable b) -> shared wire
+        let cases = [
+            ([true, true, false, false], Logic::One),
+            ([true, false, false, true], Logic::Zero),
+            ([true, false, false, false], Logic::Z),
+            ([true, true, false, true], Logic::X),
+            ([true, true, true, true], Logic::One),
+        ];
+
+        for (inputs, expected) in cases {
+            for (i, value) in inputs.iter().enumerate() {
+                circuit.set_input(i, *value);
+            }
+            circuit.calculate()?;
+            assert_eq!(circuit.get_output_values(), vec![expected]);
+        }
+
+        Ok(())
+    }
+
+    fn nand_circuit() -> Result<Circuit, Box<dyn Error>> {
+        let mut circuit = Circuit::new("My Nand".to_string());
+
+        let and_gate: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("AND".to_string(), std::path::Path::new("./comps/and.lua").into())?
+        );
+        let not_gate: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("NOT".to_string(), std::path::Path::new("./comps/not.lua").into())?
+        );
+
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let and_gate = circuit.add_gate(and_gate, Uuid::new_v4());
+        let not_gate = circuit.add_gate(not_gate, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, and_gate, 0)?;
+        circuit.conn_input_to_gate(1, and_gate, 1)?;
+        circuit.connect(and_gate, 0, not_gate, 0)?;
+        circuit.conn_gate_to_output(0, not_gate, 0)?;
+
+        Ok(circuit)
+    }
+
+    #[test]
+    fn test_export_verilog() -> Result<(), Box<dyn Error>> {
+        let verilog = nand_circuit()?.to_verilog()?;
+
+        assert!(verilog.starts_with("module My_Nand (\n    input wire in_0,\n    input wire in_1,\n    output wire out_0\n);"));
+        assert!(verilog.contains("    assign g0_0 = (in_0 & in_1);\n"));
+        assert!(verilog.contains("    assign g1_0 = ~g0_0;\n"));
+        assert!(verilog.contains("    assign out_0 = g1_0;\n"));
+        assert!(verilog.ends_with("endmodule\n"));
+
+        // Nested circuits become submodule instances
+        let mut top = Circuit::new("top".to_string());
+        let nand: Box<dyn LogicGate> = Box::new(nand_circuit()?);
+        top.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        top.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let nand = top.add_gate(nand, Uuid::new_v4());
+        top.conn_input_to_gate(0, nand, 0)?;
+        top.conn_input_to_gate(0, nand, 1)?;
+        top.conn_gate_to_output(0, nand, 0)?;
+
+        let verilog = top.to_verilog()?;
+        assert_eq!(verilog.matches("module My_Nand").count(), 1);
+        assert!(verilog.contains("module top (\n"));
+        assert!(verilog.contains("    My_Nand g0 (\n        .in_0(in_0),\n        .in_1(in_0),\n        .out_0(g0_0)\n    );\n"));
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_verilog_names_stateful_gates() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Delay".to_string());
+
+        let buffer: Box<dyn LogicGate> = Box::new(
+            BasicGate::from_lua("BUFFER".to_string(), std::path::Path::new("./comps/buffer.lua").into())?
+        );
+        let id = Uuid::new_v4();
+        circuit.add_gate(buffer, id);
+
+        match circuit.to_verilog() {
+            Err(VerilogError::NotCompilable { gates }) => {
+                assert_eq!(gates, vec![GateInfo { id, name: "BUFFER".to_string() }]);
+            },
+            _ => panic!("Expected a NotCompilable error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_blif() -> Result<(), Box<dyn Error>> {
+        let blif = "
+# half adder with a registered carry
+.model half_adder
+.inputs a b
+.outputs sum carry_q
+.names a b sum
+10 1
+01 1
+.names a b \\
+  carry
+11 1
+.latch carry carry_q 1
+.end
+";
+        let mut circuit = import_blif(blif, std::path::Path::new("./comps"))?;
+        assert_eq!(circuit.get_name(), "half_adder");
+        assert_eq!(circuit.get_input_num(), 2);
+        assert_eq!(circuit.get_output_num(), 2);
+
+        // The latch starts at its initial value and then follows the carry one step late
+        let steps = [([true, true], [false, true]), ([false, true], [true, true]), ([false, true], [true, false])];
+        for (inputs, outputs) in steps {
+            circuit.set_input(0, inputs[0]);
+            circuit.set_input(1, inputs[1]);
+            circuit.calculate()?;
+            assert_eq!(circuit.get_outputs(), outputs.to_vec());
+        }
+
+        let err = import_blif(".model m\n.inputs a\n.outputs y\n.names a b y\n11 1\n", std::path::Path::new("./comps")).err().unwrap();
+        assert_eq!(err.line, 4);
+        assert!(err.to_string().contains("Unknown signal b"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_verilog() -> Result<(), Box<dyn Error>> {
+        let verilog = "
+// Full adder built from two half adders
+module half (input a, input b, output s, output c);
+    xor (s, a, b);
+    and g1 (c, a, b);
+endmodule
+
+module full(a, b, cin, sum, cout);
+    input a, b, cin;
+    output sum;
+    output wire cout;
+    wire s1, c1, c2;
+
+    half h1 (.a(a), .b(b), .s(s1), .c(c1));
+    half h2 (.a(s1), .b(cin), .s(sum), .c(c2));
+    assign cout = c1 | c2;
+endmodule
+";
+        let mut circuit = import_verilog(verilog, std::path::Path::new("./comps"))?;
+        assert_eq!(circuit.get_name(), "full");
+        assert!(circuit.get_gate_ids().iter().any(|id| circuit.get_gate(id).is_some_and(|g| g.as_circuit().is_some())));
+
+        for i in 0..8_usize {
+            let bits = [i & 4 != 0, i & 2 != 0, i & 1 != 0];
+            for (j, bit) in bits.iter().enumerate() {
+                circuit.set_input(j, *bit);
+            }
+            circuit.calculate()?;
+
+            let total = bits.iter().filter(|b| **b).count();
+            assert_eq!(circuit.get_outputs(), vec![total % 2 == 1, total >= 2]);
+        }
+
+        let err = import_verilog("module m(input a, output y);\n\n    and (y, a, b);\nendmodule\n", std::path::Path::new("./comps")).err().unwrap();
+        assert_eq!(err.line, 3);
+        assert!(err.to_string().contains("Unknown signal b"));
+
+        let err = import_verilog("module m(input a, output y);\n    mystery u1 (y, a);\nendmodule\n", std::path::Path::new("./comps")).err().unwrap();
+        assert_eq!(err.line, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_verilog_positional_ports() -> Result<(), Box<dyn Error>> {
+        // Bound in the order the ports are declared, inputs before the output here
+        let verilog = "
+module half (input a, input b, output s, output c);
+    xor (s, a, b);
+    and (c, a, b);
+endmodule
+
+module andnot (input a, input b, output y);
+    assign y = a & ~b;
+endmodule
+
+module top (input x, input z, output sum, output carry, output diff);
+    half h (x, z, sum, carry);
+    andnot u (x, z, diff);
+endmodule
+";
+        let mut circuit = import_verilog(verilog, std::path::Path::new("./comps"))?;
+        assert_eq!(circuit.get_name(), "top");
+
+        for (x, z) in [(false, false), (false, true), (true, false), (true, true)] {
+            circuit.set_input(0, x);
+            circuit.set_input(1, z);
+            circuit.calculate()?;
+            assert_eq!(circuit.get_outputs(), vec![x ^ z, x && z, x && !z]);
+        }
+
+        let err = import_verilog(&verilog.replace("andnot u (x, z, diff)", "andnot u (x, diff)"), std::path::Path::new("./comps")).err().unwrap();
+        assert!(err.to_string().contains("Expected 3 connections, found 2"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verilog_export_import_round_trip() -> Result<(), Box<dyn Error>> {
+        let mut original = nand_circuit()?;
+        let mut imported = import_verilog(&original.to_verilog()?, std::path::Path::new("./comps"))?;
+
+        assert_eq!(imported.compile()?.map, original.compile()?.map);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_minimize_majority() {
+        let mut table = TruthTable::new();
+        for i in 0..8 {
+            let inputs: Vec<bool> = (0..3).map(|bit| i & (1 << bit) != 0).collect();
+            let ones = inputs.iter().filter(|b| **b).count();
+            table.add(inputs, vec![ones >= 2]);
+        }
+
+        let sop = minimize_sop(&table, 0);
+        assert_eq!(sop.to_string(), "(in_0 & in_1) | (in_0 & in_2) | (in_1 & in_2)");
+        let pos = minimize_pos(&table, 0);
+        assert_eq!(pos.to_string(), "(in_0 | in_1) & (in_0 | in_2) & (in_1 | in_2)");
+
+        for (inputs, outputs) in table.map.iter() {
+            assert_eq!(sop.eval(inputs), outputs[0]);
+            assert_eq!(pos.eval(inputs), outputs[0]);
+        }
+    }
+
+    #[test]
+    fn test_minimize_dont_cares() {
+        // BCD digit >= 5, the codes 10 to 15 never happen
+        let on = [5, 6, 7, 8, 9];
+        let dc = [10, 11, 12, 13, 14, 15];
+
+        let exact = quine_mccluskey(4, &on, &dc);
+        assert_eq!(exact.iter().map(|c| c.literal_num()).sum::<usize>(), 5);
+
+        let heuristic = espresso(4, &on, &dc);
+        for m in 0..16_u64 {
+            let inputs: Vec<bool> = (0..4).map(|bit| m & (1 << bit) != 0).collect();
+            let covered = |cubes: &[Cube]| cubes.iter().any(|c| c.contains(&inputs));
+            if on.contains(&m) {
+                assert!(covered(&exact) && covered(&heuristic));
+            }
+            else if !dc.contains(&m) {
+                assert!(!covered(&exact) && !covered(&heuristic));
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimize_circuit() -> Result<(), Box<dyn Error>> {
+        let mut original = nand_circuit()?;
+        assert_eq!(original.minimize()?[0].to_string(), "~in_0 | ~in_1");
+
+        let mut optimized = original.optimize()?;
+        assert_eq!(optimized.compile()?.map, original.compile()?.map);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_duty_cycle() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Clock".to_string());
+        let clock = circuit.add_gate(Box::new(ClockGate::new("CLK".to_string(), 4, 0.25)), Uuid::new_v4());
+
+        let mut wave = Vec::new();
+        for _ in 0..8 {
+            wave.push(circuit.get_gate(&clock).unwrap().get_outputs()[0]);
+            circuit.tick()?;
+        }
+        assert_eq!(wave, vec![true, false, false, false, true, false, false, false]);
+        assert_eq!(circuit.get_time(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_register_ignores_gate_order() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Shift".to_string());
+        let dff = || -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+            Ok(Box::new(BasicGate::from_lua("DFF".to_string(), std::path::Path::new("./comps/dff.lua").into())?))
+        };
+
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        // The second stage is added first, so it would be evaluated first without the clock phases
+        let second = circuit.add_gate(dff()?, Uuid::new_v4());
+        let first = circuit.add_gate(dff()?, Uuid::new_v4());
+        let clock = circuit.add_gate(Box::new(ClockGate::new("CLK".to_string(), 2, 0.5)), Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, first, 0)?;
+        circuit.connect(first, 0, second, 0)?;
+        circuit.connect(clock, 0, first, 1)?;
+        circuit.connect(clock, 0, second, 1)?;
+        circuit.conn_gate_to_output(0, second, 0)?;
+
+        let data = [true, false, true, true, false, false];
+        let mut seen = Vec::new();
+        circuit.calculate()?;
+        for bit in data {
+            circuit.set_input(0, bit);
+            // One full clock period, the rising edge is at the start of it
+            circuit.tick()?;
+            circuit.tick()?;
+            seen.push(circuit.get_output_values()[0]);
+        }
+
+        // The first stage takes the bit on the edge it was set before, the second one edge later
+        assert_eq!(seen[0], Logic::X);
+        let shifted: Vec<Logic> = data[..data.len() - 1].iter().map(|b| Logic::from(*b)).collect();
+        assert_eq!(seen[1..], shifted[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_counter_counts_rising_edges() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Counter".to_string());
+        let counter = circuit.add_gate(Box::new(
+            BasicGate::from_lua("COUNTER".to_string(), std::path::Path::new("./comps/counter.lua").into())?
+        ), Uuid::new_v4());
+        let clock = circuit.add_gate(Box::new(ClockGate::new("CLK".to_string(), 2, 0.5)), Uuid::new_v4());
+        circuit.connect(clock, 0, counter, 0)?;
+
+        circuit.calculate()?;
+        for _ in 0..10 {
+            circuit.tick()?;
+        }
+
+        // Ticks 2, 4, 6, 8 and 10 are rising edges
+        let counter = circuit.get_gate(&counter).unwrap();
+        assert_eq!(counter.get_outputs(), vec![true, false, true, false]);
+        assert!(!counter.compilable());
+
+        Ok(())
+    }
+
+    fn lua_gate(name: &str, path: &str) -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+        Ok(Box::new(BasicGate::from_lua(name.to_string(), std::path::Path::new(path).into())?))
+    }
+
+    #[test]
+    fn test_delays_show_static_hazard() -> Result<(), Box<dyn Error>> {
+        // a & ~a is always 0, but the NOT is one tick late
+        let mut circuit = Circuit::new("Hazard".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let and_gate = circuit.add_gate(lua_gate("AND", "./comps/and.lua")?, Uuid::new_v4());
+        let not_gate = circuit.add_gate(lua_gate("NOT", "./comps/not.lua")?, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, and_gate, 0)?;
+        circuit.conn_input_to_gate(0, not_gate, 0)?;
+        circuit.connect(not_gate, 0, and_gate, 1)?;
+        circuit.conn_gate_to_output(0, and_gate, 0)?;
+
+        circuit.set_timed(true);
+        circuit.set_input(0, false);
+        assert!(circuit.settle(10)?.is_some());
+        assert_eq!(circuit.get_outputs(), vec![false]);
+
+        circuit.set_input(0, true);
+        circuit.calculate()?;
+        let mut wave = vec![circuit.get_outputs()[0]];
+        for _ in 0..3 {
+            circuit.tick()?;
+            wave.push(circuit.get_outputs()[0]);
+        }
+        assert_eq!(wave, vec![false, true, false, false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delay_override() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Chain".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+
+        let mut nots = Vec::new();
+        for _ in 0..4 {
+            nots.push(circuit.add_gate(lua_gate("NOT", "./comps/not.lua")?, Uuid::new_v4()));
+        }
+        circuit.conn_input_to_gate(0, nots[0], 0)?;
+        for pair in nots.windows(2) {
+            circuit.connect(pair[0], 0, pair[1], 0)?;
+        }
+        circuit.conn_gate_to_output(0, nots[3], 0)?;
+
+        circuit.set_timed(true);
+        circuit.set_input(0, false);
+        circuit.settle(100)?;
+
+        circuit.set_input(0, true);
+        assert_eq!(circuit.settle(100)?, Some(4));
+        assert_eq!(circuit.get_outputs(), vec![true]);
+
+        circuit.get_gate_mut(&nots[1]).unwrap().set_delay(Some(5));
+        circuit.set_input(0, false);
+        assert_eq!(circuit.settle(100)?, Some(8));
+
+        // Without delays everything settles at once
+        circuit.set_timed(false);
+        circuit.set_input(0, true);
+        assert_eq!(circuit.settle(100)?, Some(0));
+        assert_eq!(circuit.get_outputs(), vec![true]);
+
+        let path = std::env::temp_dir().join(format!("delay_circuit_{}.json", Uuid::new_v4()));
+        save_circuit(&circuit, &path)?;
+        let loaded = load_circuit(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let delays: Vec<Option<u64>> = loaded.get_gate_ids().iter().map(|id| loaded.get_gate(id).unwrap().get_delay_override()).collect();
+        assert_eq!(delays, vec![None, Some(5), None, None]);
+        assert_eq!(loaded.get_gate(&loaded.get_gate_ids()[0]).unwrap().get_delay(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_circuit_delays() -> Result<(), Box<dyn Error>> {
+        // Three NOTs inside a subcircuit followed by one more outside
+        let mut inner = Circuit::new("Chain".to_string());
+        inner.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        inner.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let mut nots = Vec::new();
+        for _ in 0..3 {
+            nots.push(inner.add_gate(lua_gate("NOT", "./comps/not.lua")?, Uuid::new_v4()));
+        }
+        inner.conn_input_to_gate(0, nots[0], 0)?;
+        for pair in nots.windows(2) {
+            inner.connect(pair[0], 0, pair[1], 0)?;
+        }
+        inner.conn_gate_to_output(0, nots[2], 0)?;
+
+        let mut circuit = Circuit::new("Top".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let nested = circuit.add_gate(Box::new(inner), Uuid::new_v4());
+        let not_gate = circuit.add_gate(lua_gate("NOT", "./comps/not.lua")?, Uuid::new_v4());
+        circuit.conn_input_to_gate(0, nested, 0)?;
+        circuit.connect(nested, 0, not_gate, 0)?;
+        circuit.conn_gate_to_output(0, not_gate, 0)?;
+
+        circuit.set_timed(true);
+        assert!(circuit.get_gate(&nested).unwrap().as_circuit().unwrap().is_timed());
+        circuit.set_input(0, false);
+        circuit.settle(100)?;
+        assert_eq!(circuit.get_outputs(), vec![false]);
+
+        circuit.set_input(0, true);
+        assert_eq!(circuit.settle(100)?, Some(4));
+        assert_eq!(circuit.get_outputs(), vec![true]);
+
+        // A subcircuit added later runs in the mode of its parent
+        let late = circuit.add_gate(Box::new(Circuit::new("Late".to_string())), Uuid::new_v4());
+        assert!(circuit.get_gate(&late).unwrap().as_circuit().unwrap().is_timed());
+
+        circuit.set_timed(false);
+        assert!(!circuit.get_gate(&nested).unwrap().as_circuit().unwrap().is_timed());
+        circuit.set_input(0, false);
+        assert_eq!(circuit.settle(100)?, Some(0));
+        assert_eq!(circuit.get_outputs(), vec![false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_vcd() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Top".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let nand: Box<dyn LogicGate> = Box::new(nand_circuit()?);
+        let nand = circuit.add_gate(nand, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, nand, 0)?;
+        circuit.conn_input_to_gate(1, nand, 1)?;
+        circuit.conn_gate_to_output(0, nand, 0)?;
+
+        circuit.set_input(0, false);
+        circuit.set_input(1, true);
+        circuit.start_recording();
+        circuit.tick()?;
+        circuit.set_input(0, true);
+        circuit.tick()?;
+        circuit.tick()?;
+
+        let recording: Recording = circuit.stop_recording().unwrap();
+        let out = recording.find_signal("Top.out_0").unwrap();
+        assert_eq!(recording.get_history(out), &[(0, vec![Logic::X]), (1, vec![Logic::One]), (2, vec![Logic::Zero])]);
+        let inner = recording.find_signal("Top.g0_My_Nand.g0_AND_0_to_g1_NOT_0").unwrap();
+        assert_eq!(recording.value_at(inner, 5), Some(&vec![Logic::One]));
+
+        let mut vcd = Vec::new();
+        recording.write_vcd(&mut vcd)?;
+        let vcd = String::from_utf8(vcd)?;
+
+        assert!(vcd.contains("$scope module Top $end\n$var wire 1 ! in_0 $end\n"));
+        assert!(vcd.contains("$scope module g0_My_Nand $end\n"));
+        assert!(vcd.contains("$upscope $end\n$upscope $end\n$enddefinitions $end\n#0\n$dumpvars\n0!\n"));
+        assert!(vcd.ends_with("#2\n1!\n0#\n1$\n0&\n1'\n0)\n1*\n1,\n0-\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_pin_signal() -> Result<(), Box<dyn Error>> {
+        let mut circuit = Circuit::new("Top".to_string());
+        let input: Box<dyn LogicGate> = Box::new(CircuitBus::new());
+        let input = circuit.add_input(input, Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let nand: Box<dyn LogicGate> = Box::new(nand_circuit()?);
+        let nand = circuit.add_gate(nand, Uuid::new_v4());
+
+        circuit.conn_input_to_gate(0, nand, 0)?;
+        circuit.conn_gate_to_output(0, nand, 0)?;
+
+        // Nothing is found before recording starts
+        assert_eq!(circuit.find_pin_signal(&nand, Pin::Output(0)), None);
+        circuit.start_recording();
+
+        let recording = circuit.get_recording().unwrap();
+        let name = |pin| circuit.find_pin_signal(&nand, pin).map(|i| recording.get_signals()[i].full_name());
+        assert_eq!(circuit.find_pin_signal(&input, Pin::Output(0)), recording.find_signal("Top.in_0"));
+        assert_eq!(name(Pin::Input(0)), Some("Top.in_0_0_to_g0_My_Nand_0".to_string()));
+        assert_eq!(name(Pin::Output(0)), Some("Top.g0_My_Nand_0_to_out_0_0".to_string()));
+        // Unconnected pins aren't recorded
+        assert_eq!(name(Pin::Input(1)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_logic_vec_to_hex() {
+        let bits = |s: &str| s.chars().rev().map(|c| Logic::from_char(c).unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(logic_vec_to_hex(&bits("10100101")), "A5");
+        assert_eq!(logic_vec_to_hex(&bits("11111")), "1F");
+        assert_eq!(logic_vec_to_hex(&bits("ZZZZ10X1")), "ZX");
+        assert_eq!(logic_vec_to_hex(&bits("0Z011")), "0X");
+    }
+
+
+    #[test]
+    fn test_lua_sandbox_hides_os_and_io() -> Result<(), Box<dyn Error>> {
+        let code = r#"
+        NUM_OF_INS = 1
+        NUM_OF_OUTS = 1
+        MEMORY_SIZE = 0
+        HIDDEN = os == nil and io == nil and load == nil and require == nil and dofile == nil
+        CLOCK = os ~= nil and os.clock ~= nil and os.exit == nil
+
+        function Calculate(inputs)
+            return {HIDDEN}
+        end
+        "#;
+
+        let mut gate = BasicGate::from_lua_code("SANDBOX".to_string(), LuaCode(code.to_string()))?;
+        gate.set_input(0, false);
+        gate.calculate()?;
+        assert_eq!(gate.get_output_values(), vec![Logic::One]);
+
+        // "os.clock" is all it takes to bring back a single function
+        let limits = LuaLimits {
+            allowed_globals: LuaLimits::default().allowed_globals.into_iter().chain(["os.clock".to_string()]).collect(),
+            ..LuaLimits::default()
+        };
+        let mut gate = BasicGate::from_lua_code_with_limits("SANDBOX".to_string(), LuaCode(code.replace("{HIDDEN}", "{CLOCK}")), limits)?;
+        gate.set_input(0, false);
+        gate.calculate()?;
+        assert_eq!(gate.get_output_values(), vec![Logic::One]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_limits_stop_runaway_scripts() -> Result<(), Box<dyn Error>> {
+        let code = r#"
+        NUM_OF_INS = 1
+        NUM_OF_OUTS = 1
+        MEMORY_SIZE = 0
+
+        function Calculate(inputs)
+            if inputs[1] then
+                while true do end
+            end
+            return {true}
+        end
+        "#;
+        let limits = LuaLimits { max_instructions: 100_000, ..LuaLimits::default() };
+        let gate = BasicGate::from_lua_code_with_limits("SPIN".to_string(), LuaCode(code.to_string()), limits)?;
+        let gate: Box<dyn LogicGate> = Box::new(gate);
+
+        let mut circuit = Circuit::new("Top".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let id = Uuid::new_v4();
+        let gate = circuit.add_gate(gate, id);
+        circuit.conn_input_to_gate(0, gate, 0)?;
+
+        // Every call gets a fresh budget
+        circuit.set_input(0, false);
+        for _ in 0..3 {
+            circuit.tick()?;
+        }
+
+        // The error names the gate instead of hanging
+        circuit.set_input(0, true);
+        let err = circuit.calculate().unwrap_err();
+        let err = err.downcast_ref::<GateError>().unwrap();
+        assert_eq!(err.gate.id, id);
+        assert!(err.to_string().contains("more than 100000 instructions"));
+
+        let limits = LuaLimits { timeout: std::time::Duration::from_millis(20), ..LuaLimits::default() };
+        let mut gate = BasicGate::from_lua_code_with_limits("SPIN".to_string(), LuaCode(code.to_string()), limits)?;
+        gate.set_input(0, true);
+        let err = gate.calculate().unwrap_err();
+        assert!(err.to_string().contains(&LuaLimitError::Timeout { limit: std::time::Duration::from_millis(20) }.to_string()));
+
+        let limits = LuaLimits { max_memory: 1024 * 1024, ..LuaLimits::default() };
+        let mut gate = BasicGate::from_lua_code_with_limits("HOG".to_string(), LuaCode(code.replace("while true do end", "local s = string.rep(\"x\", 10000000)")), limits)?;
+        gate.set_input(0, true);
+        assert!(gate.calculate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_limits_cant_be_caught() -> Result<(), Box<dyn Error>> {
+        let code = r#"
+        NUM_OF_INS = 1
+        NUM_OF_OUTS = 1
+        MEMORY_SIZE = 0
+
+        function Calculate(inputs)
+            while true do LOOP end
+            return {true}
+        end
+        "#;
+        let loops = [
+            "pcall(function() while true do end end)",
+            "xpcall(function() while true do end end, function(err) return err end)",
+            "pcall(pcall, function() while true do end end)",
+        ];
+
+        for inner in loops {
+            let limits = LuaLimits { max_instructions: 100_000, ..LuaLimits::default() };
+            let mut gate = BasicGate::from_lua_code_with_limits("CATCH".to_string(), LuaCode(code.replace("LOOP", inner)), limits)?;
+            gate.set_input(0, true);
+            let err = gate.calculate().unwrap_err();
+            assert!(err.to_string().contains("more than 100000 instructions"), "{}: {}", inner, err);
+        }
+
+        // Errors of the script itself are still caught
+        let caught = code.replace("while true do LOOP end\n            return {true}", "return {(pcall(error, \"no\"))}");
+        let mut gate = BasicGate::from_lua_code("CATCH".to_string(), LuaCode(caught))?;
+        gate.set_input(0, true);
+        gate.calculate()?;
+        assert_eq!(gate.get_outputs(), vec![false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_script_loads_once() -> Result<(), Box<dyn Error>> {
+        let code = r#"
+        NUM_OF_INS = 1
+        NUM_OF_OUTS = 2
+        MEMORY_SIZE = 0
+        LOADS = (LOADS or 0) + 1
+        CALLS = 0
+
+        function Calculate(inputs)
+            CALLS = CALLS + 1
+            return {LOADS == 1, CALLS % 2 == 0}
+        end
+        "#;
+
+        let mut gate = BasicGate::from_lua_code("COUNT".to_string(), LuaCode(code.to_string()))?;
+        gate.set_input(0, false);
+
+        // Globals set by Calculate are still there on the next call
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            gate.calculate()?;
+            seen.push(gate.get_outputs());
+        }
+        assert_eq!(seen, vec![vec![true, false], vec![true, true], vec![true, false]]);
+
+        let script = gate.get_lua_script().unwrap();
+        assert!(script.get_calculate()?.is_some());
+        assert!(script.get_on_clock()?.is_none());
+        assert_eq!(script.get_lua().globals().get::<_, u32>("CALLS")?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_netlist_matches_simulation() -> Result<(), Box<dyn Error>> {
+        let verilog = "
+module half (input a, input b, output s, output c);
+    xor (s, a, b);
+    and (c, a, b);
+endmodule
+
+module add2(input a0, input a1, input b0, input b1, output s0, output s1, output cout);
+    wire c0, t, c1, c2;
+    half h0 (.a(a0), .b(b0), .s(s0), .c(c0));
+    half h1 (.a(a1), .b(b1), .s(t), .c(c1));
+    half h2 (.a(t), .b(c0), .s(s1), .c(c2));
+    assign cout = c1 | c2;
+endmodule
+";
+        let mut circuit = import_verilog(verilog, std::path::Path::new("./comps"))?;
+        let netlist = BatchNetlist::new(&mut circuit)?;
+        assert_eq!(netlist.get_input_num(), 4);
+        assert_eq!(netlist.get_output_num(), 3);
+
+        // More vectors than fit into one batch
+        let vectors: Vec<Vec<bool>> = (0..100_usize).map(|i| (0..4).map(|bit| (i * 7) >> bit & 1 == 1).collect()).collect();
+        let results = netlist.eval_vectors(&mut circuit, &vectors)?;
+        for (vector, result) in vectors.iter().zip(results) {
+            for (i, bit) in vector.iter().enumerate() {
+                circuit.set_input(i, *bit);
+            }
+            circuit.calculate()?;
+            assert_eq!(result, circuit.get_outputs());
+        }
+
+        let table = netlist.truth_table(&mut circuit)?;
+        assert_eq!(table.map.len(), 16);
+        assert_eq!(table.map, circuit.compile()?.map);
+        assert_eq!(table.get(vec![true, true, true, false]), vec![false, false, true]);
+
+        let mut latch = import_blif(".model m\n.inputs a\n.outputs q\n.latch a q 0\n.end\n", std::path::Path::new("./comps"))?;
+        assert!(matches!(BatchNetlist::new(&mut latch), Err(BatchError::Stateful { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_netlist_per_vector_gate() -> Result<(), Box<dyn Error>> {
+        // Too many inputs for a truth table, the gate runs once per vector
+        let code = "NUM_OF_INS = 13\nNUM_OF_OUTS = 1\nMEMORY_SIZE = 0\n\
+            function Calculate(inputs)\n\
+                local n = 0\n\
+                for i, input in ipairs(inputs) do if input then n = n + 1 end end\n\
+                return {n % 2 == 1}\n\
+            end";
+        let parity = BasicGate::from_lua_code("PARITY".to_string(), LuaCode(code.to_string()))?;
+
+        let mut circuit = Circuit::new("Parity".to_string());
+        for _ in 0..13 {
+            circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        }
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let gate = circuit.add_gate(Box::new(parity), Uuid::new_v4());
+        for i in 0..13 {
+            circuit.conn_input_to_gate(i, gate, i)?;
+        }
+        circuit.conn_gate_to_output(0, gate, 0)?;
+
+        let netlist = BatchNetlist::new(&mut circuit)?;
+        assert!(matches!(netlist.get_steps(), [Step::PerVector { .. }]));
+
+        // Two full batches and a partial one
+        let vectors: Vec<Vec<bool>> = (0..150_usize).map(|i| (0..13).map(|bit| (i * 37) >> bit & 1 == 1).collect()).collect();
+        let results = netlist.eval_vectors(&mut circuit, &vectors)?;
+        assert_eq!(results.len(), 150);
+        for (vector, result) in vectors.iter().zip(results) {
+            let ones = vector.iter().filter(|&&bit| bit).count();
+            assert_eq!(result, vec![ones % 2 == 1]);
+        }
+
+        // A short vector is an error, not a panic
+        let mut vectors = vec![vec![false; 13]; 3];
+        vectors[2].pop();
+        let err = netlist.eval_vectors(&mut circuit, &vectors).unwrap_err();
+        match err.downcast_ref::<BatchError>() {
+            Some(BatchError::VectorWidth { vector, bits, expected }) => assert_eq!((*vector, *bits, *expected), (2, 12, 13)),
+            _ => panic!("Expected a vector width error, got: {}", err),
+        }
+        let err = netlist.eval(&mut circuit, &[0; 12], 64).unwrap_err();
+        assert!(matches!(err.downcast_ref::<BatchError>(), Some(BatchError::VectorWidth { bits: 12, expected: 13, .. })), "{}", err);
+
+        // The per vector gate has to be in the circuit that is passed in
+        let err = netlist.eval(&mut Circuit::new("Other".to_string()), &[0; 13], 64).unwrap_err();
+        match err.downcast_ref::<BatchError>() {
+            Some(BatchError::CircuitChanged { gate: info }) => assert_eq!(info.id, gate),
+            _ => panic!("Expected a changed circuit error, got: {}", err),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_netlist_rejects_loops_and_multiple_drivers() -> Result<(), Box<dyn Error>> {
+        let mut tt = TruthTable::new();
+        tt.add(vec![false], vec![true]);
+        tt.add(vec![true], vec![false]);
+
+        // Only gates that lead to an output are looked at
+        let mut ring = Circuit::new("Ring".to_string());
+        ring.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let not = ring.add_gate(Box::new(BasicGate::from_truth_table("NOT".to_string(), tt.clone())), Uuid::new_v4());
+        ring.connect(not, 0, not, 0)?;
+        ring.conn_gate_to_output(0, not, 0)?;
+        match BatchNetlist::new(&mut ring) {
+            Err(BatchError::CombinationalLoop { gates }) => assert!(gates.contains(&GateInfo { id: not, name: "NOT".to_string() })),
+            _ => panic!("Expected a combinational loop"),
+        }
+
+        let mut shorted = Circuit::new("Shorted".to_string());
+        shorted.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        shorted.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        shorted.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let not = shorted.add_gate(Box::new(BasicGate::from_truth_table("NOT".to_string(), tt)), Uuid::new_v4());
+        shorted.conn_input_to_gate(0, not, 0)?;
+        shorted.conn_input_to_gate(1, not, 0)?;
+        shorted.conn_gate_to_output(0, not, 0)?;
+        match BatchNetlist::new(&mut shorted) {
+            Err(BatchError::MultipleDrivers { gate, bit }) => assert_eq!((gate.id, bit), (not, 0)),
+            _ => panic!("Expected multiple drivers"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_equivalence_exhaustive() -> Result<(), Box<dyn Error>> {
+        let verilog = "
+module xor_from_nands(input a, input b, output y);
+    wire n1, n2, n3;
+    nand (n1, a, b);
+    nand (n2, a, n1);
+    nand (n3, b, n1);
+    nand (y, n2, n3);
+endmodule
+";
+        let mut nands = import_verilog(verilog, std::path::Path::new("./comps"))?;
+        let mut xor = BasicGate::from_lua("XOR".to_string(), std::path::Path::new("./comps/xor.lua").into())?;
+        assert_eq!(check_equivalence(&mut nands, &mut xor)?, None);
+
+        let mut or = BasicGate::from_lua("OR".to_string(), std::path::Path::new("./comps/or.lua").into())?;
+        let counterexample = check_equivalence(&mut nands, &mut or)?.unwrap();
+        assert_eq!(counterexample.inputs, vec![true, true]);
+        assert_eq!(counterexample.left, vec![Logic::Zero]);
+        assert_eq!(counterexample.right, vec![Logic::One]);
+
+        let table = xor.compile()?;
+        assert_eq!(check_against_table(&mut nands, &table)?, None);
+
+        let mut not = BasicGate::from_lua("NOT".to_string(), std::path::Path::new("./comps/not.lua").into())?;
+        let err = check_equivalence(&mut nands, &mut not).err().unwrap();
+        assert!(matches!(err.downcast_ref::<EquivError>(), Some(EquivError::PinMismatch { .. })));
+
+        Ok(())
+    }
+
+    // Ripple carry adder as verilog, with an optional broken carry
+    fn adder_verilog(bits: usize, majority_carry: bool, broken_bit: Option<usize>) -> String {
+        let mut ports = Vec::new();
+        let mut body = String::new();
+        for i in 0..bits {
+            ports.push(format!("input a{}, input b{}", i, i));
+            ports.push(format!("output s{}", i));
+            body += &format!("    wire c{};\n", i + 1);
+            if i == 0 {
+                body += "    assign s0 = a0 ^ b0;\n    assign c1 = a0 & b0;\n";
+                continue;
+            }
+            body += &format!("    assign s{i} = a{i} ^ b{i} ^ c{i};\n", i = i);
+            let carry = if broken_bit == Some(i) {
+                format!("a{i} & b{i}", i = i)
+            }
+            else if majority_carry {
+                format!("(a{i} & b{i}) | (a{i} & c{i}) | (b{i} & c{i})", i = i)
+            }
+            else {
+                format!("(a{i} & b{i}) | (c{i} & (a{i} ^ b{i}))", i = i)
+            };
+            body += &format!("    assign c{} = {};\n", i + 1, carry);
+        }
+        body += &format!("    assign cout = c{};\n", bits);
+        format!("module adder({}, output cout);\n{}endmodule\n", ports.join(", "), body)
+    }
+
+    #[test]
+    fn test_equivalence_sat() -> Result<(), Box<dyn Error>> {
+        let comps = std::path::Path::new("./comps");
+        let mut reference = import_verilog(&adder_verilog(10, false, None), comps)?;
+        let mut majority = import_verilog(&adder_verilog(10, true, None), comps)?;
+        assert_eq!(reference.get_input_num(), 20);
+        assert_eq!(check_equivalence(&mut reference, &mut majority)?, None);
+
+        // The carry only goes wrong when bit 7 has to propagate one
+        let mut broken = import_verilog(&adder_verilog(10, true, Some(7)), comps)?;
+        let counterexample = check_equivalence(&mut reference, &mut broken)?.unwrap();
+        assert_ne!(counterexample.left, counterexample.right);
+
+        for (i, bit) in counterexample.inputs.iter().enumerate() {
+            broken.set_input(i, *bit);
+        }
+        broken.calculate()?;
+        assert_eq!(broken.get_output_values(), counterexample.right);
+
+        // Random vectors won't find the one input where these differ, the solver has to
+        let inputs: Vec<String> = (0..20).map(|i| format!("x{}", i)).collect();
+        let ports: Vec<String> = inputs.iter().map(|x| format!("input {}", x)).collect();
+        let chain: String = (1..20).map(|i| format!("    wire w{i};\n    assign w{i} = w{} & x{i};\n", i - 1, i = i)).collect();
+        let mut all = import_verilog(&format!("module all({}, output y);\n    wire w0;\n    assign w0 = x0;\n{}    assign y = w19;\nendmodule\n", ports.join(", "), chain), comps)?;
+        let mut none = import_verilog(&format!("module none({}, output y);\n    assign y = x0 & ~x0;\nendmodule\n", ports.join(", ")), comps)?;
+        let counterexample = check_equivalence(&mut all, &mut none)?.unwrap();
+        assert_eq!(counterexample.inputs, vec![true; 20]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headless_equivalence_reports_counterexample() -> Result<(), Box<dyn Error>> {
+        use std::process::Command;
+
+        let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .args(["./comps/and.lua", "--equiv", "./comps/and.lua"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout)?, "Equivalent\n");
+
+        // AND and OR only disagree when exactly one input is 1
+        let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .args(["./comps/and.lua", "--equiv", "./comps/or.lua"])
+            .output()?;
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(["Not equivalent: 01 -> 0 vs 1\n", "Not equivalent: 10 -> 0 vs 1\n"].contains(&stderr.as_str()), "{}", stderr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_bench() -> Result<(), Box<dyn Error>> {
+        let comps = std::path::Path::new("./comps");
+        let mut adder = import_verilog(&adder_verilog(2, false, None), comps)?;
+        let bench = "
+alias('a0', 'in_0')
+alias('b0', 'in_1')
+alias('carry', 'out_2')
+set('a0', 1)
+set('b0', true)
+set('in_2', '0')
+set('in_3', 0)
+eval()
+assert_eq('out_0', 0)
+assert_eq('out_1', 1, 'carry into bit 1')
+assert_eq('carry', '0')
+";
+        let report = run_test_bench(&mut adder, bench, "adder")?;
+        assert!(report.passed(), "{:?}", report.failure);
+        assert_eq!(report.checks, 3);
+        assert_eq!(report.steps, 1);
+
+        // The third check fails, the report points at it and has every pin
+        let broken = bench.replace("assert_eq('carry', '0')", "set('in_2', 1)\nset('in_3', 1)\neval()\nassert_eq('carry', 0, 'no carry')");
+        let report = run_test_bench(&mut adder, &broken, "adder")?;
+        let failure = report.failure.unwrap();
+        assert_eq!(failure.step, 2);
+        assert_eq!(failure.line, Some(15));
+        assert_eq!(failure.message, "carry is 1, expected 0 (no carry)");
+        assert!(failure.values.contains(&("carry (out_2)".to_string(), "1".to_string())));
+        assert_eq!(failure.values.len(), 7);
+
+        let report = run_test_bench(&mut adder, "set('out_0', 1)", "adder")?;
+        assert_eq!(report.failure.unwrap().message, "out_0 is an output");
+        let report = run_test_bench(&mut adder, "set('in_0', 2)", "adder")?;
+        assert_eq!(report.failure.unwrap().message, "2 doesn't fit into 1 bit(s)");
+
+        let mut dff = BasicGate::from_lua("DFF".to_string(), std::path::Path::new("./comps/dff.lua").into())?;
+        let bench = "
+set('in_0', 1)
+set('in_1', 0)
+eval()
+assert_eq('out_0', 'X', 'no edge yet')
+clock('in_1')
+assert_eq('out_0', 1)
+set('in_0', 0)
+clock('in_1', 2)
+assert_eq('out_0', 0)
+";
+        let report = run_test_bench(&mut dff, bench, "dff")?;
+        assert!(report.passed(), "{:?}", report.failure);
+        assert_eq!(report.steps, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headless_test_bench_failure() -> Result<(), Box<dyn Error>> {
+        use std::process::Command;
+
+        let dir = std::env::temp_dir().join(format!("benches_{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        std::fs::write(dir.join("pass.lua"), "set('in_0', 1)\nset('in_1', 1)\neval()\nassert_eq('out_0', 1)\n")?;
+        std::fs::write(dir.join("fail.lua"), "set('in_0', 1)\nset('in_1', 0)\neval()\nassert_eq('out_0', 1, 'both set')\n")?;
+
+        let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+            .args(["./comps/and.lua", "--jobs", "1", "--test"])
+            .arg(dir.join("pass.lua"))
+            .arg("--test")
+            .arg(dir.join("fail.lua"))
+            .output()?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            "PASS pass.lua (1 checks, 1 steps)\nFAIL fail.lua at step 1, line 4: out_0 is 0, expected 1 (both set)\n    in_0 = 1\n    in_1 = 0\n    out_0 = 0\n"
+        );
+        assert_eq!(String::from_utf8(output.stderr)?, "1 of 2 test benches failed\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gate_arena() -> Result<(), Box<dyn Error>> {
+        let mut arena = GateArena::new();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let slot_a = arena.insert(a, Box::new(CircuitBus::new()));
+        arena.insert(b, Box::new(CircuitBus::with_width(4)));
+
+        // A removed gate frees its slot for the next one
+        assert!(arena.remove(&a).is_some());
+        assert!(arena.get(&a).is_none());
+        assert_eq!(arena.insert(c, Box::new(CircuitBus::new())), slot_a);
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena.get(&b).unwrap().get_input_widths(), vec![4]);
+
+        // Removing a gate drops its connections
+        let mut circuit = nand_circuit()?;
+        let not_gate = circuit.get_gate_ids()[1];
+        assert_eq!(circuit.get_connections().len(), 4);
+        circuit.remove_gate(&not_gate);
+        assert!(circuit.get_gate(&not_gate).is_none());
+        assert_eq!(circuit.get_connections().len(), 2);
+
+        // Circuits hold no shared pointers, so they can be simulated on another thread
+        let mut circuit = nand_circuit()?;
+        let outputs = std::thread::spawn(move || -> Result<Vec<bool>, String> {
+            circuit.set_input(0, true);
+            circuit.set_input(1, true);
+            circuit.calculate().map_err(|e| e.to_string())?;
+            Ok(circuit.get_outputs())
+        }).join().unwrap()?;
+        assert_eq!(outputs, vec![false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_simulation() -> Result<(), Box<dyn Error>> {
+        let circuit = nand_circuit()?;
+        let vectors: Vec<Vec<bool>> = (0..40).map(|i| vec![i % 2 == 0, i % 3 == 0]).collect();
+
+        // Results come back in the order of the vectors, whatever thread ran them
+        let control = JobControl::new();
+        let outputs = parallel::simulate_vectors(&circuit, &vectors, 4, &control)?;
+        for (vector, output) in vectors.iter().zip(&outputs) {
+            assert_eq!(output, &vec![Logic::from(!(vector[0] && vector[1]))]);
+        }
+        assert_eq!(control.get_done(), 40);
+        assert_eq!(control.get_progress(), 1.0);
+
+        let table = parallel::truth_table(&circuit, 3, &JobControl::new())?;
+        assert_eq!(table.map, nand_circuit()?.compile()?.map);
+
+        let cancelled = JobControl::new();
+        cancelled.cancel();
+        assert!(matches!(parallel::simulate_vectors(&circuit, &vectors, 2, &cancelled), Err(ParallelError::Cancelled)));
+
+        // A wrong vector is reported by its index
+        let mut wrong = vectors.clone();
+        wrong[7].push(true);
+        let err = parallel::simulate_vectors(&circuit, &wrong, 4, &JobControl::new()).unwrap_err();
+        assert!(matches!(err, ParallelError::Failed { index: 7, .. }), "{}", err);
+
+        // Each worker has its own copy, so the outcome of a vector can't depend on state
+        let mut stateful = Circuit::new("Stateful".to_string());
+        stateful.add_gate(Box::new(BasicGate::from_lua("DFF".to_string(), std::path::Path::new("./comps/dff.lua").into())?), Uuid::new_v4());
+        assert!(matches!(parallel::simulate_vectors(&stateful, &[], 2, &JobControl::new()), Err(ParallelError::Stateful)));
+
+        // A NOR latch keeps state in its loop, even though its gates don't
+        let mut nor = TruthTable::new();
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            nor.add(vec![a, b], vec![!(a || b)]);
+        }
+        let mut latch = Circuit::new("Latch".to_string());
+        for _ in 0..8 {
+            latch.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        }
+        latch.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let q = latch.add_gate(Box::new(BasicGate::from_truth_table("NOR".to_string(), nor.clone())), Uuid::new_v4());
+        let q_bar = latch.add_gate(Box::new(BasicGate::from_truth_table("NOR".to_string(), nor)), Uuid::new_v4());
+        latch.conn_input_to_gate(0, q, 0)?;
+        latch.conn_input_to_gate(1, q_bar, 0)?;
+        latch.connect(q_bar, 0, q, 1)?;
+        latch.connect(q, 0, q_bar, 1)?;
+        latch.conn_gate_to_output(0, q, 0)?;
+        assert!(latch.compilable());
+        assert!(matches!(parallel::simulate_vectors(&latch, &[vec![false; 8]], 2, &JobControl::new()), Err(ParallelError::Stateful)));
+        assert!(matches!(parallel::truth_table(&latch, 2, &JobControl::new()), Err(ParallelError::Stateful)));
+
+        let benches: Vec<(String, String)> = (0..6)
+            .map(|i| (format!("bench{}", i), format!("set('in_0', 1)\nset('in_1', 1)\neval()\nassert_eq('out_0', {})", i % 2)))
+            .collect();
+        let load = || -> Result<Box<dyn LogicGate>, String> { Ok(Box::new(nand_circuit().map_err(|e| e.to_string())?)) };
+        let reports = parallel::run_test_benches(load, &benches, 3, &JobControl::new())?;
+        let passed: Vec<bool> = reports.iter().map(|r| r.passed()).collect();
+        assert_eq!(passed, vec![true, false, true, false, true, false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_control() -> Result<(), Box<dyn Error>> {
+        let items: Vec<usize> = (0..50).collect();
+
+        // With one worker the progress seen by each item is the number of items before it
+        let control = JobControl::new();
+        assert_eq!(control.get_progress(), 0.0);
+        let seen = parallel::run_jobs(&items, 1, &control, || Ok(()), |_, _| Ok((control.get_done(), control.get_total())))?;
+        assert_eq!(seen, items.iter().map(|&i| (i, 50)).collect::<Vec<_>>());
+        assert_eq!(control.get_progress(), 1.0);
+
+        // Cancelling from inside a job stops the workers before the next item
+        let control = JobControl::new();
+        let result = parallel::run_jobs(&items, 4, &control, || Ok(()), |_, &item| {
+            if item == 10 {
+                control.cancel();
+            }
+            Ok(item)
+        });
+        assert!(matches!(result, Err(ParallelError::Cancelled)));
+        assert!(control.get_done() < 50);
+        assert!(control.get_progress() < 1.0);
+
+        // A clone controls the same job, e.g. from the ui thread
+        let control = JobControl::new();
+        let remote = control.clone();
+        let result = std::thread::scope(|scope| {
+            let job = scope.spawn(|| parallel::run_jobs(&items, 2, &control, || Ok(()), |_, &item| {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                Ok(item)
+            }));
+            while remote.get_done() == 0 {
+                std::thread::yield_now();
+            }
+            remote.cancel();
+            job.join().unwrap()
+        });
+        assert!(matches!(result, Err(ParallelError::Cancelled)));
+        assert!(remote.is_cancelled());
+
+        // Workers that can't set themselves up report why
+        let result = parallel::run_jobs(&items, 2, &JobControl::new(), || Err::<(), _>(ParallelError::CantCopy("no copy".to_string())), |_, &item| Ok(item));
+        assert!(matches!(result, Err(ParallelError::CantCopy(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_canvas_as_component() -> Result<(), Box<dyn Error>> {
+        let lua = |name: &str| -> Result<Box<dyn LogicGate>, Box<dyn Error>> {
+            Ok(Box::new(BasicGate::from_lua(name.to_ascii_uppercase(), std::path::Path::new(&format!("./comps/{}.lua", name)).into())?))
+        };
+
+        // Drawn like on a canvas, the second button sits above the first one
+        let mut canvas = Circuit::new("Canvas 1".to_string());
+        canvas.add_input(lua("button")?, Uuid::new_v4());
+        canvas.add_input(lua("button")?, Uuid::new_v4());
+        canvas.add_output(lua("lamp")?, Uuid::new_v4());
+        let and_gate = canvas.add_gate(lua("and")?, Uuid::new_v4());
+        canvas.conn_input_to_gate(0, and_gate, 0)?;
+        canvas.conn_input_to_gate(1, and_gate, 1)?;
+        canvas.conn_gate_to_output(0, and_gate, 0)?;
+
+        let mut doc = canvas.to_document()?;
+        for (entry, y) in doc.gates.iter_mut().zip([100.0, 40.0, 60.0, 60.0]) {
+            entry.layout = Some(GateLayout { pos: (0.0, y), size: (40.0, 40.0) });
+        }
+
+        let component = make_component(&doc, "MYAND");
+        assert_eq!(component.name, "MYAND");
+        assert!(component.gates.iter().filter(|g| g.role != GateRole::Gate).all(|g| g.name == "BUS"));
+        assert_eq!(component.symbol, Some(Symbol { width: 4, height: 3, inputs_pos: vec![12, 13], outputs_pos: vec![5] }));
+
+        let path = std::env::temp_dir().join(format!("component_{}", Uuid::new_v4())).join("myand.json");
+        save_component(&component, &path)?;
+        let (myand, symbol, info) = load_component(&path)?;
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+        assert_eq!(Some(symbol), component.symbol);
+        assert_eq!(info.get_category(), "Circuits");
+        assert_eq!(myand.get_input_widths(), vec![1, 1]);
+
+        // Placed instances simulate as part of the outer circuit and are saved with it
+        let mut outer = Circuit::new("Outer".to_string());
+        outer.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        outer.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        outer.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let myand = outer.add_gate(Box::new(myand), Uuid::new_v4());
+        outer.conn_input_to_gate(0, myand, 0)?;
+        outer.conn_input_to_gate(1, myand, 1)?;
+        outer.conn_gate_to_output(0, myand, 0)?;
+
+        let mut outer = Circuit::from_document(&outer.to_document()?)?;
+        for (a, b) in [(false, true), (true, true)] {
+            outer.set_input(0, a);
+            outer.set_input(1, b);
+            outer.calculate()?;
+            assert_eq!(outer.get_outputs(), vec![a && b]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_with_changed_pins() -> Result<(), Box<dyn Error>> {
+        let mut canvas = Circuit::new("Canvas 1".to_string());
+        canvas.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        canvas.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        canvas.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let and_gate = canvas.add_gate(lua_gate("AND", "./comps/and.lua")?, Uuid::new_v4());
+        canvas.conn_input_to_gate(0, and_gate, 0)?;
+        canvas.conn_input_to_gate(1, and_gate, 1)?;
+        canvas.conn_gate_to_output(0, and_gate, 0)?;
+        let component = make_component(&canvas.to_document()?, "MYAND");
+
+        // The file gained an input after the symbol was made
+        let mut canvas = Circuit::from_document(&component)?;
+        canvas.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let mut changed = canvas.to_document()?;
+        changed.symbol = component.symbol.clone();
+
+        let path = std::env::temp_dir().join(format!("component_{}", Uuid::new_v4())).join("myand.json");
+        save_component(&changed, &path)?;
+        let result = load_component(&path);
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+
+        let err = result.err().expect("pin count doesn't match the symbol");
+        assert_eq!(err.to_string(), "The symbol of MYAND has 2 inputs and 1 outputs, the circuit has 3 and 1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_circuit_instances() -> Result<(), Box<dyn Error>> {
+        let mut doc = nand_circuit()?.to_document()?;
+        for (i, entry) in doc.gates.iter_mut().enumerate() {
+            entry.layout = Some(GateLayout { pos: (20.0 * i as f32, 0.0), size: (40.0, 40.0) });
+        }
+
+        // The layout survives being built into a circuit, so an instance can be drawn again
+        let nand = Circuit::from_document(&doc)?;
+        let layouts: Vec<_> = nand.to_document()?.gates.iter().map(|g| g.layout).collect();
+        assert_eq!(layouts, doc.gates.iter().map(|g| g.layout).collect::<Vec<_>>());
+
+        let mut outer = Circuit::new("Outer".to_string());
+        outer.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        outer.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        outer.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let first = outer.add_gate(Box::new(nand), Uuid::new_v4());
+        let second = outer.add_gate(Box::new(Circuit::from_document(&doc)?), Uuid::new_v4());
+        outer.conn_input_to_gate(0, first, 0)?;
+        outer.conn_input_to_gate(1, first, 1)?;
+        outer.conn_input_to_gate(0, second, 0)?;
+        outer.conn_input_to_gate(0, second, 1)?;
+        outer.conn_gate_to_output(0, first, 0)?;
+
+        outer.set_input(0, true);
+        outer.set_input(1, false);
+        outer.calculate()?;
+
+        // Every instance has its own values
+        let and_gate = doc.gates.iter().find(|g| g.name == "AND").unwrap().id;
+        let and_output = |instance: Uuid| outer.get_nested(&[instance]).unwrap().get_gate(&and_gate).unwrap().get_outputs();
+        assert_eq!(and_output(first), vec![false]);
+        assert_eq!(and_output(second), vec![true]);
+
+        assert_eq!(outer.get_nested(&[]).unwrap().get_name(), "Outer");
+        assert!(outer.get_nested(&[first, and_gate]).is_none());
+        assert!(outer.get_nested_mut(&[Uuid::new_v4()]).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instance_breadcrumbs() -> Result<(), Box<dyn Error>> {
+        let mut outer = Circuit::new("Outer".to_string());
+        let nand = outer.add_gate(Box::new(nand_circuit()?), Uuid::new_v4());
+        let and_gate = outer.get_nested(&[nand]).unwrap().get_gate_ids()[0];
+
+        let path = InstancePath { root: Uuid::new_v4(), gates: vec![nand] };
+        let crumbs = path.breadcrumbs("Canvas 1", Some(&outer))?;
+        let names: Vec<&str> = crumbs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Canvas 1", "My Nand"]);
+        assert_eq!(crumbs[0].1, InstancePath { root: path.root, gates: vec![] });
+        assert_eq!(crumbs[1].1, path);
+        assert_eq!(path.resolve(Some(&outer))?.get_name(), "My Nand");
+
+        // The tab of the outer circuit was closed
+        assert_eq!(path.breadcrumbs("Canvas 1", None), Err(InstanceError::RootClosed));
+        assert_eq!(path.resolve(None).err(), Some(InstanceError::RootClosed));
+        assert_eq!(InstanceError::RootClosed.to_string(), "The tab of the outer circuit was closed");
+
+        // A path through a gate that isn't a circuit or that was removed leads nowhere
+        let through_gate = InstancePath { root: path.root, gates: vec![nand, and_gate] };
+        assert_eq!(through_gate.breadcrumbs("Canvas 1", Some(&outer)), Err(InstanceError::NotNested));
+        outer.remove_gate(&nand);
+        assert_eq!(path.resolve(Some(&outer)).err(), Some(InstanceError::NotNested));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_info() -> Result<(), Box<dyn Error>> {
+        let and = ComponentInfo::from_lua_file(std::path::Path::new("./comps/and.lua"))?;
+        assert_eq!(and.get_category(), "Logic");
+        assert_eq!(and.icon.as_deref(), Some("&"));
+        assert!(and.author.is_none());
+
+        let lua = new_logic_gates::sandbox::sandboxed_lua()?;
+        lua.load("NUM_OF_INS = 1\nAUTHOR = 'Ada'\nVERSION = 1.5\nDESCRIPTION = 'Adds two bits'").exec()?;
+        let info = ComponentInfo::from_lua(&lua)?;
+        assert_eq!(info.version.as_deref(), Some("1.5"));
+        assert_eq!(info.get_category(), "Other");
+
+        // Every word has to be somewhere, case doesn't matter
+        assert!(info.matches("HALF_ADDER", "ada bits"));
+        assert!(info.matches("HALF_ADDER", "half other"));
+        assert!(info.matches("HALF_ADDER", "  "));
+        assert!(!info.matches("HALF_ADDER", "adder carry"));
+        assert!(and.matches("AND", "logic"));
+
+        // Saved components can describe themselves too
+        let mut doc = nand_circuit()?.to_document()?;
+        doc.info = Some(ComponentInfo { category: Some("Arithmetic".to_string()), ..Default::default() });
+        let path = std::env::temp_dir().join(format!("described_{}.json", Uuid::new_v4()));
+        save_component(&doc, &path)?;
+        let (_, _, info) = load_component(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(info.get_category(), "Arithmetic");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_lua_component() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("reload_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let script = dir.join("hold.lua");
+        let hold = |widths: &str, calculate: &str| format!(
+            "NUM_OF_INS = 1\nNUM_OF_OUTS = 1\nMEMORY_SIZE = 1\nINPUT_WIDTHS = {{{}}}\nfunction Calculate(inputs) {} end\n",
+            widths, calculate
+        );
+        std::fs::write(&script, hold("1", "return {memory[1]}"))?;
+
+        let mut watcher = ComponentWatcher::with_interval(&dir, std::time::Duration::ZERO);
+        assert!(watcher.poll().is_empty());
+
+        let gate = |circuit: &mut Circuit| -> Result<Uuid, Box<dyn Error>> {
+            let input = circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+            let output = circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+            let gate = circuit.add_gate(Box::new(BasicGate::from_lua("HOLD".to_string(), script.clone().into_boxed_path())?), Uuid::new_v4());
+            circuit.connect(input, 0, gate, 0)?;
+            circuit.connect(gate, 0, output, 0)?;
+            Ok(gate)
+        };
+
+        // One gate on top and one inside a nested circuit
+        let mut inner = Circuit::new("INNER".to_string());
+        let nested = gate(&mut inner)?;
+        let mut circuit = Circuit::new("Reload".to_string());
+        let top = gate(&mut circuit)?;
+        let inner = circuit.add_gate(Box::new(inner), Uuid::new_v4());
+        circuit.get_gate_mut(&top).unwrap().set_memory(0, true);
+        circuit.set_input(0, false);
+        circuit.calculate()?;
+        assert_eq!(circuit.get_gate(&top).unwrap().get_outputs(), vec![true]);
+
+        // Same pins, the memory stays
+        std::fs::write(&script, hold("1", "return {not memory[1]}"))?;
+        assert_eq!(watcher.poll(), vec![script.clone()]);
+        let reloaded = reload_lua_gates(&mut circuit, &script);
+        assert_eq!(reloaded.len(), 2);
+        assert!(reloaded.iter().any(|gate| gate.path == vec![inner, nested]));
+        assert!(reloaded.iter().all(|gate| gate.result.is_ok()));
+
+        circuit.calculate()?;
+        let top_gate = circuit.get_gate(&top).unwrap();
+        assert_eq!(top_gate.get_memory(), Some(vec![true]));
+        assert_eq!(top_gate.get_outputs(), vec![false]);
+        assert!(matches!(top_gate.get_source(), Some(GateSource::Lua { path }) if path == script));
+        assert_eq!(circuit.get_connections().len(), 2);
+
+        // The input got wider, its wire is dropped and the memory starts over
+        std::fs::write(&script, hold("2", "return {not memory[1]}"))?;
+        assert_eq!(watcher.poll(), vec![script.clone()]);
+        assert!(reload_lua_gates(&mut circuit, &script).iter().all(|gate| gate.result.is_ok()));
+        assert_eq!(circuit.get_connections().len(), 1);
+        assert_eq!(circuit.get_gate(&top).unwrap().get_memory(), Some(vec![false]));
+        assert_eq!(circuit.get_nested(&[inner]).unwrap().get_connections().len(), 1);
+
+        // A broken script leaves the gates as they were
+        std::fs::write(&script, hold("1", "return {"))?;
+        assert_eq!(watcher.poll(), vec![script.clone()]);
+        let reloaded = reload_lua_gates(&mut circuit, &script);
+        assert_eq!(reloaded.len(), 2);
+        assert!(reloaded.iter().all(|gate| gate.result.is_err()));
+        assert_eq!(circuit.get_gate(&top).unwrap().get_input_widths(), vec![2]);
+        circuit.calculate()?;
+
+        std::fs::remove_dir_all(&dir)?;
+        assert!(watcher.poll().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_script_that_fails_to_load() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("reload_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let script = dir.join("flip.lua");
+        let flip = "NUM_OF_INS = 1\nNUM_OF_OUTS = 1\nMEMORY_SIZE = 0\nfunction Calculate(inputs) return {not inputs[1]} end\n";
+        std::fs::write(&script, flip)?;
+
+        let mut circuit = Circuit::new("Reload".to_string());
+        circuit.add_input(Box::new(CircuitBus::new()), Uuid::new_v4());
+        circuit.add_output(Box::new(CircuitBus::new()), Uuid::new_v4());
+        let gate = circuit.add_gate(Box::new(BasicGate::from_lua("FLIP".to_string(), script.clone().into_boxed_path())?), Uuid::new_v4());
+        circuit.conn_input_to_gate(0, gate, 0)?;
+        circuit.conn_gate_to_output(0, gate, 0)?;
+
+        // Runs into an error and into the sandbox limit while loading, not while calculating
+        let broken = [
+            (format!("{}error('half saved')\n", flip), "half saved"),
+            (format!("{}while true do end\n", flip), "instructions"),
+        ];
+        for (code, reason) in broken.iter() {
+            std::fs::write(&script, code)?;
+            let reloaded = reload_lua_gates(&mut circuit, &script);
+            assert_eq!(reloaded.len(), 1);
+            assert_eq!(reloaded[0].path, vec![gate]);
+            let err = reloaded[0].result.as_ref().unwrap_err();
+            assert!(err.contains(reason), "{}", err);
+
+            // The gate keeps running the script it had
+            circuit.set_input(0, true);
+            circuit.calculate()?;
+            assert_eq!(circuit.get_outputs(), vec![false]);
+        }
+
+        // Fixing the script makes it load again
+        std::fs::write(&script, flip)?;
+        assert!(reload_lua_gates(&mut circuit, &script)[0].result.is_ok());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
+}